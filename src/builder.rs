@@ -1,15 +1,36 @@
+use crate::decisions::Decisions;
 use crate::*;
 
 use rand::{RngCore, SeedableRng};
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
 use std::time::{Duration, SystemTime};
+#[cfg(feature = "serde")]
+use std::{io, path::Path};
 
 /// Configure the simulation
 pub struct Builder {
     rng: Option<Box<dyn RngCore>>,
 
+    /// Seeds the simulation's random number generator, if set.
+    seed: Option<u64>,
+
     config: Config,
 
     link: config::Link,
+
+    zones: config::Zones,
+
+    latency_traces: config::LatencyTraces,
+
+    tcp: config::Tcp,
+
+    ports: config::Ports,
+
+    replay: Trace,
+
+    decisions: DecisionLog,
 }
 
 impl Default for Builder {
@@ -22,11 +43,22 @@ impl Builder {
     pub fn new() -> Self {
         Self {
             rng: None,
+            seed: None,
             config: Config::default(),
             link: config::Link {
                 latency: Some(config::Latency::default()),
                 message_loss: Some(config::MessageLoss::default()),
+                ..Default::default()
             },
+            zones: config::Zones::default(),
+            latency_traces: config::LatencyTraces::default(),
+            tcp: config::Tcp {
+                handshake_rtts: 1,
+                ..Default::default()
+            },
+            ports: config::Ports::default(),
+            replay: Trace::new(),
+            decisions: DecisionLog::new(),
         }
     }
 
@@ -42,12 +74,81 @@ impl Builder {
         self
     }
 
+    /// Never time out, whatever the simulation duration.
+    ///
+    /// [`Sim::run`] then returns only once every client completes, a host
+    /// fails, or the simulation deadlocks: every host is blocked, and no
+    /// message is in flight and no [`sleep`] or [`timeout`] is pending to wake
+    /// one. Timers created directly with tokio aren't visible, so hosts should
+    /// wait with [`sleep`] and [`timeout`] instead. Useful for soak tests, or
+    /// for driving a simulation externally with [`Sim::step`] or
+    /// [`SimHandle::step`] and stopping it by hand, as [`SimHandle::run`]
+    /// can't be interrupted.
+    pub fn run_forever(&mut self) -> &mut Self {
+        self.config.duration = Duration::MAX;
+        self
+    }
+
     /// How much simulated time should elapse each tick.
     pub fn tick_duration(&mut self, value: Duration) -> &mut Self {
         self.config.tick = value;
         self
     }
 
+    /// Poll newly registered hosts for the first time in a random order, rather
+    /// than the order they were registered in.
+    ///
+    /// This only affects startup, and is reproducible using the simulation's
+    /// random number generator.
+    pub fn shuffle_startup(&mut self, value: bool) -> &mut Self {
+        self.config.shuffle_startup = value;
+        self
+    }
+
+    /// Record every message delivered to a host, for assertions with
+    /// [`Sim::assert_delivered`](crate::Sim::assert_delivered).
+    ///
+    /// Off by default, as the record grows for as long as the simulation runs.
+    pub fn capture(&mut self, value: bool) -> &mut Self {
+        self.config.capture = value;
+        self
+    }
+
+    /// Record the latency of every message delivered over each link, for
+    /// [`Sim::latency_histogram`](crate::Sim::latency_histogram).
+    ///
+    /// Off by default, to avoid the overhead.
+    pub fn record_latencies(&mut self, value: bool) -> &mut Self {
+        self.config.record_latencies = value;
+        self
+    }
+
+    /// Send at most `value` messages onto the network each tick, across all
+    /// hosts, modeling a send rate limit.
+    ///
+    /// Messages over the limit are deferred to the next tick, in the order
+    /// they were sent, so bursts are spread over several ticks rather than
+    /// sent at once. See [`Sim::messages_this_tick`](crate::Sim::messages_this_tick).
+    /// Unlimited by default.
+    pub fn max_messages_per_tick(&mut self, value: usize) -> &mut Self {
+        assert!(value > 0, "at least one message must be sent each tick");
+
+        self.config.max_messages_per_tick = Some(value);
+        self
+    }
+
+    /// A builder whose simulations are seeded with the `index`-th seed
+    /// derived from `master`.
+    ///
+    /// Useful for running many simulations in parallel, e.g. when fuzzing.
+    /// Each simulation is independent of the others, and is reproduced by the
+    /// same `master` and `index`.
+    pub fn from_master_seed(master: u64, index: u64) -> Self {
+        let mut builder = Self::new();
+        builder.seed = Some(derive_seed(master, index));
+        builder
+    }
+
     /// Set the random number generator used to fuzz
     pub fn rng(&mut self, rng: impl RngCore + 'static) -> &mut Self {
         self.rng = Some(Box::new(rng));
@@ -72,6 +173,60 @@ impl Builder {
         self
     }
 
+    /// Never deliver a message faster than `value`, even over links whose
+    /// latency is configured lower, e.g. with [`Sim::set_link_latency`].
+    ///
+    /// With the default minimum latency of zero, a request and its response
+    /// can arrive within a single tick, which hides ordering bugs. Latencies
+    /// below the floor are clamped up to it. Defaults to zero.
+    pub fn min_latency_floor(&mut self, value: Duration) -> &mut Self {
+        self.link.latency_floor = value;
+        self
+    }
+
+    /// Model links that put `bytes_per_sec` on the wire, delaying each
+    /// message by its size over the rate, on top of its latency.
+    ///
+    /// Small control messages are barely affected, while bulk transfers are
+    /// slow. Messages queue behind each other on the link, as configured with
+    /// [`Builder::bandwidth_sharing`], and higher priority datagrams, see
+    /// [`UdpSocket::set_priority`](crate::net::UdpSocket::set_priority), go
+    /// ahead of waiting ones. Off by default.
+    pub fn bandwidth(&mut self, bytes_per_sec: u64) -> &mut Self {
+        assert!(bytes_per_sec > 0, "bandwidth must be positive");
+        self.link.bandwidth = Some(bytes_per_sec);
+        self
+    }
+
+    /// Set how connections sending over a bandwidth limited link share its
+    /// rate. See [`BandwidthSharing`].
+    ///
+    /// Defaults to [`BandwidthSharing::Fifo`], where a bulk transfer delays
+    /// every message sent after it. [`BandwidthSharing::Fair`] lets a latency
+    /// sensitive connection on the same link through at its share of the rate.
+    pub fn bandwidth_sharing(&mut self, sharing: BandwidthSharing) -> &mut Self {
+        self.link.bandwidth_sharing = sharing;
+        self
+    }
+
+    /// Limit how many bytes of a UDP socket's datagrams may wait to be sent on
+    /// bandwidth limited links and hosts, modeling the socket's send buffer.
+    ///
+    /// Once a socket has `bytes` waiting, [`UdpSocket::try_send_to`] fails with
+    /// [`ErrorKind::WouldBlock`], and [`UdpSocket::send_to`] and
+    /// [`UdpSocket::writable`] wait until some have been sent. Without a
+    /// bandwidth limit, datagrams never wait. Unlimited by default.
+    ///
+    /// [`UdpSocket::try_send_to`]: crate::net::UdpSocket::try_send_to
+    /// [`UdpSocket::send_to`]: crate::net::UdpSocket::send_to
+    /// [`UdpSocket::writable`]: crate::net::UdpSocket::writable
+    /// [`ErrorKind::WouldBlock`]: std::io::ErrorKind::WouldBlock
+    pub fn udp_send_buffer(&mut self, bytes: usize) -> &mut Self {
+        assert!(bytes > 0, "send buffer must be positive");
+        self.link.udp_send_buffer = Some(bytes);
+        self
+    }
+
     pub fn fail_rate(&mut self, value: f64) -> &mut Self {
         self.link.message_loss_mut().fail_rate = value;
         self
@@ -82,12 +237,507 @@ impl Builder {
         self
     }
 
+    /// Set how messages that become deliverable to a host in the same tick,
+    /// but were sent by different hosts, are ordered. See [`SameTickOrder`].
+    ///
+    /// Defaults to [`SameTickOrder::Fifo`], which delivers them in the order
+    /// they were sent. [`SameTickOrder::Random`] surfaces bugs that depend on
+    /// the order, and is reproducible with the same seed.
+    pub fn same_tick_order(&mut self, order: SameTickOrder) -> &mut Self {
+        self.link.same_tick_order = order;
+        self
+    }
+
+    /// Assign the hosts named in `hosts` to the zone `name`.
+    ///
+    /// Links between hosts in zones use the latency configured with
+    /// [`Builder::zone_latency`], rather than the global latency. Per link
+    /// overrides, such as [`Sim::set_link_latency`], still take precedence.
+    pub fn zone<S: ToString>(
+        &mut self,
+        name: &str,
+        hosts: impl IntoIterator<Item = S>,
+    ) -> &mut Self {
+        for host in hosts {
+            self.zones.hosts.insert(host.to_string(), name.to_string());
+        }
+        self
+    }
+
+    /// Set the message latency between hosts in zones `a` and `b`, which may
+    /// be the same zone.
+    pub fn zone_latency(&mut self, a: &str, b: &str, min: Duration, max: Duration) -> &mut Self {
+        self.zones.set_latency(a, b, min, max);
+        self
+    }
+
+    /// Replay `trace` as the message latency between the hosts named `a` and
+    /// `b`, rather than drawing it from a distribution.
+    ///
+    /// The trace takes precedence over all other latency configuration for
+    /// the link, including zones and overrides such as
+    /// [`Sim::set_link_latency`]. The latency floor and bandwidth still apply.
+    pub fn link_latency_trace(&mut self, a: &str, b: &str, trace: LatencyTrace) -> &mut Self {
+        let key = if a < b { (a, b) } else { (b, a) };
+        self.latency_traces
+            .insert((key.0.to_string(), key.1.to_string()), trace);
+        self
+    }
+
+    /// Assign the hosts named in `hosts` to the failure domain `name`, e.g. a
+    /// rack or an availability zone.
+    ///
+    /// A domain shares fate: [`Sim::crash_domain`] crashes all of its hosts at
+    /// once, and [`Sim::partition_domain`] partitions them from all other
+    /// hosts. A host may belong to several domains.
+    pub fn failure_domain<S: ToString>(
+        &mut self,
+        name: &str,
+        hosts: impl IntoIterator<Item = S>,
+    ) -> &mut Self {
+        let domain = self
+            .config
+            .failure_domains
+            .entry(name.to_string())
+            .or_default();
+
+        for host in hosts {
+            let host = host.to_string();
+
+            if !domain.contains(&host) {
+                domain.push(host);
+            }
+        }
+        self
+    }
+
+    /// Call `f` with a host's name each time the host becomes idle, with none
+    /// of its tasks runnable, or busy again.
+    ///
+    /// Hosts start out busy. `f` is called from the scheduler after the host's
+    /// tick, so the calls are deterministic, and it has no effect on
+    /// scheduling.
+    pub fn on_host_state(&mut self, f: impl FnMut(&str, HostState) + 'static) -> &mut Self {
+        self.config.on_host_state = Some(Rc::new(RefCell::new(f)));
+        self
+    }
+
+    /// Drop messages with a probability of `base`, plus `per_queued` for each
+    /// message already in flight on the link, capped at 1.
+    ///
+    /// A saturated link drops more messages, modeling tail-drop under
+    /// congestion. By default, messages are not dropped individually.
+    pub fn congestion_drop(&mut self, base: f64, per_queued: f64) -> &mut Self {
+        self.link.message_loss_mut().congestion_drop =
+            Some(config::CongestionDrop { base, per_queued });
+        self
+    }
+
+    /// Fail TCP writes immediately with `HostUnreachable` when the link to the
+    /// peer is partitioned.
+    ///
+    /// By default, writes succeed and the data is dropped by the network.
+    pub fn fail_writes_on_partition(&mut self, value: bool) -> &mut Self {
+        self.tcp.fail_writes_on_partition = value;
+        self
+    }
+
+    /// Verify that bytes read from each TCP stream exactly match, in order, the
+    /// bytes written by the peer, panicking otherwise.
+    ///
+    /// This is a safety net against fault injection leaking into the TCP byte
+    /// stream. Off by default, as every byte written is recorded.
+    pub fn validate_tcp_integrity(&mut self, value: bool) -> &mut Self {
+        self.tcp.validate_integrity = value;
+        self
+    }
+
+    /// Retransmit a lost SYN up to `retries` times before a TCP connect fails,
+    /// waiting `backoff` before the first retransmission and doubling the wait
+    /// for each one after.
+    ///
+    /// By default the SYN is sent once, and connect fails as soon as it is
+    /// lost.
+    pub fn syn_retries(&mut self, retries: u32, backoff: Duration) -> &mut Self {
+        self.tcp.syn_retries = retries;
+        self.tcp.syn_backoff = backoff;
+        self
+    }
+
+    /// Retransmit a SYN once its backoff passes without a SYN-ACK, even if it
+    /// is only delayed rather than lost, as real clients do.
+    ///
+    /// The server may then receive duplicate SYNs for the same connection
+    /// attempt, which it collapses into a single connection. Has no effect
+    /// without [`Builder::syn_retries`]. Disabled by default.
+    pub fn retransmit_delayed_syns(&mut self, value: bool) -> &mut Self {
+        self.tcp.retransmit_delayed_syns = value;
+        self
+    }
+
+    /// Configure connections opened with
+    /// [`TcpStream::connect_secure`](crate::net::TcpStream::connect_secure):
+    /// their handshake takes `rtts` round trips after the TCP connect, and
+    /// fails at `fail_rate`.
+    ///
+    /// By default, the handshake takes one round trip, as in TLS 1.3, and
+    /// always succeeds.
+    pub fn secure_handshake(&mut self, rtts: u32, fail_rate: f64) -> &mut Self {
+        assert!(
+            (0.0..=1.0).contains(&fail_rate),
+            "fail rate must be between 0 and 1"
+        );
+
+        self.tcp.handshake_rtts = rtts;
+        self.tcp.handshake_fail_rate = fail_rate;
+        self
+    }
+
+    /// Hold received TCP data back from readers until at least `value` bytes
+    /// are available, or the peer closes the stream.
+    ///
+    /// TCP has no message boundaries, but this is useful for testing framing
+    /// assumptions, e.g. that a length-prefixed message written across several
+    /// writes is only readable once fully received. By default, data is
+    /// readable as soon as each segment arrives.
+    pub fn tcp_read_boundary(&mut self, value: usize) -> &mut Self {
+        self.tcp.read_boundary = Some(value);
+        self
+    }
+
+    /// Return at most `value` bytes from each TCP read, even when more are
+    /// buffered, modeling arbitrary segmentation.
+    ///
+    /// This forces framing code to handle partial reads. By default, a read
+    /// returns as many buffered bytes as fit.
+    pub fn max_read_chunk(&mut self, value: usize) -> &mut Self {
+        assert!(value > 0, "max read chunk must be positive");
+        self.tcp.max_read_chunk = Some(value);
+        self
+    }
+
+    /// Close TCP streams that have sent and received nothing for `value`,
+    /// modeling a load balancer or server that reaps idle connections.
+    ///
+    /// Both sides of a reaped stream read EOF, i.e. `Ok(0)`, once they've read
+    /// what had already arrived, and their writes fail with `BrokenPipe`.
+    /// Either side's reads or writes reset the timeout. By default, streams are
+    /// never reaped.
+    pub fn idle_timeout(&mut self, value: Duration) -> &mut Self {
+        self.tcp.idle_timeout = Some(value);
+        self
+    }
+
+    /// Fail the simulation when a host opens more than `n` TCP connections to
+    /// the same peer within `window`, which points at a reconnect storm, e.g.
+    /// a client that reconnects in a tight loop rather than reusing or backing
+    /// off.
+    ///
+    /// The connect that exceeds the threshold panics with the hosts involved,
+    /// failing the host. Connects are counted whether or not they succeed,
+    /// and across host restarts. Off by default.
+    pub fn reconnect_storm_threshold(&mut self, n: usize, window: Duration) -> &mut Self {
+        self.tcp.reconnect_storm = Some((n, window));
+        self
+    }
+
+    /// Set how TCP streams deliver data. See [`TcpModel`].
+    ///
+    /// [`TcpModel::AckGated`] is slower, but exposes head-of-line blocking and
+    /// retransmission timing. Defaults to [`TcpModel::Reliable`].
+    pub fn tcp_model(&mut self, model: TcpModel) -> &mut Self {
+        if let TcpModel::AckGated { window } = model {
+            assert!(window > 0, "window must be positive");
+        }
+
+        self.tcp.model = model;
+        self
+    }
+
+    /// Never assign `ports` as ephemeral ports, e.g. to keep well-known ports
+    /// free for servers. Reserved ports can still be bound explicitly.
+    pub fn reserved_ports(&mut self, ports: &[u16]) -> &mut Self {
+        self.ports.reserved.extend(ports);
+        self
+    }
+
+    /// The range of ephemeral ports assigned to TCP connections and to
+    /// listeners bound to port 0. Defaults to `1024..=65535`.
+    pub fn tcp_ephemeral_ports(&mut self, range: RangeInclusive<u16>) -> &mut Self {
+        self.ports.tcp = range;
+        self
+    }
+
+    /// The range of ephemeral ports assigned to UDP sockets bound to port 0.
+    /// Defaults to `1024..=65535`.
+    pub fn udp_ephemeral_ports(&mut self, range: RangeInclusive<u16>) -> &mut Self {
+        self.ports.udp = range;
+        self
+    }
+
+    /// Replay the packets in `trace` into the simulation at their recorded
+    /// times.
+    ///
+    /// Packets between hosts that are not registered are skipped with a
+    /// warning.
+    pub fn replay(&mut self, trace: Trace) -> &mut Self {
+        self.replay = trace;
+        self
+    }
+
+    /// Replay the network's random decisions from `log`, e.g. one recorded
+    /// with [`Sim::record_decisions`](crate::Sim::record_decisions), instead
+    /// of drawing them from the random number generator.
+    ///
+    /// Decisions are replayed in order, by kind: each decision takes the next
+    /// one of its kind from the log, so a kind that is missing from the log,
+    /// or made in a different order relative to the others, doesn't shift the
+    /// rest. Once the log has no more decisions of a kind, that kind falls
+    /// back to the random number generator.
+    pub fn replay_decisions(&mut self, log: DecisionLog) -> &mut Self {
+        self.decisions = log;
+        self
+    }
+
     pub fn build<'a>(&self) -> Sim<'a> {
-        self.build_with_rng(Box::new(rand::rngs::SmallRng::from_entropy()))
+        self.build_with_rng(self.seeded_rng())
     }
 
     pub fn build_with_rng<'a>(&self, rng: Box<dyn RngCore>) -> Sim<'a> {
-        let world = World::new(self.link.clone(), rng);
-        Sim::new(self.config.clone(), world)
+        Sim::new(self.config.clone(), self.world(rng), self.replay.clone())
+    }
+
+    /// Build a [`Network`], which drives the simulated network without host
+    /// software or a tokio runtime.
+    ///
+    /// Only the network configuration applies: latency, message loss, zones
+    /// and the tick duration.
+    pub fn build_network(&self) -> Network {
+        Network::new(self.world(self.seeded_rng()), self.config.tick)
+    }
+
+    fn seeded_rng(&self) -> Box<dyn RngCore> {
+        match self.seed {
+            Some(seed) => Box::new(rand::rngs::SmallRng::seed_from_u64(seed)),
+            None => Box::new(rand::rngs::SmallRng::from_entropy()),
+        }
+    }
+
+    fn world(&self, mut rng: Box<dyn RngCore>) -> World {
+        // Without a seed, the base for host seeds is drawn once, before any
+        // host is registered
+        let host_seed = self.seed.unwrap_or_else(|| rng.next_u64());
+        let mut world = World::new(
+            self.link.clone(),
+            self.zones.clone(),
+            self.latency_traces.clone(),
+            self.tcp.clone(),
+            self.ports.clone(),
+            rng,
+            host_seed,
+        );
+        world.topology.decisions = Decisions::replaying(self.decisions.clone());
+        world
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Builder {
+    /// Save the builder's configuration to `path`, as JSON.
+    ///
+    /// Everything but host software is saved: the seed, durations, link
+    /// behavior, zones, latency traces, failure domains, and TCP and port settings. A generator set with
+    /// [`Builder::rng`], a [`Builder::replay`] trace and a
+    /// [`Builder::replay_decisions`] log are not saved, so set
+    /// a seed, e.g. with [`Builder::from_master_seed`], to make the file a
+    /// reproduction.
+    pub fn to_config_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let latency = self.link.latency();
+        let message_loss = self.link.message_loss();
+
+        let file = config::File {
+            seed: self.seed,
+            duration: self.config.duration,
+            tick: self.config.tick,
+            epoch: self.config.epoch,
+            shuffle_startup: self.config.shuffle_startup,
+            capture: self.config.capture,
+            record_latencies: self.config.record_latencies,
+            max_messages_per_tick: self.config.max_messages_per_tick,
+            min_message_latency: latency.min_message_latency,
+            max_message_latency: latency.max_message_latency,
+            latency_floor: self.link.latency_floor,
+            bandwidth: self.link.bandwidth,
+            bandwidth_sharing: self.link.bandwidth_sharing,
+            udp_send_buffer: self.link.udp_send_buffer,
+            same_tick_order: self.link.same_tick_order,
+            fail_rate: message_loss.fail_rate,
+            repair_rate: message_loss.repair_rate,
+            congestion_drop: message_loss
+                .congestion_drop
+                .as_ref()
+                .map(|c| (c.base, c.per_queued)),
+            hosts: self
+                .zones
+                .hosts
+                .iter()
+                .map(|(name, zone)| config::FileHost {
+                    name: name.clone(),
+                    zone: zone.clone(),
+                })
+                .collect(),
+            zone_latency: self
+                .zones
+                .latency
+                .iter()
+                .map(|((a, b), (min, max))| config::FileZoneLatency {
+                    a: a.clone(),
+                    b: b.clone(),
+                    min: *min,
+                    max: *max,
+                })
+                .collect(),
+            link_latency_traces: self
+                .latency_traces
+                .iter()
+                .map(|((a, b), trace)| config::FileLatencyTrace {
+                    a: a.clone(),
+                    b: b.clone(),
+                    trace: trace.clone(),
+                })
+                .collect(),
+            failure_domains: self
+                .config
+                .failure_domains
+                .iter()
+                .map(|(name, hosts)| config::FileFailureDomain {
+                    name: name.clone(),
+                    hosts: hosts.clone(),
+                })
+                .collect(),
+            fail_writes_on_partition: self.tcp.fail_writes_on_partition,
+            validate_tcp_integrity: self.tcp.validate_integrity,
+            tcp_read_boundary: self.tcp.read_boundary,
+            max_read_chunk: self.tcp.max_read_chunk,
+            tcp_model: self.tcp.model,
+            syn_retries: self.tcp.syn_retries,
+            syn_backoff: self.tcp.syn_backoff,
+            retransmit_delayed_syns: self.tcp.retransmit_delayed_syns,
+            idle_timeout: self.tcp.idle_timeout,
+            reconnect_storm_threshold: self.tcp.reconnect_storm,
+            handshake_rtts: self.tcp.handshake_rtts,
+            handshake_fail_rate: self.tcp.handshake_fail_rate,
+            reserved_ports: self.ports.reserved.iter().copied().collect(),
+            tcp_ephemeral_ports: self.ports.tcp.clone(),
+            udp_ephemeral_ports: self.ports.udp.clone(),
+        };
+
+        let json = serde_json::to_vec_pretty(&file)?;
+        std::fs::write(path, json)
+    }
+
+    /// Load a builder from a configuration file saved with
+    /// [`Builder::to_config_file`].
+    ///
+    /// Host software is re-attached by registering hosts with the same names,
+    /// e.g. with [`Sim::host`]. Fails with [`io::ErrorKind::InvalidData`] if
+    /// the file is malformed, a host is listed more than once, a zone latency
+    /// names a zone without hosts, or a latency trace has no samples. Hosts
+    /// named by latency traces and failure domains must be registered before
+    /// the simulation first steps, or [`Sim::step`] and [`Sim::run`] fail
+    /// naming the unknown host.
+    pub fn from_config_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = std::fs::read(path)?;
+        let file: config::File = serde_json::from_slice(&json)?;
+
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let mut builder = Self::new();
+        builder.seed = file.seed;
+        builder
+            .simulation_duration(file.duration)
+            .tick_duration(file.tick)
+            .epoch(file.epoch)
+            .shuffle_startup(file.shuffle_startup)
+            .capture(file.capture)
+            .record_latencies(file.record_latencies)
+            .min_message_latency(file.min_message_latency)
+            .max_message_latency(file.max_message_latency)
+            .min_latency_floor(file.latency_floor)
+            .bandwidth_sharing(file.bandwidth_sharing)
+            .same_tick_order(file.same_tick_order)
+            .fail_rate(file.fail_rate)
+            .repair_rate(file.repair_rate)
+            .fail_writes_on_partition(file.fail_writes_on_partition)
+            .validate_tcp_integrity(file.validate_tcp_integrity)
+            .tcp_model(file.tcp_model)
+            .syn_retries(file.syn_retries, file.syn_backoff)
+            .retransmit_delayed_syns(file.retransmit_delayed_syns)
+            .reserved_ports(&file.reserved_ports)
+            .tcp_ephemeral_ports(file.tcp_ephemeral_ports)
+            .udp_ephemeral_ports(file.udp_ephemeral_ports);
+
+        if let Some((base, per_queued)) = file.congestion_drop {
+            builder.congestion_drop(base, per_queued);
+        }
+
+        builder.config.max_messages_per_tick = file.max_messages_per_tick;
+        builder.link.bandwidth = file.bandwidth;
+        builder.link.udp_send_buffer = file.udp_send_buffer;
+        builder.tcp.read_boundary = file.tcp_read_boundary;
+        builder.tcp.max_read_chunk = file.max_read_chunk;
+        builder.tcp.idle_timeout = file.idle_timeout;
+        builder.tcp.reconnect_storm = file.reconnect_storm_threshold;
+        builder.secure_handshake(file.handshake_rtts, file.handshake_fail_rate);
+
+        for host in file.hosts {
+            if builder.zones.hosts.contains_key(&host.name) {
+                return Err(invalid(format!(
+                    "host `{}` is listed more than once",
+                    host.name
+                )));
+            }
+
+            builder.zone(&host.zone, [host.name]);
+        }
+
+        for latency in file.zone_latency {
+            for zone in [&latency.a, &latency.b] {
+                if !builder.zones.hosts.values().any(|z| z == zone) {
+                    return Err(invalid(format!("zone latency names unknown zone `{zone}`")));
+                }
+            }
+
+            builder.zone_latency(&latency.a, &latency.b, latency.min, latency.max);
+        }
+
+        for latency in file.link_latency_traces {
+            builder
+                .config
+                .referenced_hosts
+                .extend([latency.a.clone(), latency.b.clone()]);
+            builder.link_latency_trace(&latency.a, &latency.b, latency.trace);
+        }
+
+        for domain in file.failure_domains {
+            builder
+                .config
+                .referenced_hosts
+                .extend(domain.hosts.iter().cloned());
+            builder.failure_domain(&domain.name, domain.hosts);
+        }
+
+        Ok(builder)
     }
 }
+
+/// The `index`-th output of a SplitMix64 generator seeded with `master`.
+///
+/// SplitMix64 outputs are well distributed even for adjacent inputs, so
+/// consecutive indexes give unrelated seeds.
+pub(crate) fn derive_seed(master: u64, index: u64) -> u64 {
+    let mut z = master.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
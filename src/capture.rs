@@ -0,0 +1,196 @@
+use bytes::Bytes;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::{Bound, RangeBounds};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::envelope::{Datagram, Envelope, Frame, Icmp, Protocol, Segment};
+use crate::Sim;
+
+/// A message delivered to a host, recorded when capture is enabled with
+/// [`Builder::capture`](crate::Builder::capture).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delivery {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub kind: DeliveryKind,
+
+    /// The message payload. Empty for messages that don't carry data.
+    pub payload: Bytes,
+}
+
+/// The kind of a [`Delivery`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryKind {
+    Udp,
+    TcpSyn,
+    TcpData,
+    TcpFin,
+    TcpRst,
+    TcpAck,
+    IcmpPortUnreachable,
+    IcmpHostUnreachable,
+    IcmpFragmentationNeeded,
+    Raw,
+}
+
+impl Delivery {
+    pub(crate) fn new(envelope: &Envelope) -> Delivery {
+        Delivery::of(envelope.src, envelope.dst, &envelope.message)
+    }
+
+    /// Describe `message`, sent from `src` to `dst`.
+    pub(crate) fn of(src: SocketAddr, dst: SocketAddr, message: &Protocol) -> Delivery {
+        let (kind, payload) = match message {
+            Protocol::Udp(Datagram(data)) => (DeliveryKind::Udp, data.clone()),
+            Protocol::Tcp(Segment::Syn(_)) => (DeliveryKind::TcpSyn, Bytes::new()),
+            Protocol::Tcp(Segment::Data(_, data)) => (DeliveryKind::TcpData, data.clone()),
+            Protocol::Tcp(Segment::Fin(_)) => (DeliveryKind::TcpFin, Bytes::new()),
+            Protocol::Tcp(Segment::Rst) => (DeliveryKind::TcpRst, Bytes::new()),
+            Protocol::Tcp(Segment::Ack(_)) => (DeliveryKind::TcpAck, Bytes::new()),
+            Protocol::Icmp(Icmp::PortUnreachable) => {
+                (DeliveryKind::IcmpPortUnreachable, Bytes::new())
+            }
+            Protocol::Icmp(Icmp::HostUnreachable) => {
+                (DeliveryKind::IcmpHostUnreachable, Bytes::new())
+            }
+            Protocol::Icmp(Icmp::FragmentationNeeded) => {
+                (DeliveryKind::IcmpFragmentationNeeded, Bytes::new())
+            }
+            Protocol::Raw(Frame(data)) => (DeliveryKind::Raw, data.clone()),
+        };
+
+        Delivery {
+            src,
+            dst,
+            kind,
+            payload,
+        }
+    }
+}
+
+/// Selects captured deliveries by host, kind and size, for
+/// [`Sim::assert_ordered`](crate::Sim::assert_ordered).
+///
+/// A new matcher matches every delivery; each method narrows it down.
+#[derive(Clone, Debug, Default)]
+pub struct EventMatcher {
+    from: Option<String>,
+    to: Option<String>,
+    kind: Option<DeliveryKind>,
+    size: Option<(Bound<usize>, Bound<usize>)>,
+}
+
+impl EventMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match deliveries sent by `host`.
+    pub fn from(mut self, host: &str) -> Self {
+        self.from = Some(host.to_string());
+        self
+    }
+
+    /// Match deliveries received by `host`.
+    pub fn to(mut self, host: &str) -> Self {
+        self.to = Some(host.to_string());
+        self
+    }
+
+    /// Match deliveries of `kind`.
+    pub fn kind(mut self, kind: DeliveryKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Match deliveries whose payload length is in `range`.
+    pub fn size(mut self, range: impl RangeBounds<usize>) -> Self {
+        self.size = Some((range.start_bound().cloned(), range.end_bound().cloned()));
+        self
+    }
+
+    /// The hosts to match, by name.
+    pub(crate) fn hosts(&self) -> (Option<&str>, Option<&str>) {
+        (self.from.as_deref(), self.to.as_deref())
+    }
+
+    /// Whether `delivery` matches, given the addresses the hosts resolved to.
+    pub(crate) fn matches(
+        &self,
+        delivery: &Delivery,
+        from: Option<IpAddr>,
+        to: Option<IpAddr>,
+    ) -> bool {
+        from.is_none_or(|from| delivery.src.ip() == from)
+            && to.is_none_or(|to| delivery.dst.ip() == to)
+            && self.kind.is_none_or(|kind| delivery.kind == kind)
+            && self
+                .size
+                .is_none_or(|size| size.contains(&delivery.payload.len()))
+    }
+}
+
+/// Run the simulation returned by `setup` twice, and assert that both runs
+/// deliver the same messages in the same order and have the same result.
+///
+/// This catches nondeterminism in host software, such as use of the thread rng
+/// or wall-clock time. `setup` should seed its builder, e.g. with
+/// [`Builder::from_master_seed`](crate::Builder::from_master_seed), so that both
+/// runs see the same randomness. Messages are captured regardless of
+/// [`Builder::capture`](crate::Builder::capture). On a mismatch, panics with the
+/// first divergent delivery.
+pub fn assert_deterministic<'a>(setup: impl Fn() -> Sim<'a>) {
+    let (first_result, first) = setup().run_captured();
+    let (second_result, second) = setup().run_captured();
+
+    if let Some(i) = (0..first.len().max(second.len())).find(|&i| first.get(i) != second.get(i)) {
+        panic!(
+            "runs diverged at delivery {i}: first run {:?}, second run {:?}",
+            first.get(i),
+            second.get(i)
+        );
+    }
+
+    let first_result = first_result.map_err(|e| e.to_string());
+    let second_result = second_result.map_err(|e| e.to_string());
+
+    assert_eq!(
+        first_result, second_result,
+        "runs diverged in their results"
+    );
+}
+
+/// A passive tap on the TCP connections between two hosts, returned by
+/// [`Sim::tap`](crate::Sim::tap).
+///
+/// Yields a copy of each TCP payload as it is delivered, in delivery order,
+/// without altering delivery. Dropping the tap, or calling
+/// [`Sim::untap`](crate::Sim::untap), removes it.
+#[derive(Debug)]
+pub struct Tap {
+    rx: mpsc::UnboundedReceiver<Tapped>,
+}
+
+/// Bytes observed by a [`Tap`].
+#[derive(Clone, Debug)]
+pub struct Tapped {
+    /// The sending side of the connection, which gives the direction.
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+
+    /// Simulation elapsed time when the bytes were delivered.
+    pub elapsed: Duration,
+    pub data: Bytes,
+}
+
+impl Tap {
+    pub(crate) fn new(rx: mpsc::UnboundedReceiver<Tapped>) -> Tap {
+        Tap { rx }
+    }
+
+    /// Returns the next tapped bytes, if any have been delivered.
+    pub fn try_recv(&mut self) -> Option<Tapped> {
+        self.rx.try_recv().ok()
+    }
+}
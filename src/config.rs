@@ -1,6 +1,15 @@
+use crate::top::lerp;
+use crate::HostState;
+
+use indexmap::{IndexMap, IndexSet};
 use rand_distr::Exp;
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
 use std::time::{Duration, SystemTime};
 
+pub(crate) type OnHostState = Rc<RefCell<dyn FnMut(&str, HostState)>>;
+
 #[derive(Clone)]
 pub(crate) struct Config {
     /// How long the test should run for
@@ -11,6 +20,28 @@ pub(crate) struct Config {
 
     /// When the simulation starts
     pub(crate) epoch: SystemTime,
+
+    /// Whether newly registered hosts are first polled in a random order
+    pub(crate) shuffle_startup: bool,
+
+    /// Whether delivered messages are recorded
+    pub(crate) capture: bool,
+
+    /// Whether the latency of each message is recorded, by link
+    pub(crate) record_latencies: bool,
+
+    /// If set, the most messages sent onto the network each tick
+    pub(crate) max_messages_per_tick: Option<usize>,
+
+    /// Hostnames, by failure domain name
+    pub(crate) failure_domains: IndexMap<String, Vec<String>>,
+
+    /// Hostnames named by a loaded config file, which must be registered by
+    /// the first step
+    pub(crate) referenced_hosts: IndexSet<String>,
+
+    /// Called when a host becomes idle or busy
+    pub(crate) on_host_state: Option<OnHostState>,
 }
 
 /// Configures link behavior.
@@ -19,8 +50,284 @@ pub(crate) struct Link {
     /// Message latency between two hosts
     pub(crate) latency: Option<Latency>,
 
+    /// Overrides `latency` for TCP messages
+    pub(crate) tcp_latency: Option<Latency>,
+
+    /// Overrides `latency` for UDP messages
+    pub(crate) udp_latency: Option<Latency>,
+
     /// How often sending a message works vs. the message getting dropped
     pub(crate) message_loss: Option<MessageLoss>,
+
+    /// No message is delivered faster than this, whatever the latency
+    pub(crate) latency_floor: Duration,
+
+    /// Link rate in bytes per second. If set, each message is delayed by its
+    /// size over this rate, on top of its latency.
+    pub(crate) bandwidth: Option<u64>,
+
+    /// How connections sharing a bandwidth limited link split its rate
+    pub(crate) bandwidth_sharing: BandwidthSharing,
+
+    /// If set, the most bytes of a UDP socket's datagrams that may wait on
+    /// bandwidth limited links and hosts before its sends block
+    pub(crate) udp_send_buffer: Option<usize>,
+
+    /// How messages from different links, deliverable to a host in the same
+    /// tick, are ordered
+    pub(crate) same_tick_order: SameTickOrder,
+}
+
+/// Groups hosts into zones, with latency configured between zones rather
+/// than between individual hosts.
+#[derive(Clone, Default)]
+pub(crate) struct Zones {
+    /// Zone name, by hostname
+    pub(crate) hosts: IndexMap<String, String>,
+
+    /// (min, max) latency between two zones, keyed by the ordered zone names
+    pub(crate) latency: IndexMap<(String, String), (Duration, Duration)>,
+}
+
+impl Zones {
+    /// The (min, max) latency configured between zones `a` and `b`.
+    pub(crate) fn latency(&self, a: &str, b: &str) -> Option<(Duration, Duration)> {
+        let key = if a < b { (a, b) } else { (b, a) };
+        self.latency
+            .get(&(key.0.to_string(), key.1.to_string()))
+            .copied()
+    }
+
+    pub(crate) fn set_latency(&mut self, a: &str, b: &str, min: Duration, max: Duration) {
+        let key = if a < b { (a, b) } else { (b, a) };
+        self.latency
+            .insert((key.0.to_string(), key.1.to_string()), (min, max));
+    }
+}
+
+/// How TCP streams deliver data, set with
+/// [`Builder::tcp_model`](crate::Builder::tcp_model).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TcpModel {
+    /// Segments are sent as they are written, without acknowledgment, so
+    /// writes never block.
+    #[default]
+    Reliable,
+
+    /// The receiver acknowledges segments, and the sender has at most `window`
+    /// bytes unacknowledged. Writes block while the window is full.
+    ///
+    /// Acknowledgments travel the return path, subject to its latency and
+    /// loss. Segments unacknowledged after 200ms are retransmitted, which shows
+    /// up as repeated deliveries when capturing.
+    AckGated { window: usize },
+}
+
+/// How messages that become deliverable to a host in the same tick, but were
+/// sent over different links, are ordered. Set with
+/// [`Builder::same_tick_order`](crate::Builder::same_tick_order).
+///
+/// Messages sent over the same link are always delivered in send order, within
+/// a priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SameTickOrder {
+    /// Deliver messages in the order they were sent.
+    #[default]
+    Fifo,
+
+    /// Interleave messages from different links in a random order, drawn from
+    /// the simulation's seed.
+    Random,
+}
+
+/// How the connections sending over a bandwidth limited link share its rate.
+/// Set with [`Builder::bandwidth_sharing`](crate::Builder::bandwidth_sharing).
+///
+/// Each direction of a link is shared separately, and a connection is a pair
+/// of socket addresses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BandwidthSharing {
+    /// Messages are put on the link one at a time, in send order, so a
+    /// message waits for everything sent before it.
+    #[default]
+    Fifo,
+
+    /// The rate is split equally between the connections with messages
+    /// queued on the link when a message is sent, so a connection only waits
+    /// behind its own messages.
+    Fair,
+}
+
+/// Message latency recorded over time, e.g. from production, and replayed on
+/// a link with [`Builder::link_latency_trace`](crate::Builder::link_latency_trace).
+///
+/// Each sample is the latency from a point in time, measured from the start of
+/// the simulation, and a message takes the latency in effect when it's sent.
+/// Between samples, the earlier sample holds, unless the trace
+/// [interpolates](LatencyTrace::interpolate). Before the first sample, the
+/// first holds, and past the last, the last holds, unless the trace
+/// [loops](LatencyTrace::looped).
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "FileLatencyTraceSamples"))]
+pub struct LatencyTrace {
+    /// (time, latency) samples, sorted by time
+    samples: Vec<(Duration, Duration)>,
+
+    interpolate: bool,
+
+    looped: bool,
+}
+
+impl LatencyTrace {
+    /// Create a trace from `(at, latency)` samples, in any order.
+    ///
+    /// # Panics
+    ///
+    /// If `samples` is empty.
+    pub fn new(samples: impl IntoIterator<Item = (Duration, Duration)>) -> Self {
+        let mut samples: Vec<_> = samples.into_iter().collect();
+        assert!(!samples.is_empty(), "latency trace has no samples");
+
+        samples.sort_by_key(|(at, _)| *at);
+
+        Self {
+            samples,
+            interpolate: false,
+            looped: false,
+        }
+    }
+
+    /// Interpolate linearly between samples, rather than holding each until
+    /// the next.
+    pub fn interpolate(mut self) -> Self {
+        self.interpolate = true;
+        self
+    }
+
+    /// Replay the trace from its start once the last sample's time is reached,
+    /// rather than holding the last latency.
+    pub fn looped(mut self) -> Self {
+        self.looped = true;
+        self
+    }
+
+    /// The latency in effect `elapsed` after the start of the simulation.
+    pub(crate) fn latency_at(&self, elapsed: Duration) -> Duration {
+        let end = self.samples[self.samples.len() - 1].0;
+
+        let elapsed = if self.looped && !end.is_zero() {
+            Duration::from_nanos((elapsed.as_nanos() % end.as_nanos()) as u64)
+        } else {
+            elapsed
+        };
+
+        let next = self.samples.partition_point(|(at, _)| *at <= elapsed);
+        let Some(&(at, latency)) = next.checked_sub(1).map(|i| &self.samples[i]) else {
+            return self.samples[0].1;
+        };
+
+        match self.samples.get(next) {
+            Some(&(next_at, next_latency)) if self.interpolate => {
+                let t = (elapsed - at).as_nanos() as f64 / (next_at - at).as_nanos() as f64;
+                lerp(latency, next_latency, t)
+            }
+            _ => latency,
+        }
+    }
+}
+
+/// The serialized form of a [`LatencyTrace`], which is checked and sorted by
+/// [`LatencyTrace::new`] when loaded.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileLatencyTraceSamples {
+    samples: Vec<(Duration, Duration)>,
+    interpolate: bool,
+    looped: bool,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<FileLatencyTraceSamples> for LatencyTrace {
+    type Error = String;
+
+    fn try_from(file: FileLatencyTraceSamples) -> Result<Self, Self::Error> {
+        if file.samples.is_empty() {
+            return Err("latency trace has no samples".to_string());
+        }
+
+        let mut trace = LatencyTrace::new(file.samples);
+        trace.interpolate = file.interpolate;
+        trace.looped = file.looped;
+        Ok(trace)
+    }
+}
+
+/// Latency traces, keyed by the ordered hostnames of the link.
+pub(crate) type LatencyTraces = IndexMap<(String, String), LatencyTrace>;
+
+/// Configures TCP behavior.
+#[derive(Clone, Default)]
+pub(crate) struct Tcp {
+    /// Whether writes to a peer behind a partitioned link fail immediately,
+    /// rather than being sent and dropped by the network.
+    pub(crate) fail_writes_on_partition: bool,
+
+    /// Whether to verify that bytes read from a stream exactly match, in
+    /// order, the bytes written by the peer.
+    pub(crate) validate_integrity: bool,
+
+    /// If set, received bytes are held back from readers until at least this
+    /// many are available, or the stream is closed.
+    pub(crate) read_boundary: Option<usize>,
+
+    /// If set, each read returns at most this many bytes.
+    pub(crate) max_read_chunk: Option<usize>,
+
+    /// How streams deliver data.
+    pub(crate) model: TcpModel,
+
+    /// How many times a lost SYN is retransmitted before connect fails.
+    pub(crate) syn_retries: u32,
+
+    /// How long to wait before the first SYN retransmission, doubling for
+    /// each subsequent one.
+    pub(crate) syn_backoff: Duration,
+
+    /// Whether a SYN is also retransmitted when its SYN-ACK is only late,
+    /// rather than just once it's lost.
+    pub(crate) retransmit_delayed_syns: bool,
+
+    /// If set, streams without traffic for this long are closed.
+    pub(crate) idle_timeout: Option<Duration>,
+
+    /// Round trips a secure connection's handshake takes, after the TCP
+    /// connect.
+    pub(crate) handshake_rtts: u32,
+
+    /// How often a secure connection's handshake fails.
+    pub(crate) handshake_fail_rate: f64,
+
+    /// If set, opening more than this many connections to the same peer
+    /// within the window fails the simulation.
+    pub(crate) reconnect_storm: Option<(usize, Duration)>,
+}
+
+/// Configures port allocation on each host.
+#[derive(Clone)]
+pub(crate) struct Ports {
+    /// Ports that are never assigned as ephemeral ports
+    pub(crate) reserved: IndexSet<u16>,
+
+    /// Ephemeral ports for TCP connections and listeners bound to port 0
+    pub(crate) tcp: RangeInclusive<u16>,
+
+    /// Ephemeral ports for UDP sockets bound to port 0
+    pub(crate) udp: RangeInclusive<u16>,
 }
 
 /// Configure latency behavior between two hosts.
@@ -44,6 +351,28 @@ pub(crate) struct MessageLoss {
 
     /// Probability of a failed link returning
     pub(crate) repair_rate: f64,
+
+    /// Optional message drops that increase with link congestion
+    pub(crate) congestion_drop: Option<CongestionDrop>,
+}
+
+/// Drop individual messages with a probability that scales with the number of
+/// messages queued on the link, modeling tail-drop.
+#[derive(Clone)]
+pub(crate) struct CongestionDrop {
+    /// Probability of dropping a message on an empty link
+    pub(crate) base: f64,
+
+    /// Additional probability for each message queued on the link
+    pub(crate) per_queued: f64,
+}
+
+impl CongestionDrop {
+    /// Probability of dropping a message when `queued` messages are on the
+    /// link.
+    pub(crate) fn drop_rate(&self, queued: usize) -> f64 {
+        (self.base + self.per_queued * queued as f64).clamp(0.0, 1.0)
+    }
 }
 
 impl Default for Config {
@@ -52,6 +381,13 @@ impl Default for Config {
             duration: Duration::from_secs(10),
             tick: Duration::from_millis(1),
             epoch: SystemTime::now(),
+            shuffle_startup: false,
+            capture: false,
+            record_latencies: false,
+            max_messages_per_tick: None,
+            failure_domains: IndexMap::new(),
+            referenced_hosts: IndexSet::new(),
+            on_host_state: None,
         }
     }
 }
@@ -74,6 +410,16 @@ impl Link {
     }
 }
 
+impl Default for Ports {
+    fn default() -> Ports {
+        Ports {
+            reserved: IndexSet::new(),
+            tcp: 1024..=65535,
+            udp: 1024..=65535,
+        }
+    }
+}
+
 impl Default for Latency {
     fn default() -> Latency {
         Latency {
@@ -89,6 +435,94 @@ impl Default for MessageLoss {
         MessageLoss {
             fail_rate: 0.0,
             repair_rate: 1.0,
+            congestion_drop: None,
         }
     }
 }
+
+/// The serialized form of a [`Builder`](crate::Builder)'s configuration.
+///
+/// Host software can't be serialized, so only the network and simulation
+/// configuration is saved. Hosts are matched to their zone by name when they
+/// are registered.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct File {
+    pub(crate) seed: Option<u64>,
+    pub(crate) duration: Duration,
+    pub(crate) tick: Duration,
+    pub(crate) epoch: SystemTime,
+    pub(crate) shuffle_startup: bool,
+    pub(crate) capture: bool,
+    pub(crate) record_latencies: bool,
+    pub(crate) max_messages_per_tick: Option<usize>,
+    pub(crate) min_message_latency: Duration,
+    pub(crate) max_message_latency: Duration,
+    pub(crate) latency_floor: Duration,
+    pub(crate) bandwidth: Option<u64>,
+    pub(crate) bandwidth_sharing: BandwidthSharing,
+    pub(crate) udp_send_buffer: Option<usize>,
+    pub(crate) same_tick_order: SameTickOrder,
+    pub(crate) fail_rate: f64,
+    pub(crate) repair_rate: f64,
+    pub(crate) congestion_drop: Option<(f64, f64)>,
+    pub(crate) hosts: Vec<FileHost>,
+    pub(crate) zone_latency: Vec<FileZoneLatency>,
+    pub(crate) link_latency_traces: Vec<FileLatencyTrace>,
+    pub(crate) failure_domains: Vec<FileFailureDomain>,
+    pub(crate) fail_writes_on_partition: bool,
+    pub(crate) validate_tcp_integrity: bool,
+    pub(crate) tcp_read_boundary: Option<usize>,
+    pub(crate) max_read_chunk: Option<usize>,
+    pub(crate) tcp_model: TcpModel,
+    pub(crate) syn_retries: u32,
+    pub(crate) syn_backoff: Duration,
+    pub(crate) retransmit_delayed_syns: bool,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) reconnect_storm_threshold: Option<(usize, Duration)>,
+    pub(crate) handshake_rtts: u32,
+    pub(crate) handshake_fail_rate: f64,
+    pub(crate) reserved_ports: Vec<u16>,
+    pub(crate) tcp_ephemeral_ports: RangeInclusive<u16>,
+    pub(crate) udp_ephemeral_ports: RangeInclusive<u16>,
+}
+
+/// A host's zone in a [`File`].
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileHost {
+    pub(crate) name: String,
+    pub(crate) zone: String,
+}
+
+/// The latency between two zones in a [`File`].
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileZoneLatency {
+    pub(crate) a: String,
+    pub(crate) b: String,
+    pub(crate) min: Duration,
+    pub(crate) max: Duration,
+}
+
+/// A latency trace between two hosts in a [`File`].
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileLatencyTrace {
+    pub(crate) a: String,
+    pub(crate) b: String,
+    pub(crate) trace: LatencyTrace,
+}
+
+/// A failure domain in a [`File`].
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileFailureDomain {
+    pub(crate) name: String,
+    pub(crate) hosts: Vec<String>,
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// A recording of the network's random decisions, which can be replayed into
+/// a simulation with
+/// [`Builder::replay_decisions`](crate::Builder::replay_decisions).
+///
+/// Unlike a seed, a log reproduces the same losses and latencies even if code
+/// changes shift how the random number generator is consumed elsewhere.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecisionLog {
+    decisions: Vec<Decision>,
+}
+
+/// A random decision made by a link as it sends a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Decision {
+    /// Whether a healthy link was randomly partitioned.
+    Partition(bool),
+
+    /// Whether a randomly partitioned link was repaired.
+    Repair(bool),
+
+    /// Whether a message was dropped due to congestion.
+    Drop(bool),
+
+    /// The latency of a message, before the latency floor applies.
+    Latency(Duration),
+}
+
+impl DecisionLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a decision to the log.
+    pub fn push(&mut self, decision: Decision) -> &mut Self {
+        self.decisions.push(decision);
+        self
+    }
+
+    /// The recorded decisions, in the order they were made.
+    pub fn iter(&self) -> impl Iterator<Item = &Decision> {
+        self.decisions.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.decisions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decisions.is_empty()
+    }
+}
+
+/// Records decisions, and hands out replayed ones in place of random ones.
+#[derive(Default)]
+pub(crate) struct Decisions {
+    /// Decisions made so far, if recording.
+    pub(crate) recorded: Option<DecisionLog>,
+
+    /// Decisions left to replay.
+    replay: VecDeque<Decision>,
+}
+
+impl Decisions {
+    pub(crate) fn replaying(log: DecisionLog) -> Self {
+        Self {
+            recorded: None,
+            replay: log.decisions.into(),
+        }
+    }
+
+    pub(crate) fn partition(&mut self, rand: impl FnOnce() -> bool) -> bool {
+        let value = self
+            .replay(|decision| match decision {
+                Decision::Partition(value) => Some(*value),
+                _ => None,
+            })
+            .unwrap_or_else(rand);
+        self.record(Decision::Partition(value));
+        value
+    }
+
+    pub(crate) fn repair(&mut self, rand: impl FnOnce() -> bool) -> bool {
+        let value = self
+            .replay(|decision| match decision {
+                Decision::Repair(value) => Some(*value),
+                _ => None,
+            })
+            .unwrap_or_else(rand);
+        self.record(Decision::Repair(value));
+        value
+    }
+
+    pub(crate) fn drop(&mut self, rand: impl FnOnce() -> bool) -> bool {
+        let value = self
+            .replay(|decision| match decision {
+                Decision::Drop(value) => Some(*value),
+                _ => None,
+            })
+            .unwrap_or_else(rand);
+        self.record(Decision::Drop(value));
+        value
+    }
+
+    pub(crate) fn latency(&mut self, rand: impl FnOnce() -> Duration) -> Duration {
+        let value = self
+            .replay(|decision| match decision {
+                Decision::Latency(value) => Some(*value),
+                _ => None,
+            })
+            .unwrap_or_else(rand);
+        self.record(Decision::Latency(value));
+        value
+    }
+
+    /// Take the next decision to replay of the kind `value` matches, leaving
+    /// decisions of other kinds in place.
+    fn replay<T>(&mut self, value: impl Fn(&Decision) -> Option<T>) -> Option<T> {
+        let at = self
+            .replay
+            .iter()
+            .position(|decision| value(decision).is_some())?;
+        self.replay.remove(at).and_then(|decision| value(&decision))
+    }
+
+    fn record(&mut self, decision: Decision) {
+        if let Some(log) = &mut self.recorded {
+            log.push(decision);
+        }
+    }
+}
@@ -42,6 +42,26 @@ impl Dns {
         addrs.to_ip_addrs(self)
     }
 
+    /// Get the address assigned to `name`, without assigning a new one.
+    pub(crate) fn get(&self, name: &str) -> Option<IpAddr> {
+        self.names.get(name).copied()
+    }
+
+    /// All assigned names, in assignment order.
+    pub(crate) fn names(&self) -> impl Iterator<Item = (&str, IpAddr)> {
+        self.names.iter().map(|(name, addr)| (&name[..], *addr))
+    }
+
+    /// Point `name` at `addr`, replacing any address it already has.
+    pub(crate) fn insert(&mut self, name: &str, addr: IpAddr) {
+        self.names.insert(name.to_string(), addr);
+    }
+
+    /// Remove `name`, returning its address if it was assigned.
+    pub(crate) fn remove(&mut self, name: &str) -> Option<IpAddr> {
+        self.names.shift_remove(name)
+    }
+
     pub(crate) fn reverse(&self, addr: IpAddr) -> &str {
         self.names
             .iter()
@@ -57,7 +77,7 @@ impl ToIpAddr for String {
     }
 }
 
-impl<'a> ToIpAddr for &'a str {
+impl ToIpAddr for &str {
     fn to_ip_addr(&self, dns: &mut Dns) -> IpAddr {
         *dns.names.entry(self.to_string()).or_insert_with(|| {
             let host = dns.next;
@@ -105,11 +125,11 @@ impl ToSocketAddrs for (String, u16) {
     }
 }
 
-impl<'a> ToSocketAddrs for (&'a str, u16) {
+impl ToSocketAddrs for (&str, u16) {
     fn to_socket_addr(&self, dns: &Dns) -> SocketAddr {
         match dns.names.get(self.0) {
             Some(ip) => (*ip, self.1).into(),
-            None => panic!("no hostname found for ip address"),
+            None => panic!("no ip address found for hostname: {}", self.0),
         }
     }
 }
@@ -1,4 +1,4 @@
-use std::{fmt::Display, net::SocketAddr};
+use std::{fmt::Display, io, net::SocketAddr};
 
 use bytes::Bytes;
 use tokio::sync::oneshot;
@@ -15,12 +15,51 @@ pub(crate) struct Envelope {
 pub enum Protocol {
     Tcp(Segment),
     Udp(Datagram),
+    Icmp(Icmp),
+    Raw(Frame),
 }
 
 /// UDP datagram.
 #[derive(Debug)]
 pub struct Datagram(pub Bytes);
 
+/// An opaque frame, sent with a [`RawSocket`](crate::net::RawSocket).
+#[derive(Debug)]
+pub struct Frame(pub Bytes);
+
+/// ICMP messages, sent by the simulated network stack in response to UDP
+/// datagrams, or injected into TCP connections with
+/// [`Sim::inject_icmp`](crate::Sim::inject_icmp).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Icmp {
+    /// No socket is bound to the destination port of a datagram.
+    PortUnreachable,
+
+    /// The destination host can't be reached.
+    HostUnreachable,
+
+    /// A packet exceeded the path MTU, and may not be fragmented.
+    FragmentationNeeded,
+}
+
+impl Icmp {
+    /// The error a socket surfaces for this message, e.g. for a connection
+    /// to `peer`.
+    pub(crate) fn error(&self, peer: SocketAddr) -> io::Error {
+        match self {
+            Icmp::PortUnreachable => {
+                io::Error::new(io::ErrorKind::ConnectionRefused, peer.to_string())
+            }
+            Icmp::HostUnreachable => {
+                io::Error::new(io::ErrorKind::HostUnreachable, peer.to_string())
+            }
+            Icmp::FragmentationNeeded => {
+                io::Error::new(io::ErrorKind::InvalidInput, "Message too long")
+            }
+        }
+    }
+}
+
 /// This is a simplification of real TCP.
 ///
 /// We implement just enough to ensure fidelity and provide knobs for real world
@@ -32,6 +71,10 @@ pub enum Segment {
     Data(u64, Bytes),
     Fin(u64),
     Rst,
+
+    /// Acknowledges every segment up to and including the sequence number,
+    /// with [`TcpModel::AckGated`](crate::TcpModel::AckGated).
+    Ack(u64),
 }
 
 #[derive(Debug)]
@@ -44,6 +87,8 @@ impl Display for Protocol {
         match self {
             Protocol::Tcp(segment) => Display::fmt(segment, f),
             Protocol::Udp(datagram) => Display::fmt(&datagram, f),
+            Protocol::Icmp(icmp) => Display::fmt(icmp, f),
+            Protocol::Raw(frame) => Display::fmt(frame, f),
         }
     }
 }
@@ -54,6 +99,22 @@ impl Display for Datagram {
     }
 }
 
+impl Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        hex("RAW", &self.0, f)
+    }
+}
+
+impl Display for Icmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Icmp::PortUnreachable => write!(f, "ICMP PORT UNREACHABLE"),
+            Icmp::HostUnreachable => write!(f, "ICMP HOST UNREACHABLE"),
+            Icmp::FragmentationNeeded => write!(f, "ICMP FRAGMENTATION NEEDED"),
+        }
+    }
+}
+
 impl Display for Segment {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -61,6 +122,7 @@ impl Display for Segment {
             Segment::Data(_, data) => hex("TCP", data, f),
             Segment::Fin(_) => write!(f, "TCP FIN"),
             Segment::Rst => write!(f, "TCP RST"),
+            Segment::Ack(_) => write!(f, "TCP ACK"),
         }
     }
 }
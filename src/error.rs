@@ -1,3 +1,7 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
 /// A specialized [`Result`] type for turmoil simulations.
 ///
 /// This type is generally useful for fallible test cases, i.e. where you want
@@ -6,3 +10,65 @@
 ///
 /// [`Result`]: std::result::Result
 pub type Result<T = ()> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Turmoil specific failures.
+///
+/// Host software sees these as [`io::Error`]s, with a matching
+/// [`io::ErrorKind`], so existing `io::Result` signatures are unaffected. Use
+/// [`Error::from_io`] to match on the precise failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// Binding to a specific address is not supported. Only the unspecified
+    /// address, e.g. 0.0.0.0, may be bound.
+    UnsupportedBind(SocketAddr),
+
+    /// No host owns the address.
+    HostNotFound(IpAddr),
+
+    /// Every ephemeral port on the host is in use.
+    PortsExhausted,
+
+    /// The operation must be performed from within a Turmoil simulation.
+    NoSimulation,
+
+    /// The simulated secure handshake with the peer failed. See
+    /// [`TcpStream::connect_secure`](crate::net::TcpStream::connect_secure).
+    HandshakeFailed(SocketAddr),
+}
+
+impl Error {
+    /// The turmoil error wrapped by `err`, if any.
+    pub fn from_io(err: &io::Error) -> Option<&Error> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::UnsupportedBind(_) | Error::PortsExhausted => io::ErrorKind::AddrNotAvailable,
+            Error::HostNotFound(_) => io::ErrorKind::HostUnreachable,
+            Error::NoSimulation => io::ErrorKind::Other,
+            Error::HandshakeFailed(_) => io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnsupportedBind(addr) => write!(f, "{addr} is not supported"),
+            Error::HostNotFound(addr) => write!(f, "no host found for {addr}"),
+            Error::PortsExhausted => write!(f, "ephemeral ports exhausted"),
+            Error::NoSimulation => write!(f, "not within a Turmoil simulation"),
+            Error::HandshakeFailed(addr) => write!(f, "secure handshake with {addr} failed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        io::Error::new(err.kind(), err)
+    }
+}
@@ -0,0 +1,204 @@
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use std::time::Duration;
+
+use crate::Sim;
+
+/// Why a simulation run with [`run`] failed.
+#[derive(Debug)]
+pub struct TestFailure {
+    /// The host that failed, or `None` if the simulation itself failed, e.g.
+    /// by running out of time.
+    pub host: Option<String>,
+
+    /// How long the simulation had run for.
+    pub elapsed: Duration,
+
+    /// The error, or panic message.
+    pub message: String,
+
+    /// Whether a host panicked, rather than returning an error.
+    pub panicked: bool,
+
+    /// Where the panic happened, captured as configured by `RUST_BACKTRACE`.
+    pub backtrace: Option<Backtrace>,
+}
+
+impl fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.host {
+            Some(host) => write!(f, "host `{host}`")?,
+            None => write!(f, "simulation")?,
+        }
+
+        let failed = if self.panicked { "panicked" } else { "failed" };
+        write!(f, " {failed} after {:?}: {}", self.elapsed, self.message)?;
+
+        if let Some(backtrace) = &self.backtrace {
+            if backtrace.status() == BacktraceStatus::Captured {
+                write!(f, "\n{backtrace}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for TestFailure {}
+
+/// Build a simulation with `setup`, run it to completion, and return the
+/// first host error or panic, or simulation error, with its context.
+///
+/// This replaces the `sim.run().unwrap()` at the end of most tests, with a
+/// failure that names the host and simulated time. Host panics are caught,
+/// including those in tasks the host spawned, and returned rather than
+/// unwinding through the test.
+///
+/// ```
+/// let res = turmoil::run(|| {
+///     let mut sim = turmoil::Builder::new().build();
+///     sim.client("client", async { Err("boom".into()) });
+///     sim
+/// });
+///
+/// let failure = res.unwrap_err();
+/// assert_eq!(Some("client"), failure.host.as_deref());
+/// assert_eq!("boom", failure.message);
+/// ```
+pub fn run<'a>(setup: impl FnOnce() -> Sim<'a>) -> Result<(), TestFailure> {
+    let mut sim = setup();
+
+    hook_panics();
+    PANIC.with(|panic| *panic.borrow_mut() = Some(None));
+
+    let res = panic::catch_unwind(AssertUnwindSafe(|| sim.run()));
+    let panic = PANIC.with(|panic| panic.borrow_mut().take().flatten());
+
+    let (message, panicked, backtrace) = match (res, panic) {
+        (Ok(Ok(())), _) => return Ok(()),
+        // A task panicked, failing its host, or the whole simulation when
+        // tokio forwards panics. The original panic is the useful one.
+        (_, Some((message, backtrace))) => (message, true, Some(backtrace)),
+        (Ok(Err(err)), None) => (err.to_string(), false, None),
+        (Err(payload), None) => (payload_message(&*payload), true, None),
+    };
+
+    Err(TestFailure {
+        host: sim.failed_host(),
+        elapsed: sim.elapsed(),
+        message,
+        panicked,
+        backtrace,
+    })
+}
+
+thread_local! {
+    /// `Some` while [`run`] is running on this thread, holding the first
+    /// panic's message and backtrace.
+    static PANIC: RefCell<Option<Option<(String, Backtrace)>>> = const { RefCell::new(None) };
+}
+
+/// Install a panic hook, once, that records the first panic on threads in
+/// [`run`], then defers to the previous hook.
+fn hook_panics() {
+    static HOOK: Once = Once::new();
+
+    HOOK.call_once(|| {
+        let prev = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            let _ = PANIC.try_with(|panic| {
+                if let Ok(mut panic) = panic.try_borrow_mut() {
+                    if let Some(first @ None) = panic.as_mut() {
+                        *first = Some((payload_message(info.payload()), Backtrace::capture()));
+                    }
+                }
+            });
+
+            prev(info);
+        }));
+    });
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{run, Builder};
+
+    #[test]
+    fn host_error() {
+        let failure = run(|| {
+            let mut sim = Builder::new().build();
+
+            sim.host("server", || async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Err("server failed")?
+            });
+            sim.client("client", std::future::pending());
+
+            sim
+        })
+        .unwrap_err();
+
+        assert_eq!(Some("server"), failure.host.as_deref());
+        assert_eq!(Duration::from_millis(10), failure.elapsed);
+        assert_eq!("server failed", failure.message);
+        assert!(!failure.panicked);
+    }
+
+    #[test]
+    fn host_panic() {
+        let failure = run(|| {
+            let mut sim = Builder::new().build();
+
+            sim.client("client", async {
+                tokio::task::spawn_local(async { panic!("task panicked") });
+                std::future::pending().await
+            });
+
+            sim
+        })
+        .unwrap_err();
+
+        assert_eq!(Some("client"), failure.host.as_deref());
+        assert_eq!("task panicked", failure.message);
+        assert!(failure.panicked);
+    }
+
+    #[test]
+    fn timeout() {
+        let failure = run(|| {
+            let mut sim = Builder::new()
+                .simulation_duration(Duration::from_secs(1))
+                .build();
+            sim.client("client", std::future::pending());
+            sim
+        })
+        .unwrap_err();
+
+        assert_eq!(None, failure.host);
+        assert!(failure.message.starts_with("Ran for 1s"));
+
+        assert!(run(|| {
+            let mut sim = Builder::new().build();
+            sim.client("client", async { Ok(()) });
+            sim
+        })
+        .is_ok());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{Result, Sim, ToIpAddrs};
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// A command marshaled into the simulation thread.
+type Command = Box<dyn FnOnce(&mut Sim<'static>) + Send>;
+
+/// A handle to a simulation running on a background thread, created with
+/// [`Sim::spawn`].
+///
+/// The simulation is single threaded, so every method marshals a command into
+/// the simulation thread and blocks until it has run. Commands run between
+/// steps, which preserves determinism, and the simulation only advances when
+/// [`SimHandle::step`] or [`SimHandle::run`] is called.
+///
+/// Dropping the handle stops the simulation thread.
+pub struct SimHandle {
+    tx: Option<mpsc::Sender<Command>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Sim<'static> {
+    /// Run a simulation on a dedicated thread, returning a handle to control
+    /// it.
+    ///
+    /// A simulation can't be moved across threads, and so it is built on the
+    /// simulation thread by `setup`.
+    pub fn spawn<F>(setup: F) -> SimHandle
+    where
+        F: FnOnce() -> Sim<'static> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel::<Command>();
+
+        let thread = thread::spawn(move || {
+            let mut sim = setup();
+
+            while let Ok(command) = rx.recv() {
+                command(&mut sim);
+            }
+        });
+
+        SimHandle {
+            tx: Some(tx),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl SimHandle {
+    /// Run `f` with the simulation on its thread, returning the result.
+    ///
+    /// Panics if the simulation thread has panicked.
+    pub fn exec<R, F>(&self, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Sim<'static>) -> R + Send + 'static,
+    {
+        let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+
+        let command: Command = Box::new(move |sim| {
+            let _ = reply_tx.send(f(sim));
+        });
+
+        let tx = self.tx.as_ref().expect("simulation thread stopped");
+
+        match tx.send(command).ok().and_then(|_| reply_rx.recv().ok()) {
+            Some(reply) => reply,
+            None => panic!("simulation thread panicked"),
+        }
+    }
+
+    /// Step the simulation. See [`Sim::step`].
+    pub fn step(&self) -> Result<bool> {
+        self.exec(|sim| sim.step().map_err(|e| e.to_string()))
+            .map_err(Into::into)
+    }
+
+    /// Run the simulation to completion. See [`Sim::run`].
+    ///
+    /// Other commands wait until the run returns, so a simulation that should
+    /// be stopped by hand is better driven with [`SimHandle::step`].
+    pub fn run(&self) -> Result {
+        self.exec(|sim| sim.run().map_err(|e| e.to_string()))
+            .map_err(Into::into)
+    }
+
+    /// Freeze the simulation. See [`Sim::pause`].
+    pub fn pause(&self) {
+        self.exec(|sim| sim.pause())
+    }
+
+    /// Resume a paused simulation. See [`Sim::resume`].
+    pub fn resume(&self) {
+        self.exec(|sim| sim.resume())
+    }
+
+    /// How much logical time has elapsed since the simulation started.
+    pub fn elapsed(&self) -> Duration {
+        self.exec(|sim| sim.elapsed())
+    }
+
+    /// Partition two hosts, or sets of hosts. See [`crate::partition`].
+    pub fn partition<A, B>(&self, a: A, b: B)
+    where
+        A: ToIpAddrs + Send + 'static,
+        B: ToIpAddrs + Send + 'static,
+    {
+        self.exec(|sim| sim.enter(|| crate::partition(a, b)))
+    }
+
+    /// Repair the connection between two hosts, or sets of hosts. See
+    /// [`crate::repair`].
+    pub fn repair<A, B>(&self, a: A, b: B)
+    where
+        A: ToIpAddrs + Send + 'static,
+        B: ToIpAddrs + Send + 'static,
+    {
+        self.exec(|sim| sim.enter(|| crate::repair(a, b)))
+    }
+
+    /// Crashes the resolved hosts. See [`Sim::crash`].
+    pub fn crash(&self, addrs: impl ToIpAddrs + Send + 'static) {
+        self.exec(|sim| sim.crash(addrs))
+    }
+
+    /// Bounces the resolved hosts. See [`Sim::bounce`].
+    pub fn bounce(&self, addrs: impl ToIpAddrs + Send + 'static) {
+        self.exec(|sim| sim.bounce(addrs))
+    }
+}
+
+impl Drop for SimHandle {
+    fn drop(&mut self) {
+        // Closing the channel ends the command loop
+        drop(self.tx.take());
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        net::{TcpListener, TcpStream},
+        Builder, Result, Sim,
+    };
+
+    #[test]
+    fn control_from_another_thread() -> Result {
+        let handle = Sim::spawn(|| {
+            let mut sim = Builder::new().build();
+
+            sim.host("server", || async {
+                let listener = TcpListener::bind("0.0.0.0:1234").await?;
+
+                loop {
+                    let _ = listener.accept().await?;
+                }
+            });
+
+            sim.client("client", async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+
+                // The partition was made from the main thread
+                assert!(TcpStream::connect("server:1234").await.is_err());
+
+                Ok(())
+            });
+
+            sim
+        });
+
+        assert!(!handle.step()?);
+        assert_eq!(Duration::from_millis(1), handle.elapsed());
+
+        handle.partition("client", "server");
+        handle.run()?;
+
+        Ok(())
+    }
+}
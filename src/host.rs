@@ -1,18 +1,29 @@
-use crate::envelope::{hex, Datagram, Protocol, Segment, Syn};
-use crate::net::{SocketPair, TcpListener, UdpSocket};
+use crate::envelope::{hex, Datagram, Frame, Icmp, Protocol, Segment, Syn};
+use crate::net::{Queued, RawSocket, SocketPair, TcpListener, UdpSocket};
 use crate::world::World;
-use crate::{Envelope, TRACING_TARGET};
+use crate::{config, Delivery, Envelope, Error, TRACING_TARGET};
 
 use bytes::Bytes;
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
+use rand::distributions::{Distribution, Standard};
+use rand::rngs::SmallRng;
+use rand::{Rng, RngCore};
 use std::collections::VecDeque;
 use std::fmt::Display;
+use std::future::{poll_fn, Future};
 use std::io;
 use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
 use tokio::sync::{mpsc, Notify};
+use tokio::time::error::Elapsed;
 use tokio::time::{Duration, Instant};
 
+/// Decides whether a packet crosses a host's boundary. See
+/// [`Sim::set_egress_filter`](crate::Sim::set_egress_filter).
+pub(crate) type PacketFilter = Box<dyn Fn(&Delivery) -> bool>;
+
 /// A host in the simulated network.
 ///
 /// Hosts have [`Udp`] and [`Tcp`] software available for networking.
@@ -28,25 +39,78 @@ pub(crate) struct Host {
     /// L4 Transmission Control Protocol (TCP).
     pub(crate) tcp: Tcp,
 
-    /// Ports 1024..=65535 for client connections.
-    next_ephemeral_port: u16,
+    /// Opaque frames, for protocols built on top of the simulated links.
+    pub(crate) raw: Raw,
+
+    /// Drops packets the host sends that it rejects.
+    pub(crate) egress_filter: Option<PacketFilter>,
+
+    /// Drops packets delivered to the host that it rejects.
+    pub(crate) ingress_filter: Option<PacketFilter>,
+
+    /// Ports that are never assigned as ephemeral ports.
+    reserved_ports: IndexSet<u16>,
+
+    /// Ephemeral ports for TCP.
+    tcp_ports: EphemeralPorts,
+
+    /// Ephemeral ports for UDP.
+    udp_ports: EphemeralPorts,
 
     /// Host elapsed time.
     elapsed: Duration,
 
     /// Set each time the software is run.
     now: Option<Instant>,
+
+    /// The granularity of the time observed by host software, if coarser than
+    /// the tick.
+    pub(crate) clock_resolution: Option<Duration>,
+
+    /// Tasks that called [`yield_now`], or are waiting for a full send buffer,
+    /// to be woken after the next step.
+    yielded: Vec<Waker>,
+
+    /// Random number generator for host software, seeded from the simulation
+    /// and the host's name.
+    rng: SmallRng,
+
+    /// Simulated storage, which outlives the host's software.
+    #[cfg(feature = "storage")]
+    pub(crate) storage: crate::storage::Storage,
+
+    /// When the host opened each recent TCP connection, in host elapsed time,
+    /// by peer. Only tracked when detecting reconnect storms.
+    connects: IndexMap<IpAddr, VecDeque<Duration>>,
+
+    /// Deadlines, in host elapsed time, of pending [`sleep`] calls.
+    timers: IndexMap<u64, Duration>,
+
+    next_timer_id: u64,
 }
 
 impl Host {
-    pub(crate) fn new(addr: IpAddr) -> Host {
+    pub(crate) fn new(addr: IpAddr, rng: SmallRng, ports: &config::Ports) -> Host {
         Host {
             addr,
             udp: Udp::new(),
             tcp: Tcp::new(),
-            next_ephemeral_port: 1024,
+            raw: Raw::new(),
+            egress_filter: None,
+            ingress_filter: None,
+            reserved_ports: ports.reserved.clone(),
+            tcp_ports: EphemeralPorts::new(ports.tcp.clone()),
+            udp_ports: EphemeralPorts::new(ports.udp.clone()),
             elapsed: Duration::ZERO,
             now: None,
+            clock_resolution: None,
+            yielded: vec![],
+            rng,
+            #[cfg(feature = "storage")]
+            storage: Default::default(),
+            connects: IndexMap::new(),
+            timers: IndexMap::new(),
+            next_timer_id: 0,
         }
     }
 
@@ -67,31 +131,98 @@ impl Host {
         self.elapsed + run_duration
     }
 
-    pub(crate) fn assign_ephemeral_port(&mut self) -> u16 {
-        // Check for existing binds to avoid port conflicts
-        loop {
-            let ret = self.next_ephemeral_port;
+    /// The elapsed time host software observes, rounded down to a multiple of
+    /// the clock resolution.
+    pub(crate) fn observed_elapsed(&self) -> Duration {
+        let elapsed = self.elapsed();
 
-            if self.next_ephemeral_port == 65535 {
-                // re-load
-                self.next_ephemeral_port = 1024;
-            } else {
-                // advance
-                self.next_ephemeral_port += 1;
+        match self.clock_resolution {
+            Some(resolution) => {
+                let steps = elapsed.as_nanos() / resolution.as_nanos();
+                Duration::from_nanos((steps * resolution.as_nanos()) as u64)
             }
+            None => elapsed,
+        }
+    }
 
-            if self.udp.is_port_assigned(ret) || self.tcp.is_port_assigned(ret) {
-                continue;
-            }
+    /// Record a TCP connect to `peer`, returning how many the host has opened
+    /// to it within `window`, including this one.
+    pub(crate) fn record_connect(&mut self, peer: IpAddr, window: Duration) -> usize {
+        let now = self.elapsed();
+        let connects = self.connects.entry(peer).or_default();
+
+        while connects.front().is_some_and(|at| now - *at >= window) {
+            connects.pop_front();
+        }
+        connects.push_back(now);
+
+        connects.len()
+    }
+
+    /// Whether a UDP socket, or a TCP listener or connection, uses `port`.
+    pub(crate) fn is_port_assigned(&self, port: u16) -> bool {
+        self.udp.is_port_assigned(port) || self.tcp.is_port_assigned(port)
+    }
+
+    /// Whether nothing on the host will wake its software by itself: no task
+    /// has yielded, no TCP segment awaits retransmission, and, if streams are
+    /// reaped after `idle_timeout`, no stream is open.
+    pub(crate) fn is_quiescent(&self, idle_timeout: Option<Duration>) -> bool {
+        self.yielded.is_empty()
+            && !self.tcp.has_unacked()
+            && (idle_timeout.is_none() || self.tcp.stream_pairs().is_empty())
+    }
+
+    /// The streams that have had no traffic for `timeout`.
+    pub(crate) fn tcp_idle_streams(&self, timeout: Duration) -> Vec<SocketPair> {
+        self.tcp.idle_streams(timeout, self.elapsed)
+    }
+
+    /// Track a sent TCP segment until it is acknowledged. See
+    /// [`TcpModel::AckGated`](crate::TcpModel::AckGated).
+    pub(crate) fn tcp_sent(&mut self, pair: SocketPair, seq: u64, segment: SequencedSegment) {
+        self.tcp.sent(pair, seq, segment, self.elapsed);
+    }
 
-            return ret;
+    /// The latency to add to a segment sent on the stream `pair`: its extra
+    /// latency, plus a random share of its jitter.
+    pub(crate) fn tcp_send_latency(&mut self, pair: SocketPair) -> Duration {
+        let jitter = self.tcp.jitter(pair);
+        let extra_latency = self.tcp.extra_latency(pair);
+
+        if jitter.is_zero() {
+            extra_latency
+        } else {
+            extra_latency + self.rng.gen_range(Duration::ZERO..=jitter)
         }
     }
 
+    /// TCP segments due for retransmission, with the extra latency of their
+    /// stream.
+    pub(crate) fn tcp_retransmits(&mut self) -> Vec<(SocketPair, Segment, Duration)> {
+        self.tcp.retransmits(self.elapsed)
+    }
+
+    /// Assign an ephemeral port for a TCP connection or listener.
+    pub(crate) fn assign_tcp_port(&mut self) -> io::Result<u16> {
+        let (udp, tcp) = (&self.udp, &self.tcp);
+        self.tcp_ports.assign(&self.reserved_ports, |port| {
+            udp.is_port_assigned(port) || tcp.is_port_assigned(port)
+        })
+    }
+
+    /// Assign an ephemeral port for a UDP socket.
+    pub(crate) fn assign_udp_port(&mut self) -> io::Result<u16> {
+        let (udp, tcp) = (&self.udp, &self.tcp);
+        self.udp_ports.assign(&self.reserved_ports, |port| {
+            udp.is_port_assigned(port) || tcp.is_port_assigned(port)
+        })
+    }
+
     /// Receive the `envelope` from the network.
     ///
-    /// Returns an Err if a message needs to be sent in response to a failed
-    /// delivery, e.g. TCP RST.
+    /// Returns an Err if a message needs to be sent in response, e.g. TCP RST
+    /// for a failed delivery, or a TCP ACK.
     // FIXME: This funkiness is necessary due to how message sending works. The
     // key problem is that the Host doesn't actually send messages, rather the
     // World is borrowed, and it sends.
@@ -101,42 +232,363 @@ impl Host {
         tracing::trace!(target: TRACING_TARGET, ?dst, ?src, protocol = %message, "Delivered");
 
         match message {
-            Protocol::Tcp(segment) => self.tcp.receive_from_network(src, dst, segment),
-            Protocol::Udp(datagram) => {
-                self.udp.receive_from_network(src, dst, datagram);
+            Protocol::Tcp(segment) => {
+                self.tcp
+                    .receive_from_network(src, dst, segment, self.elapsed)
+            }
+            Protocol::Udp(datagram) => self.udp.receive_from_network(src, dst, datagram),
+            Protocol::Icmp(icmp) => {
+                self.udp.receive_icmp(src, dst, icmp);
+                Ok(())
+            }
+            Protocol::Raw(frame) => {
+                self.raw.receive_from_network(src, dst, frame);
                 Ok(())
             }
         }
     }
 
+    /// Whether the egress filter lets `message`, sent from `src` to `dst`,
+    /// leave the host.
+    pub(crate) fn permits_egress(
+        &self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        message: &Protocol,
+    ) -> bool {
+        let permitted = self
+            .egress_filter
+            .as_ref()
+            .is_none_or(|filter| filter(&Delivery::of(src, dst, message)));
+
+        if !permitted {
+            tracing::trace!(target: TRACING_TARGET, ?src, ?dst, protocol = %message, "Drop (Egress filter)");
+        }
+
+        permitted
+    }
+
     pub(crate) fn tick(&mut self, duration: Duration) {
         self.elapsed += duration
     }
+
+    /// Discard all sockets without notifying peers.
+    ///
+    /// Used when the host is killed, leaving peers with half-open connections.
+    pub(crate) fn discard_sockets(&mut self) {
+        let accept_delay = self.tcp.accept_delay;
+        let generation = self.tcp.generation + 1;
+
+        self.udp = Udp::new();
+        self.tcp = Tcp::new();
+        self.raw = Raw::new();
+        self.tcp.accept_delay = accept_delay;
+
+        // Sockets still held by the host's software belong to the previous
+        // generation, so they can't unbind sockets bound since.
+        self.udp.generation = generation;
+        self.tcp.generation = generation;
+        self.raw.generation = generation;
+    }
+
+    /// Reset the host's network: all of its sockets are discarded, and its
+    /// connections reset, while its software keeps running.
+    ///
+    /// Returns the pairs of the connections that were reset, for the caller
+    /// to notify peers.
+    pub(crate) fn reset_network(&mut self) -> Vec<SocketPair> {
+        let pairs = self.tcp.stream_pairs();
+        let draining = self.tcp.draining;
+
+        // Wake pending accepts, which fail now that the listener is gone
+        for sock in self.tcp.binds.values() {
+            sock.notify.notify_waiters();
+        }
+
+        self.discard_sockets();
+        self.tcp.draining = draining;
+
+        pairs
+    }
+
+    /// Discard pending timers, for when the host is killed outside of the
+    /// world.
+    pub(crate) fn discard_timers(&mut self) {
+        self.timers.clear();
+    }
+
+    /// How long until each pending [`sleep`] or [`timeout`] completes, ordered
+    /// by deadline.
+    pub(crate) fn pending_timers(&self) -> Vec<Duration> {
+        let mut timers = self
+            .timers
+            .values()
+            .map(|deadline| deadline.saturating_sub(self.elapsed))
+            .collect::<Vec<_>>();
+        timers.sort();
+        timers
+    }
+
+    /// Wake the task after the next step of the simulation.
+    pub(crate) fn wake_after_step(&mut self, waker: &Waker) {
+        self.yielded.push(waker.clone());
+    }
+
+    /// Take the wakers of all tasks that yielded since the last call.
+    pub(crate) fn take_yielded(&mut self) -> Vec<Waker> {
+        std::mem::take(&mut self.yielded)
+    }
 }
 
 /// Returns how long the currently executing host has been executing for in
 /// virtual time.
 ///
+/// On a host with a coarse clock, see
+/// [`Sim::set_clock_resolution`](crate::Sim::set_clock_resolution), this is
+/// rounded down to a multiple of the resolution.
+///
 /// Must be called from within a Turmoil simulation.
 pub fn elapsed() -> Duration {
-    World::current(|world| world.current_host_mut().elapsed())
+    World::current(|world| world.current_host_mut().observed_elapsed())
+}
+
+/// Generates a random value using the currently executing host's random
+/// number generator.
+///
+/// Host software should use this, rather than [`rand::random`] or the thread
+/// rng, to generate ids and make other random decisions. Each host's generator
+/// is seeded from the simulation's seed and the host's name, so values are
+/// reproducible for a given seed, whatever order hosts are registered in.
+///
+/// Must be called from within a Turmoil simulation.
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    with_rng(|rng| rng.gen())
+}
+
+/// Run `f` with the currently executing host's random number generator. See
+/// [`random`].
+///
+/// Must be called from within a Turmoil simulation.
+pub fn with_rng<R>(f: impl FnOnce(&mut dyn RngCore) -> R) -> R {
+    World::current(|world| f(&mut world.current_host_mut().rng))
+}
+
+/// The first backoff, before jitter.
+const BACKOFF_BASE: Duration = Duration::from_millis(100);
+
+/// The longest backoff, before jitter.
+const BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+/// How long to wait before retry `attempt`, counting from zero, using
+/// exponential backoff with jitter.
+///
+/// The backoff starts at 100ms and doubles with each attempt, up to 10s.
+/// Jitter then picks a duration between half of it and all of it, using the
+/// currently executing host's random number generator, see [`random`]. Retry
+/// timing is therefore reproducible for a given seed, while hosts retrying at
+/// the same time still spread out. This is the recommended backoff for host
+/// software, rather than one using real randomness.
+///
+/// Must be called from within a Turmoil simulation.
+pub fn backoff(attempt: u32) -> Duration {
+    let max = BACKOFF_BASE
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(BACKOFF_MAX);
+
+    with_rng(|rng| rng.gen_range(max / 2..=max))
+}
+
+/// Yields execution back to the simulation, resuming the current task once
+/// every other host has had a turn at the current tick.
+///
+/// Unlike [`tokio::task::yield_now`], which only yields to other tasks on the
+/// same host, this is reproducible across hosts: hosts are ticked in the order
+/// they were registered, and yielded tasks are resumed after all of them, again
+/// in registration order. If a task yields a second time while being resumed,
+/// it is resumed after the next step of the simulation.
+///
+/// Must be called from within a Turmoil simulation.
+pub async fn yield_now() {
+    let mut yielded = false;
+
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+
+        yielded = true;
+        World::current(|world| world.current_host_mut().wake_after_step(cx.waker()));
+
+        Poll::Pending
+    })
+    .await
+}
+
+/// Waits on multiple branches, returning when the first completes, with
+/// branches checked in the order they are written.
+///
+/// This is [`tokio::select!`] in `biased` mode. Without it, tokio picks the
+/// branch to poll first from a thread local generator that isn't seeded by the
+/// simulation, so when several branches are ready at once, which one wins can
+/// change between runs with the same seed. With it, the first ready branch
+/// always wins, as if every branch started with `biased;`.
+///
+/// The syntax is the same as [`tokio::select!`], without `biased;`. As earlier
+/// branches are preferred, a branch that is always ready can starve later
+/// ones. Where that matters, reorder the branches, or pick between them with
+/// [`random`], which keeps the choice reproducible.
+///
+/// [`tokio::join!`] needs no equivalent, as it polls its futures in a fixed
+/// order.
+///
+/// Must be called from within a Turmoil simulation.
+#[macro_export]
+macro_rules! select {
+    ($($branches:tt)*) => {
+        $crate::__private::tokio::select! {
+            biased;
+            $($branches)*
+        }
+    };
+}
+
+/// Waits until `duration` has elapsed.
+///
+/// This is [`tokio::time::sleep`], but the deadline is tracked by the
+/// simulation while the sleep is pending, which makes it visible through
+/// [`Sim::pending_timers`](crate::Sim::pending_timers) when debugging a host
+/// that isn't making progress. Timers created directly with tokio aren't
+/// visible there.
+///
+/// Must be called from within a Turmoil simulation.
+pub async fn sleep(duration: Duration) {
+    let _timer = Timer::new(duration);
+
+    tokio::time::sleep(duration).await
+}
+
+/// Requires `future` to complete before `duration` has elapsed.
+///
+/// This is [`tokio::time::timeout`], which already fires at the right
+/// simulated instant, e.g. when a read is stalled by a partition. As with
+/// [`sleep`], the deadline is tracked by the simulation while pending.
+///
+/// Must be called from within a Turmoil simulation.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    let _timer = Timer::new(duration);
+
+    tokio::time::timeout(duration, future).await
+}
+
+/// Removes a pending [`sleep`] or [`timeout`] from its host on drop.
+pub(crate) struct Timer {
+    addr: IpAddr,
+    id: u64,
+}
+
+impl Timer {
+    /// Track a deadline `duration` from now on the current host.
+    pub(crate) fn new(duration: Duration) -> Timer {
+        World::current(|world| {
+            let host = world.current_host_mut();
+            let id = host.next_timer_id;
+            host.next_timer_id += 1;
+            host.timers.insert(id, host.elapsed() + duration);
+
+            Timer {
+                addr: host.addr,
+                id,
+            }
+        })
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        World::current_if_set(|world| {
+            if let Some(host) = world.hosts.get_mut(&self.addr) {
+                host.timers.remove(&self.id);
+            }
+        })
+    }
+}
+
+/// A range of ephemeral ports, assigned in order and wrapping around.
+struct EphemeralPorts {
+    range: RangeInclusive<u16>,
+    next: u16,
+}
+
+impl EphemeralPorts {
+    fn new(range: RangeInclusive<u16>) -> EphemeralPorts {
+        assert!(!range.is_empty(), "empty ephemeral port range");
+
+        EphemeralPorts {
+            next: *range.start(),
+            range,
+        }
+    }
+
+    fn assign(
+        &mut self,
+        reserved: &IndexSet<u16>,
+        is_assigned: impl Fn(u16) -> bool,
+    ) -> io::Result<u16> {
+        // Check for existing binds to avoid port conflicts, giving up once
+        // every port has been tried
+        for _ in self.range.clone() {
+            let ret = self.next;
+
+            if self.next == *self.range.end() {
+                // re-load
+                self.next = *self.range.start();
+            } else {
+                // advance
+                self.next += 1;
+            }
+
+            if reserved.contains(&ret) || is_assigned(ret) {
+                continue;
+            }
+
+            return Ok(ret);
+        }
+
+        Err(Error::PortsExhausted.into())
+    }
 }
 
 /// Simulated UDP host software.
 pub(crate) struct Udp {
     /// Bound udp sockets
-    binds: IndexMap<SocketAddr, mpsc::Sender<(Datagram, SocketAddr)>>,
+    binds: IndexMap<SocketAddr, mpsc::Sender<Queued>>,
+
+    /// The peer of each connected socket, by local address.
+    peers: IndexMap<SocketAddr, SocketAddr>,
 
     /// UdpSocket channel capacity
     capacity: usize,
+
+    /// Peers that have migrated to a new address. Datagrams sent to a key are
+    /// rerouted to its value.
+    migrations: IndexMap<SocketAddr, SocketAddr>,
+
+    /// Bumped each time the host's sockets are discarded.
+    generation: u64,
 }
 
 impl Udp {
     fn new() -> Self {
         Self {
             binds: IndexMap::new(),
+            peers: IndexMap::new(),
             // TODO: Make capacity configurable
             capacity: 64,
+            migrations: IndexMap::new(),
+            generation: 0,
         }
     }
 
@@ -153,29 +605,94 @@ impl Udp {
 
         tracing::info!(target: TRACING_TARGET, ?addr, protocol = %"UDP", "Bind");
 
-        Ok(UdpSocket::new(addr, rx))
+        Ok(UdpSocket::new(addr, rx, self.generation))
     }
 
-    fn receive_from_network(&mut self, src: SocketAddr, dst: SocketAddr, datagram: Datagram) {
-        if let Some(s) = self.binds.get_mut(&dst) {
-            if let Err(err) = s.try_send((datagram, src)) {
-                // drop any packets that exceed the capacity
-                // TODO: ideally we should drop the oldest packets instead of new ones, but this would
-                //       require a different channel implementation.
-                match err {
-                    mpsc::error::TrySendError::Full((datagram, _)) => {
-                        tracing::trace!(target: TRACING_TARGET, ?dst, ?src, protocol = %Protocol::Udp(datagram), "Dropped (Full buffer)");
-                    }
-                    mpsc::error::TrySendError::Closed((datagram, _)) => {
-                        tracing::trace!(target: TRACING_TARGET, ?dst, ?src, protocol = %Protocol::Udp(datagram), "Dropped (Receiver closed)");
-                    }
+    /// Reroute datagrams sent to `old` to `new`, including those already
+    /// rerouted to `old`. Sockets connected to `old` are connected to `new`,
+    /// so they receive the peer's datagrams from its new address.
+    pub(crate) fn migrate(&mut self, old: SocketAddr, new: SocketAddr) {
+        for dst in self.migrations.values_mut().chain(self.peers.values_mut()) {
+            if *dst == old {
+                *dst = new;
+            }
+        }
+
+        self.migrations.insert(old, new);
+
+        tracing::info!(target: TRACING_TARGET, ?old, ?new, protocol = %"UDP", "Migrate");
+    }
+
+    /// The address datagrams sent to `dst` should be delivered to.
+    pub(crate) fn route(&self, dst: SocketAddr) -> SocketAddr {
+        self.migrations.get(&dst).copied().unwrap_or(dst)
+    }
+
+    /// Connect the socket bound to `local` to `peer`, so that it only receives
+    /// datagrams from the peer and is notified when the peer is unreachable.
+    pub(crate) fn connect(&mut self, local: SocketAddr, peer: SocketAddr) {
+        self.peers.insert(local, peer);
+    }
+
+    /// The peer the socket bound to `local` is connected to, if any.
+    pub(crate) fn peer(&self, local: SocketAddr) -> Option<SocketAddr> {
+        self.peers.get(&local).copied()
+    }
+
+    /// Returns an ICMP message to send back if no socket is bound to `dst`.
+    fn receive_from_network(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        datagram: Datagram,
+    ) -> Result<(), Protocol> {
+        let Some(s) = self.binds.get_mut(&dst) else {
+            return Err(Protocol::Icmp(Icmp::PortUnreachable));
+        };
+
+        // Connected sockets only receive from their peer
+        if self.peers.get(&dst).is_some_and(|peer| *peer != src) {
+            tracing::trace!(target: TRACING_TARGET, ?dst, ?src, protocol = %Protocol::Udp(datagram), "Dropped (Not connected peer)");
+            return Ok(());
+        }
+
+        if let Err(err) = s.try_send(Queued::Datagram(datagram, src)) {
+            // drop any packets that exceed the capacity
+            // TODO: ideally we should drop the oldest packets instead of new ones, but this would
+            //       require a different channel implementation.
+            match err {
+                mpsc::error::TrySendError::Full(queued) => {
+                    tracing::trace!(target: TRACING_TARGET, ?dst, ?src, protocol = %queued, "Dropped (Full buffer)");
+                }
+                mpsc::error::TrySendError::Closed(queued) => {
+                    tracing::trace!(target: TRACING_TARGET, ?dst, ?src, protocol = %queued, "Dropped (Receiver closed)");
                 }
             }
         }
+
+        Ok(())
+    }
+
+    /// Surface an ICMP error to the socket bound to `dst`, if it is connected
+    /// to `src`. Unconnected sockets ignore them.
+    fn receive_icmp(&mut self, src: SocketAddr, dst: SocketAddr, icmp: Icmp) {
+        if icmp != Icmp::PortUnreachable || self.peer(dst) != Some(src) {
+            return;
+        }
+
+        if let Some(s) = self.binds.get_mut(&dst) {
+            let _ = s.try_send(Queued::Refused);
+        }
+    }
+
+    /// Whether a socket bound in `generation` is still current.
+    pub(crate) fn is_current(&self, generation: u64) -> bool {
+        self.generation == generation
     }
 
     pub(crate) fn unbind(&mut self, addr: SocketAddr) {
         let exists = self.binds.remove(&addr);
+        self.peers.remove(&addr);
 
         assert!(exists.is_some(), "unknown bind {addr}");
 
@@ -183,6 +700,79 @@ impl Udp {
     }
 }
 
+/// Simulated raw socket host software.
+pub(crate) struct Raw {
+    /// Bound raw sockets, by protocol number
+    binds: IndexMap<u8, mpsc::Sender<(IpAddr, Frame)>>,
+
+    /// RawSocket channel capacity
+    capacity: usize,
+
+    /// Bumped each time the host's sockets are discarded.
+    generation: u64,
+}
+
+impl Raw {
+    fn new() -> Self {
+        Self {
+            binds: IndexMap::new(),
+            capacity: 64,
+            generation: 0,
+        }
+    }
+
+    /// Bind a socket to `addr`, whose port is the protocol number.
+    pub(crate) fn bind(&mut self, addr: SocketAddr) -> io::Result<RawSocket> {
+        let protocol = addr.port() as u8;
+
+        if self.binds.contains_key(&protocol) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, addr.to_string()));
+        }
+
+        let (tx, rx) = mpsc::channel(self.capacity);
+        self.binds.insert(protocol, tx);
+
+        tracing::info!(target: TRACING_TARGET, ?addr, protocol = %"RAW", "Bind");
+
+        Ok(RawSocket::new(addr, rx, self.generation))
+    }
+
+    /// Frames for a protocol without a bound socket are dropped, without a
+    /// reply.
+    fn receive_from_network(&mut self, src: SocketAddr, dst: SocketAddr, frame: Frame) {
+        let protocol = dst.port() as u8;
+
+        let Some(s) = self.binds.get_mut(&protocol) else {
+            tracing::trace!(target: TRACING_TARGET, ?dst, ?src, protocol = %frame, "Dropped (Not bound)");
+            return;
+        };
+
+        if let Err(err) = s.try_send((src.ip(), frame)) {
+            match err {
+                mpsc::error::TrySendError::Full((_, frame)) => {
+                    tracing::trace!(target: TRACING_TARGET, ?dst, ?src, protocol = %frame, "Dropped (Full buffer)");
+                }
+                mpsc::error::TrySendError::Closed((_, frame)) => {
+                    tracing::trace!(target: TRACING_TARGET, ?dst, ?src, protocol = %frame, "Dropped (Receiver closed)");
+                }
+            }
+        }
+    }
+
+    /// Whether a socket bound in `generation` is still current.
+    pub(crate) fn is_current(&self, generation: u64) -> bool {
+        self.generation == generation
+    }
+
+    pub(crate) fn unbind(&mut self, addr: SocketAddr) {
+        let exists = self.binds.remove(&(addr.port() as u8));
+
+        assert!(exists.is_some(), "unknown bind {addr}");
+
+        tracing::info!(target: TRACING_TARGET, ?addr, protocol = %"RAW", "Unbind");
+    }
+}
+
 pub(crate) struct Tcp {
     /// Bound server sockets
     binds: IndexMap<SocketAddr, ServerSocket>,
@@ -195,14 +785,39 @@ pub(crate) struct Tcp {
 
     /// TcpStream channel capacity
     socket_capacity: usize,
+
+    /// How long SYNs wait in the backlog before they can be accepted
+    pub(crate) accept_delay: Duration,
+
+    /// Whether new connections are refused while the host drains
+    pub(crate) draining: bool,
+
+    /// The most unacknowledged bytes a stream may have, if segments are
+    /// acknowledged. See [`TcpModel::AckGated`](crate::TcpModel::AckGated).
+    pub(crate) ack_window: Option<usize>,
+
+    /// Bumped each time the host's sockets are discarded.
+    generation: u64,
 }
 
+/// How long a segment goes unacknowledged before it is retransmitted.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+
 struct ServerSocket {
     /// Notify the TcpListener when SYNs are delivered
     notify: Arc<Notify>,
 
-    /// Pending connections for the TcpListener to accept
-    deque: VecDeque<(Syn, SocketAddr)>,
+    /// Pending connections for the TcpListener to accept, with the host
+    /// elapsed time at which they arrived
+    deque: VecDeque<(Syn, SocketAddr, Duration)>,
+
+    /// The most pending connections that may be half-open, i.e. whose client
+    /// will never complete the handshake
+    half_open_limit: usize,
+
+    /// SYNs dropped because too many connections were half-open. They are
+    /// kept, unanswered, so their clients wait rather than being refused.
+    stalled: Vec<Syn>,
 }
 
 struct StreamSocket {
@@ -211,14 +826,36 @@ struct StreamSocket {
     next_send_seq: u64,
     recv_seq: u64,
     sender: mpsc::Sender<SequencedSegment>,
+    /// Latency added to segments sent on this stream, on top of the link
+    /// latency.
+    extra_latency: Duration,
+    /// The most random latency added to each segment sent on this stream.
+    jitter: Duration,
+    /// Latency added to segments sent, and received, on this connection.
+    directional_latency: (Duration, Duration),
     /// A simple reference counter for tracking read/write half drops. Once 0, the
     /// socket may be removed from the host.
     ref_ct: usize,
+    /// Sent segments the peer has not acknowledged, by seq, with the host
+    /// elapsed time they were last sent. Only tracked when acknowledging.
+    unacked: IndexMap<u64, (SequencedSegment, Duration)>,
+    /// Data bytes in `unacked`.
+    unacked_bytes: usize,
+    /// A writer waiting for the send window to open.
+    window_waker: Option<Waker>,
+    /// An injected ICMP error, failing the next read or write.
+    icmp: Option<Icmp>,
+    /// A reader waiting for data, woken if an ICMP error is injected.
+    read_waker: Option<Waker>,
+    /// Whether the stream was accepted by a listener, rather than connected.
+    accepted: bool,
+    /// Host elapsed time when a segment was last sent or received.
+    last_activity: Duration,
 }
 
 /// Stripped down version of [`Segment`] for delivery out to the application
 /// layer.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) enum SequencedSegment {
     Data(Bytes),
     Fin,
@@ -234,7 +871,11 @@ impl Display for SequencedSegment {
 }
 
 impl StreamSocket {
-    fn new(local_addr: SocketAddr, capacity: usize) -> (Self, mpsc::Receiver<SequencedSegment>) {
+    fn new(
+        local_addr: SocketAddr,
+        capacity: usize,
+        now: Duration,
+    ) -> (Self, mpsc::Receiver<SequencedSegment>) {
         let (tx, rx) = mpsc::channel(capacity);
         let sock = Self {
             local_addr,
@@ -242,7 +883,17 @@ impl StreamSocket {
             next_send_seq: 1,
             recv_seq: 0,
             sender: tx,
+            extra_latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            directional_latency: (Duration::ZERO, Duration::ZERO),
             ref_ct: 2,
+            unacked: IndexMap::new(),
+            unacked_bytes: 0,
+            window_waker: None,
+            icmp: None,
+            read_waker: None,
+            accepted: false,
+            last_activity: now,
         };
 
         (sock, rx)
@@ -275,6 +926,32 @@ impl StreamSocket {
 
         Ok(())
     }
+
+    /// Remove the segments acknowledged by `seq`, opening the send window.
+    fn ack(&mut self, seq: u64) {
+        self.unacked.retain(|&sent, (segment, _)| {
+            if sent <= seq {
+                if let SequencedSegment::Data(data) = segment {
+                    self.unacked_bytes -= data.len();
+                }
+            }
+
+            sent > seq
+        });
+
+        if let Some(waker) = self.window_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for StreamSocket {
+    fn drop(&mut self) {
+        // Wake a blocked writer, which then sees the stream is gone
+        if let Some(waker) = self.window_waker.take() {
+            waker.wake();
+        }
+    }
 }
 
 impl Tcp {
@@ -285,19 +962,135 @@ impl Tcp {
             // TODO: Make capacity configurable
             server_socket_capacity: 64,
             socket_capacity: 64,
+            accept_delay: Duration::ZERO,
+            draining: false,
+            ack_window: None,
+            generation: 0,
+        }
+    }
+
+    /// How many bytes may be written to the stream for `pair` now, or pending
+    /// until the send window opens. Unlimited unless acknowledging.
+    pub(crate) fn poll_window(&mut self, pair: SocketPair, cx: &mut Context<'_>) -> Poll<usize> {
+        let (Some(window), Some(sock)) = (self.ack_window, self.sockets.get_mut(&pair)) else {
+            return Poll::Ready(usize::MAX);
+        };
+
+        match window.saturating_sub(sock.unacked_bytes) {
+            0 => {
+                sock.window_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            available => Poll::Ready(available),
+        }
+    }
+
+    /// Track a segment sent at host elapsed time `now` until it is
+    /// acknowledged, if acknowledging.
+    fn sent(&mut self, pair: SocketPair, seq: u64, segment: SequencedSegment, now: Duration) {
+        if let Some(sock) = self.sockets.get_mut(&pair) {
+            sock.last_activity = now;
+        }
+
+        if self.ack_window.is_none() {
+            return;
+        }
+
+        if let Some(sock) = self.sockets.get_mut(&pair) {
+            if let SequencedSegment::Data(data) = &segment {
+                sock.unacked_bytes += data.len();
+            }
+
+            sock.unacked.insert(seq, (segment, now));
+        }
+    }
+
+    /// Segments unacknowledged for the retransmit timeout as of host elapsed
+    /// time `now`, which are marked as sent again.
+    fn retransmits(&mut self, now: Duration) -> Vec<(SocketPair, Segment, Duration)> {
+        let mut due = vec![];
+
+        for (pair, sock) in &mut self.sockets {
+            for (&seq, (segment, sent_at)) in &mut sock.unacked {
+                if now < *sent_at + RETRANSMIT_TIMEOUT {
+                    continue;
+                }
+
+                *sent_at = now;
+
+                let segment = match segment {
+                    SequencedSegment::Data(data) => Segment::Data(seq, data.clone()),
+                    SequencedSegment::Fin => Segment::Fin(seq),
+                };
+                due.push((*pair, segment, sock.extra_latency));
+            }
+        }
+
+        due
+    }
+
+    /// Fail the next read or write on each stream to `remote` with `icmp`,
+    /// waking a blocked reader or writer.
+    pub(crate) fn inject_icmp(&mut self, remote: IpAddr, icmp: Icmp) {
+        for (pair, sock) in &mut self.sockets {
+            if pair.remote.ip() != remote {
+                continue;
+            }
+
+            sock.icmp = Some(icmp);
+
+            for waker in [sock.read_waker.take(), sock.window_waker.take()]
+                .into_iter()
+                .flatten()
+            {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Take the ICMP error injected into the stream for `pair`, or register
+    /// the reader to be woken when one is.
+    pub(crate) fn poll_icmp(&mut self, pair: SocketPair, cx: &mut Context<'_>) -> Poll<Icmp> {
+        let Some(sock) = self.sockets.get_mut(&pair) else {
+            return Poll::Pending;
+        };
+
+        match sock.icmp.take() {
+            Some(icmp) => Poll::Ready(icmp),
+            None => {
+                sock.read_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
         }
     }
 
+    /// Take the ICMP error injected into the stream for `pair`, if any.
+    pub(crate) fn take_icmp(&mut self, pair: SocketPair) -> Option<Icmp> {
+        self.sockets.get_mut(&pair)?.icmp.take()
+    }
+
     fn is_port_assigned(&self, port: u16) -> bool {
         self.binds.keys().any(|a| a.port() == port)
             || self.sockets.keys().any(|a| a.local.port() == port)
     }
 
+    /// Whether a listener bound in `generation` is still current.
+    pub(crate) fn is_current(&self, generation: u64) -> bool {
+        self.generation == generation
+    }
+
+    /// Whether a listener is bound to `addr`.
+    pub(crate) fn is_bound(&self, addr: SocketAddr) -> bool {
+        self.binds.contains_key(&addr)
+    }
+
     pub(crate) fn bind(&mut self, addr: SocketAddr) -> io::Result<TcpListener> {
         let notify = Arc::new(Notify::new());
         let sock = ServerSocket {
             notify: notify.clone(),
             deque: VecDeque::new(),
+            half_open_limit: usize::MAX,
+            stalled: vec![],
         };
 
         if self.binds.insert(addr, sock).is_some() {
@@ -306,11 +1099,16 @@ impl Tcp {
 
         tracing::info!(target: TRACING_TARGET, ?addr, protocol = %"TCP", "Bind");
 
-        Ok(TcpListener::new(addr, notify))
+        Ok(TcpListener::new(addr, notify, self.generation))
     }
 
-    pub(crate) fn new_stream(&mut self, pair: SocketPair) -> mpsc::Receiver<SequencedSegment> {
-        let (sock, rx) = StreamSocket::new(pair.local, self.socket_capacity);
+    /// Add the stream socket for `pair`, created at host elapsed time `now`.
+    pub(crate) fn new_stream(
+        &mut self,
+        pair: SocketPair,
+        now: Duration,
+    ) -> mpsc::Receiver<SequencedSegment> {
+        let (sock, rx) = StreamSocket::new(pair.local, self.socket_capacity, now);
 
         let exists = self.sockets.insert(pair, sock);
 
@@ -319,8 +1117,45 @@ impl Tcp {
         rx
     }
 
-    pub(crate) fn accept(&mut self, addr: SocketAddr) -> Option<(Syn, SocketAddr)> {
-        self.binds[&addr].deque.pop_front()
+    /// Add the stream socket for a connection accepted by a listener.
+    pub(crate) fn accept_stream(
+        &mut self,
+        pair: SocketPair,
+        now: Duration,
+    ) -> mpsc::Receiver<SequencedSegment> {
+        let rx = self.new_stream(pair, now);
+        self.sockets[&pair].accepted = true;
+        rx
+    }
+
+    /// The number of accepted streams that have not been dropped or reset.
+    pub(crate) fn accepted_count(&self) -> usize {
+        self.sockets.values().filter(|sock| sock.accepted).count()
+    }
+
+    pub(crate) fn set_half_open_limit(&mut self, addr: SocketAddr, limit: usize) {
+        self.binds[&addr].half_open_limit = limit;
+    }
+
+    /// Take the next pending connection for `addr`, if the accept delay has
+    /// passed by host elapsed time `now`.
+    ///
+    /// Returns how long until the next pending connection can be accepted if
+    /// it's still delayed.
+    pub(crate) fn accept(
+        &mut self,
+        addr: SocketAddr,
+        now: Duration,
+    ) -> Option<Result<(Syn, SocketAddr), Duration>> {
+        let deque = &mut self.binds[&addr].deque;
+        let ready_at = deque.front()?.2 + self.accept_delay;
+
+        if ready_at > now {
+            return Some(Err(ready_at - now));
+        }
+
+        let (syn, origin, _) = deque.pop_front().unwrap();
+        Some(Ok((syn, origin)))
     }
 
     // Ideally, we could "write through" the tcp software, but this is necessary
@@ -330,43 +1165,192 @@ impl Tcp {
         Some(sock.assign_seq())
     }
 
+    pub(crate) fn set_extra_latency(&mut self, pair: SocketPair, value: Duration) {
+        if let Some(sock) = self.sockets.get_mut(&pair) {
+            sock.extra_latency = value;
+        }
+    }
+
+    pub(crate) fn extra_latency(&self, pair: SocketPair) -> Duration {
+        self.sockets
+            .get(&pair)
+            .map_or(Duration::ZERO, |sock| sock.extra_latency)
+    }
+
+    pub(crate) fn set_jitter(&mut self, pair: SocketPair, value: Duration) {
+        if let Some(sock) = self.sockets.get_mut(&pair) {
+            sock.jitter = value;
+        }
+    }
+
+    pub(crate) fn jitter(&self, pair: SocketPair) -> Duration {
+        self.sockets
+            .get(&pair)
+            .map_or(Duration::ZERO, |sock| sock.jitter)
+    }
+
+    pub(crate) fn set_directional_latency(
+        &mut self,
+        pair: SocketPair,
+        outbound: Duration,
+        inbound: Duration,
+    ) {
+        if let Some(sock) = self.sockets.get_mut(&pair) {
+            sock.directional_latency = (outbound, inbound);
+        }
+    }
+
+    /// The (outbound, inbound) latency set on the stream `pair`.
+    pub(crate) fn directional_latency(&self, pair: SocketPair) -> (Duration, Duration) {
+        self.sockets
+            .get(&pair)
+            .map_or((Duration::ZERO, Duration::ZERO), |sock| {
+                sock.directional_latency
+            })
+    }
+
     fn receive_from_network(
         &mut self,
         src: SocketAddr,
         dst: SocketAddr,
         segment: Segment,
+        now: Duration,
     ) -> Result<(), Protocol> {
         match segment {
             Segment::Syn(syn) => {
+                // A draining host accepts no new connections
+                if self.draining {
+                    return Ok(());
+                }
+
+                // A retransmitted SYN for an accepted connection is dropped
+                if self.sockets.contains_key(&SocketPair::new(dst, src)) {
+                    tracing::trace!(target: TRACING_TARGET, ?src, ?dst, protocol = %"TCP SYN", "Drop (Duplicate)");
+
+                    return Ok(());
+                }
+
                 // If bound, queue the syn; else we drop the syn triggering
                 // connection refused on the client.
                 if let Some(b) = self.binds.get_mut(&dst) {
+                    // A retransmitted SYN for a queued connection is dropped
+                    if b.deque
+                        .iter()
+                        .any(|(syn, origin, _)| *origin == src && !syn.ack.is_closed())
+                    {
+                        tracing::trace!(target: TRACING_TARGET, ?src, ?dst, protocol = %"TCP SYN", "Drop (Duplicate)");
+
+                        return Ok(());
+                    }
+
+                    // SYNs whose client has gone, e.g. from a flood, can never
+                    // complete the handshake
+                    let half_open = b.deque.iter().filter(|(syn, ..)| syn.ack.is_closed());
+
+                    // Past the half-open limit, the syn is dropped and the client waits
+                    if half_open.count() >= b.half_open_limit {
+                        tracing::trace!(target: TRACING_TARGET, ?src, ?dst, protocol = %"TCP SYN", "Drop (SYN_RECV full)");
+
+                        b.stalled.retain(|syn| !syn.ack.is_closed());
+                        b.stalled.push(syn);
+
+                        return Ok(());
+                    }
+
+                    // A full backlog drops the syn, also refusing the connection
                     if b.deque.len() == self.server_socket_capacity {
-                        todo!("{} server socket buffer full", dst);
+                        tracing::trace!(target: TRACING_TARGET, ?src, ?dst, protocol = %"TCP SYN", "Drop (Backlog full)");
+
+                        return Ok(());
                     }
 
-                    b.deque.push_back((syn, src));
+                    b.deque.push_back((syn, src, now));
                     b.notify.notify_one();
                 }
             }
-            Segment::Data(seq, data) => match self.sockets.get_mut(&SocketPair::new(dst, src)) {
-                Some(sock) => sock.buffer(seq, SequencedSegment::Data(data))?,
-                None => return Err(Protocol::Tcp(Segment::Rst)),
-            },
-            Segment::Fin(seq) => match self.sockets.get_mut(&SocketPair::new(dst, src)) {
-                Some(sock) => sock.buffer(seq, SequencedSegment::Fin)?,
-                None => return Err(Protocol::Tcp(Segment::Rst)),
-            },
+            Segment::Data(seq, data) => {
+                return self.receive_sequenced(src, dst, seq, SequencedSegment::Data(data), now)
+            }
+            Segment::Fin(seq) => {
+                return self.receive_sequenced(src, dst, seq, SequencedSegment::Fin, now)
+            }
             Segment::Rst => {
                 if self.sockets.get(&SocketPair::new(dst, src)).is_some() {
                     self.sockets.remove(&SocketPair::new(dst, src)).unwrap();
                 }
             }
+            Segment::Ack(seq) => {
+                if let Some(sock) = self.sockets.get_mut(&SocketPair::new(dst, src)) {
+                    sock.ack(seq);
+                }
+            }
         };
 
         Ok(())
     }
 
+    fn receive_sequenced(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        seq: u64,
+        segment: SequencedSegment,
+        now: Duration,
+    ) -> Result<(), Protocol> {
+        let Some(sock) = self.sockets.get_mut(&SocketPair::new(dst, src)) else {
+            return Err(Protocol::Tcp(Segment::Rst));
+        };
+
+        sock.last_activity = now;
+
+        if self.ack_window.is_none() {
+            return sock.buffer(seq, segment);
+        }
+
+        // Retransmissions may deliver a segment more than once
+        if seq > sock.recv_seq && !sock.buf.contains_key(&seq) {
+            sock.buffer(seq, segment)?;
+        }
+
+        // Acknowledge everything received in order so far
+        Err(Protocol::Tcp(Segment::Ack(sock.recv_seq)))
+    }
+
+    /// Whether any stream has segments waiting to be acknowledged.
+    fn has_unacked(&self) -> bool {
+        self.sockets.values().any(|sock| !sock.unacked.is_empty())
+    }
+
+    /// The pairs of all active stream sockets.
+    pub(crate) fn stream_pairs(&self) -> Vec<SocketPair> {
+        self.sockets.keys().copied().collect()
+    }
+
+    /// The streams that have had no traffic for `timeout` as of host elapsed
+    /// time `now`.
+    fn idle_streams(&self, timeout: Duration, now: Duration) -> Vec<SocketPair> {
+        self.sockets
+            .iter()
+            .filter(|(_, sock)| now.saturating_sub(sock.last_activity) >= timeout)
+            .map(|(pair, _)| *pair)
+            .collect()
+    }
+
+    /// Close the stream for `pair`, e.g. when it has been idle: its reader
+    /// sees EOF after what has already arrived, and its writer fails.
+    pub(crate) fn reap_stream(&mut self, pair: SocketPair) {
+        if let Some(sock) = self.sockets.remove(&pair) {
+            let _ = sock.sender.try_send(SequencedSegment::Fin);
+
+            tracing::trace!(target: TRACING_TARGET, src = ?pair.local, dst = ?pair.remote, protocol = %"TCP", "Reaped (Idle)");
+        }
+    }
+
+    /// Remove the stream socket for `pair`, e.g. when it is reset.
+    pub(crate) fn remove_stream(&mut self, pair: SocketPair) {
+        self.sockets.remove(&pair);
+    }
+
     pub(crate) fn close_stream_half(&mut self, pair: SocketPair) {
         // Receiving a RST removes the socket, so it's possible that has occured
         // when halfs of the stream drop.
@@ -390,20 +1374,50 @@ impl Tcp {
 
 #[cfg(test)]
 mod test {
-    use crate::{Host, Result};
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::{config, Host, Result};
 
     #[test]
     fn recycle_ports() -> Result {
-        let mut host = Host::new(std::net::Ipv4Addr::UNSPECIFIED.into());
+        let mut host = Host::new(
+            std::net::Ipv4Addr::UNSPECIFIED.into(),
+            SmallRng::seed_from_u64(0),
+            &config::Ports::default(),
+        );
 
         host.udp.bind((host.addr, 65534).into())?;
         host.udp.bind((host.addr, 65535).into())?;
 
         for _ in 1024..65534 {
-            host.assign_ephemeral_port();
+            host.assign_tcp_port()?;
         }
 
-        assert_eq!(1024, host.assign_ephemeral_port());
+        assert_eq!(1024, host.assign_tcp_port()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ephemeral_port_config() -> Result {
+        let ports = config::Ports {
+            reserved: [2001].into_iter().collect(),
+            tcp: 2000..=2002,
+            udp: 3000..=3001,
+        };
+        let mut host = Host::new(
+            std::net::Ipv4Addr::UNSPECIFIED.into(),
+            SmallRng::seed_from_u64(0),
+            &ports,
+        );
+
+        assert_eq!(2000, host.assign_tcp_port()?);
+        assert_eq!(2002, host.assign_tcp_port()?);
+        assert_eq!(3000, host.assign_udp_port()?);
+        assert_eq!(3001, host.assign_udp_port()?);
+
+        // Reserved ports can still be bound explicitly
+        host.tcp.bind((host.addr, 2001).into())?;
 
         Ok(())
     }
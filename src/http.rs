@@ -0,0 +1,122 @@
+//! A minimal HTTP/1.1 echo server, for tests that need something to talk to.
+
+use crate::net::{TcpListener, TcpStream};
+use crate::Result;
+
+use bytes::{Buf, BytesMut};
+use std::net::{IpAddr, Ipv4Addr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// The most headers a request may have.
+const MAX_HEADERS: usize = 64;
+
+/// Accept connections on `port`, serving each on its own task.
+pub(crate) async fn echo(port: u16) -> Result {
+    let listener = TcpListener::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)).await?;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+
+        tokio::task::spawn_local(async move {
+            // The connection is closed on drop, including after errors
+            let _ = serve(stream).await;
+        });
+    }
+}
+
+/// A parsed request head.
+struct Head {
+    /// The length of the head, in bytes.
+    len: usize,
+
+    /// The length of the body, from `Content-Length`.
+    content_length: usize,
+
+    /// Whether the connection is closed after the response.
+    close: bool,
+}
+
+/// Respond to each request on `stream` with its body, until the client
+/// closes the connection or asks for it to be closed.
+async fn serve(mut stream: TcpStream) -> Result {
+    let mut buf = BytesMut::new();
+
+    loop {
+        let head = loop {
+            match parse(&buf) {
+                Ok(Some(head)) => break head,
+                Ok(None) => {}
+                Err(status) => return respond(&mut stream, status, &[], true).await,
+            }
+
+            if stream.read_buf(&mut buf).await? == 0 {
+                return Ok(());
+            }
+        };
+
+        while buf.len() < head.len + head.content_length {
+            if stream.read_buf(&mut buf).await? == 0 {
+                return Ok(());
+            }
+        }
+
+        buf.advance(head.len);
+        let body = buf.split_to(head.content_length);
+
+        respond(&mut stream, "200 OK", &body, head.close).await?;
+
+        if head.close {
+            return Ok(());
+        }
+    }
+}
+
+/// Parse a request head from `buf`, returning `None` if it's incomplete, or
+/// the status to respond with if the request can't be served.
+fn parse(buf: &[u8]) -> std::result::Result<Option<Head>, &'static str> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut req = httparse::Request::new(&mut headers);
+
+    let len = match req.parse(buf) {
+        Ok(httparse::Status::Complete(len)) => len,
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Err(_) => return Err("400 Bad Request"),
+    };
+
+    // HTTP/1.0 closes by default, HTTP/1.1 keeps the connection alive
+    let mut close = req.version == Some(0);
+    let mut content_length = 0;
+
+    for header in req.headers.iter() {
+        if header.name.eq_ignore_ascii_case("content-length") {
+            content_length = std::str::from_utf8(header.value)
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or("400 Bad Request")?;
+        } else if header.name.eq_ignore_ascii_case("transfer-encoding") {
+            return Err("501 Not Implemented");
+        } else if header.name.eq_ignore_ascii_case("connection") {
+            close = header.value.eq_ignore_ascii_case(b"close");
+        }
+    }
+
+    Ok(Some(Head {
+        len,
+        content_length,
+        close,
+    }))
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &[u8], close: bool) -> Result {
+    let connection = if close { "close" } else { "keep-alive" };
+    let mut res = format!(
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: {connection}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    res.extend_from_slice(body);
+
+    stream.write_all(&res).await?;
+
+    Ok(())
+}
@@ -47,6 +47,18 @@
 //! Turmoil is not yet oppinionated on how to structure your application code to
 //! swap in simulated types under test. More on this coming soon...
 //!
+//! # Randomness
+//!
+//! Host software should generate random values, such as request ids, with
+//! [`random`] or [`with_rng`] rather than the thread rng. Each host has a
+//! generator seeded from the simulation's seed and the host's name, which keeps
+//! runs reproducible.
+//! Retry loops should likewise wait for [`backoff`], a jittered exponential
+//! backoff drawn from the same generator.
+//!
+//! For the same reason, use [`select!`] rather than [`tokio::select!`] when
+//! several branches may be ready at once.
+//!
 //! # Network Manipulation
 //!
 //! The simulation has the following network manipulation capabilities:
@@ -67,11 +79,15 @@
 //! # Feature flags
 //!
 //! * `regex`: Enables regex host resolution through `ToIpAddrs`
+//! * `storage`: Enables simulated per host storage in `turmoil::storage`
+//! * `serde`: Enables saving and loading `Builder` configuration files
+//! * `http`: Enables a built-in HTTP/1.1 echo server, see `Sim::add_http_echo`
 //!
 //! ## tokio_unstable
 //!
 //! Turmoil uses [unhandled_panic] to forward host panics as test failures. See
-//! [unstable features] to opt in.
+//! [unstable features] to opt in. Running a simulation with [`run`] returns
+//! the panic, and the host it happened on, as a [`TestFailure`].
 //!
 //! [unhandled_panic]:
 //!     https://docs.rs/tokio/latest/tokio/runtime/struct.Builder.html#method.unhandled_panic
@@ -86,39 +102,72 @@ use std::net::IpAddr;
 
 pub use builder::Builder;
 
+mod capture;
+pub use capture::{assert_deterministic, Delivery, DeliveryKind, EventMatcher, Tap, Tapped};
+
 mod config;
 use config::Config;
+pub use config::{BandwidthSharing, LatencyTrace, SameTickOrder, TcpModel};
+
+mod decisions;
+pub use decisions::{Decision, DecisionLog};
 
 mod dns;
 use dns::Dns;
 pub use dns::{ToIpAddr, ToIpAddrs, ToSocketAddrs};
 
+mod failure;
+pub use failure::{run, TestFailure};
+
 mod envelope;
 use envelope::Envelope;
-pub use envelope::{Datagram, Protocol, Segment};
+pub use envelope::{Datagram, Frame, Icmp, Protocol, Segment};
 
 mod error;
-pub use error::Result;
+pub use error::{Error, Result};
+
+mod handle;
+pub use handle::SimHandle;
+
+#[cfg(feature = "http")]
+mod http;
 
 mod host;
-pub use host::elapsed;
 use host::Host;
+pub use host::{backoff, elapsed, random, sleep, timeout, with_rng, yield_now};
 
 pub mod net;
 
+mod network;
+pub use network::Network;
+
+mod report;
+pub use report::{HostReport, LinkReport, LinkState, TopologyReport};
+
 mod rt;
 use rt::Rt;
 
 mod sim;
-pub use sim::Sim;
+pub use sim::{FaultAction, HostState, Sim};
+
+#[cfg(feature = "storage")]
+pub mod storage;
 
 mod top;
 use top::Topology;
 pub use top::{LinkIter, LinksIter, SentRef};
 
+mod trace;
+pub use trace::Trace;
+
 mod world;
 use world::World;
 
+#[doc(hidden)]
+pub mod __private {
+    pub use tokio;
+}
+
 const TRACING_TARGET: &str = "turmoil";
 
 /// Utility method for performing a function on all hosts in `a` against all
@@ -133,9 +182,15 @@ pub(crate) fn for_pairs(a: &Vec<IpAddr>, b: &Vec<IpAddr>, mut f: impl FnMut(IpAd
 
 /// Lookup an IP address by host name.
 ///
+/// A name set up with [`Sim::geo_dns`] resolves to the replica closest to the
+/// current host.
+///
 /// Must be called from within a Turmoil simulation.
 pub fn lookup(addr: impl ToIpAddr) -> IpAddr {
-    World::current(|world| world.lookup(addr))
+    World::current(|world| {
+        let addr = world.lookup(addr);
+        world.resolve(addr)
+    })
 }
 
 /// Lookup an IP address by host name. Use regex to match a number of hosts.
@@ -145,6 +200,17 @@ pub fn lookup_many(addr: impl ToIpAddrs) -> Vec<IpAddr> {
     World::current(|world| world.lookup_many(addr))
 }
 
+/// The address of the registered host `name`, or `None` if no such host
+/// has been registered.
+///
+/// Unlike [`lookup`], this never assigns an address. Use [`Sim::host_addr`]
+/// from outside of a host.
+///
+/// Must be called from within a Turmoil simulation.
+pub fn host_addr(name: &str) -> Option<IpAddr> {
+    World::current(|world| world.host_addr(name))
+}
+
 /// Hold messages between two hosts, or sets of hosts, until [`release`] is
 /// called.
 ///
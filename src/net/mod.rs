@@ -1,4 +1,5 @@
-//! This module contains the simulated TCP/UDP networking types.
+//! This module contains the simulated TCP/UDP networking types, and raw
+//! sockets for protocols of your own.
 //!
 //! They mirror [tokio::net](https://docs.rs/tokio/latest/tokio/net/) to provide
 //! a high fidelity implementation.
@@ -6,9 +7,16 @@
 use std::net::SocketAddr;
 
 pub mod tcp;
-pub use tcp::{listener::TcpListener, stream::TcpStream};
+pub use tcp::{
+    listener::TcpListener,
+    stream::{StreamStats, TcpStream},
+};
+
+mod raw;
+pub use raw::RawSocket;
 
 mod udp;
+pub(crate) use udp::Queued;
 pub use udp::UdpSocket;
 
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
@@ -0,0 +1,121 @@
+use bytes::Bytes;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::{
+    envelope::{Frame, Protocol},
+    Error, ToIpAddr, World, TRACING_TARGET,
+};
+
+use std::{
+    io::{self, Result},
+    net::{IpAddr, SocketAddr},
+};
+
+/// A simulated raw socket, for building custom transport protocols on top of
+/// the simulated links.
+///
+/// A raw socket sends and receives opaque frames of a single protocol, named
+/// by a number as in the IP header, between hosts. Frames are subject to
+/// everything a link does to UDP datagrams: latency, loss, partitions, holds
+/// and bandwidth limits. Like datagrams, frames may be lost, but are never
+/// reordered on a link. Frames for a protocol without a bound socket are
+/// dropped without a reply.
+///
+/// All methods must be called from a host within a Turmoil simulation.
+pub struct RawSocket {
+    local_addr: SocketAddr,
+    rx: Mutex<mpsc::Receiver<(IpAddr, Frame)>>,
+
+    /// The host's socket generation when bound. See [`Sim::reset_network`].
+    ///
+    /// [`Sim::reset_network`]: crate::Sim::reset_network
+    generation: u64,
+}
+
+impl RawSocket {
+    pub(crate) fn new(
+        local_addr: SocketAddr,
+        rx: mpsc::Receiver<(IpAddr, Frame)>,
+        generation: u64,
+    ) -> Self {
+        Self {
+            local_addr,
+            rx: Mutex::new(rx),
+            generation,
+        }
+    }
+
+    /// Create a new simulated raw socket, receiving frames of `protocol` sent
+    /// to the current host.
+    ///
+    /// Fails with [`io::ErrorKind::AddrInUse`] if a socket is already bound to
+    /// the protocol on the host.
+    ///
+    /// [`io::ErrorKind::AddrInUse`]: std::io::ErrorKind::AddrInUse
+    pub async fn bind(protocol: u8) -> Result<RawSocket> {
+        World::current(|world| {
+            let host = world.current_host_mut();
+            let addr = SocketAddr::new(host.addr, protocol.into());
+
+            host.raw.bind(addr)
+        })
+    }
+
+    /// Sends `buf` as a frame to the socket bound to this socket's protocol on
+    /// `target`, which may be a hostname. On success, returns the number of
+    /// bytes sent.
+    ///
+    /// Fails with [`Error::HostNotFound`] if no host owns the address.
+    pub async fn send_to(&self, buf: &[u8], target: impl ToIpAddr) -> Result<usize> {
+        World::current(|world| {
+            let dst = world.lookup(target);
+            let dst = world.resolve(dst);
+
+            if !world.hosts.contains_key(&dst) {
+                return Err(Error::HostNotFound(dst).into());
+            }
+
+            world.send_message(
+                self.local_addr,
+                SocketAddr::new(dst, self.local_addr.port()),
+                Protocol::Raw(Frame(Bytes::copy_from_slice(buf))),
+            );
+
+            Ok(buf.len())
+        })
+    }
+
+    /// Receives a single frame on the socket, returning the address of the
+    /// host that sent it and its contents.
+    pub async fn recv_from(&self) -> Result<(IpAddr, Bytes)> {
+        // The sender is only dropped when the host's network is reset
+        let Some((origin, frame)) = self.rx.lock().await.recv().await else {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "socket was reset",
+            ));
+        };
+
+        tracing::trace!(target: TRACING_TARGET, dst = ?self.local_addr, src = ?origin, protocol = %frame, "Recv");
+
+        Ok((origin, frame.0))
+    }
+
+    /// Returns the protocol number this socket is bound to.
+    pub fn protocol(&self) -> u8 {
+        self.local_addr.port() as u8
+    }
+}
+
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        World::current_if_set(|world| {
+            let raw = &mut world.current_host_mut().raw;
+
+            // The host's network was reset since, which unbound the socket
+            if raw.is_current(self.generation) {
+                raw.unbind(self.local_addr);
+            }
+        });
+    }
+}
@@ -1,43 +1,76 @@
-use std::{io::Result, net::SocketAddr, sync::Arc};
+use std::{
+    io::{self, Result},
+    net::SocketAddr,
+    sync::Arc,
+};
 
 use tokio::sync::Notify;
 
 use crate::{
     net::{SocketPair, TcpStream},
     world::World,
-    ToSocketAddrs, TRACING_TARGET,
+    Error, ToSocketAddrs, TRACING_TARGET,
 };
 
 /// A simulated TCP socket server, listening for connections.
 ///
+/// Dropping the listener only stops it accepting connections. Streams that
+/// were already accepted are unaffected, while pending and new connections
+/// are refused.
+///
+/// Drop unbinds the listener only when it runs within the simulation, e.g. on
+/// a host or while the [`Sim`] is stepping. Dropped anywhere else, the bind is
+/// left in place and the port stays in use. Use [`TcpListener::close`] to
+/// unbind explicitly and find out if it failed.
+///
+/// [`Sim`]: crate::Sim
+///
 /// All methods must be called from a host within a Turmoil simulation.
 pub struct TcpListener {
     local_addr: SocketAddr,
     notify: Arc<Notify>,
+
+    /// Set once [`TcpListener::close`] has run, so drop doesn't unbind again.
+    closed: bool,
+
+    /// The host's socket generation when bound. See [`Sim::reset_network`].
+    ///
+    /// [`Sim::reset_network`]: crate::Sim::reset_network
+    generation: u64,
 }
 
 impl TcpListener {
-    pub(crate) fn new(local_addr: SocketAddr, notify: Arc<Notify>) -> Self {
-        Self { local_addr, notify }
+    pub(crate) fn new(local_addr: SocketAddr, notify: Arc<Notify>, generation: u64) -> Self {
+        Self {
+            local_addr,
+            notify,
+            closed: false,
+            generation,
+        }
     }
 
     /// Creates a new TcpListener, which will be bound to the specified address.
     ///
     /// The returned listener is ready for accepting connections.
     ///
-    /// Only 0.0.0.0 is currently supported.
+    /// Only 0.0.0.0 is currently supported, other addresses fail with
+    /// [`Error::UnsupportedBind`].
     pub async fn bind<A: ToSocketAddrs>(addr: A) -> Result<TcpListener> {
         World::current(|world| {
             let mut addr = addr.to_socket_addr(&world.dns);
             let host = world.current_host_mut();
 
             if !addr.ip().is_unspecified() {
-                panic!("{addr} is not supported");
+                return Err(Error::UnsupportedBind(addr).into());
             }
 
             // Unspecified -> host's IP
             addr.set_ip(host.addr);
 
+            if addr.port() == 0 {
+                addr.set_port(host.assign_tcp_port()?);
+            }
+
             host.tcp.bind(addr)
         })
     }
@@ -47,45 +80,129 @@ impl TcpListener {
     /// This function will yield once a new TCP connection is established. When
     /// established, the corresponding [`TcpStream`] and the remote peer’s
     /// address will be returned.
+    ///
+    /// Fails with [`io::ErrorKind::ConnectionAborted`] once the host's network
+    /// has been reset, see [`Sim::reset_network`].
+    ///
+    /// [`io::ErrorKind::ConnectionAborted`]: std::io::ErrorKind::ConnectionAborted
+    /// [`Sim::reset_network`]: crate::Sim::reset_network
     pub async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
         loop {
+            if !World::current(|world| world.current_host_mut().tcp.is_current(self.generation)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    format!("{} was reset", self.local_addr),
+                ));
+            }
+
             let maybe_accept = World::current(|world| {
                 let host = world.current_host_mut();
-                let (syn, origin) = host.tcp.accept(self.local_addr)?;
+                let now = host.elapsed();
 
-                tracing::trace!(target: TRACING_TARGET, dst = ?origin, src = ?self.local_addr, protocol = %"TCP SYN", "Recv");
+                loop {
+                    let (syn, origin) = match host.tcp.accept(self.local_addr, now)? {
+                        Ok(pending) => pending,
+                        Err(delay) => return Some(Err(delay)),
+                    };
 
-                // Send SYN-ACK -> origin. If Ok we proceed (acts as the ACK),
-                // else we return early to avoid host mutations.
-                let ack = syn.ack.send(());
-                tracing::trace!(target: TRACING_TARGET, src = ?self.local_addr, dst = ?origin, protocol = %"TCP SYN-ACK", "Send");
+                    tracing::trace!(target: TRACING_TARGET, dst = ?origin, src = ?self.local_addr, protocol = %"TCP SYN", "Recv");
 
-                if ack.is_err() {
-                    return None;
-                }
+                    // Send SYN-ACK -> origin. If Ok we proceed (acts as the
+                    // ACK), else the client gave up, so we move on to the next
+                    // pending connection to avoid host mutations.
+                    let ack = syn.ack.send(());
+                    tracing::trace!(target: TRACING_TARGET, src = ?self.local_addr, dst = ?origin, protocol = %"TCP SYN-ACK", "Send");
+
+                    if ack.is_err() {
+                        continue;
+                    }
 
-                let pair = SocketPair::new(self.local_addr, origin);
-                let rx = host.tcp.new_stream(pair);
+                    let pair = SocketPair::new(self.local_addr, origin);
+                    let now = host.elapsed();
+                    let rx = host.tcp.accept_stream(pair, now);
 
-                Some((TcpStream::new(pair, rx), origin))
+                    return Some(Ok((TcpStream::new(pair, rx), origin)));
+                }
             });
 
-            if let Some(accepted) = maybe_accept {
-                return Ok(accepted);
+            match maybe_accept {
+                Some(Ok(accepted)) => return Ok(accepted),
+                // The next connection is still delayed in the backlog
+                Some(Err(delay)) => crate::sleep(delay).await,
+                None => self.notify.notified().await,
             }
-
-            self.notify.notified().await;
         }
     }
 
+    /// Sets the most pending connections that may be half-open, modeling the
+    /// SYN_RECV queue.
+    ///
+    /// A connection is half-open if its client will never complete the
+    /// handshake, e.g. when the SYN is from a [`Sim::syn_flood`]. Once the
+    /// limit is reached, further SYNs are dropped without a reply, so connects
+    /// stay pending until the client gives up, rather than being refused as
+    /// they are when the backlog is full. Half-open connections are cleared as
+    /// the listener accepts. The default is no limit.
+    ///
+    /// [`Sim::syn_flood`]: crate::Sim::syn_flood
+    pub fn set_half_open_limit(&self, limit: usize) {
+        World::current(|world| {
+            world
+                .current_host_mut()
+                .tcp
+                .set_half_open_limit(self.local_addr, limit)
+        })
+    }
+
     /// Returns the local address that this listener is bound to.
     pub fn local_addr(&self) -> Result<SocketAddr> {
         Ok(self.local_addr)
     }
+
+    /// Closes the listener, unbinding its address so it can be bound again.
+    ///
+    /// Unlike drop, this fails rather than leaving the bind in place when
+    /// called outside of the simulation, with [`Error::NoSimulation`]. It also
+    /// fails with [`io::ErrorKind::NotConnected`] if the listener is no longer
+    /// bound, e.g. because its host was crashed.
+    ///
+    /// [`io::ErrorKind::NotConnected`]: std::io::ErrorKind::NotConnected
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+
+        let mut res = Err(Error::NoSimulation.into());
+
+        World::current_if_set(|world| {
+            let tcp = &mut world.current_host_mut().tcp;
+
+            res = if tcp.is_current(self.generation) && tcp.is_bound(self.local_addr) {
+                tcp.unbind(self.local_addr);
+                Ok(())
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    format!("{} is not bound", self.local_addr),
+                ))
+            };
+        });
+
+        res
+    }
 }
 
 impl Drop for TcpListener {
     fn drop(&mut self) {
-        World::current_if_set(|world| world.current_host_mut().tcp.unbind(self.local_addr));
+        if self.closed {
+            return;
+        }
+
+        World::current_if_set(|world| {
+            let tcp = &mut world.current_host_mut().tcp;
+
+            // The host's network was reset since, which unbound the listener
+            if tcp.is_current(self.generation) {
+                tcp.unbind(self.local_addr);
+            }
+        });
     }
 }
@@ -33,6 +33,7 @@ impl OwnedReadHalf {
     /// Attempts to put the two halves of a `TcpStream` back together and
     /// recover the original socket. Succeeds only if the two halves
     /// originated from the same call to `into_split`.
+    #[allow(clippy::result_large_err)]
     pub fn reunite(self, other: OwnedWriteHalf) -> Result<TcpStream, ReuniteError> {
         reunite(self, other)
     }
@@ -65,11 +66,14 @@ impl OwnedWriteHalf {
     /// Attempts to put the two halves of a `TcpStream` back together and
     /// recover the original socket. Succeeds only if the two halves
     /// originated from the same call to `into_split`.
+    #[allow(clippy::result_large_err)]
     pub fn reunite(self, other: OwnedReadHalf) -> Result<TcpStream, ReuniteError> {
         reunite(other, self)
     }
 }
 
+// The error hands back both halves, as tokio's does.
+#[allow(clippy::result_large_err)]
 fn reunite(read: OwnedReadHalf, write: OwnedWriteHalf) -> Result<TcpStream, ReuniteError> {
     if Arc::ptr_eq(&read.inner.pair, &write.inner.pair) {
         Ok(TcpStream::reunite(read.inner, write.inner))
@@ -1,13 +1,16 @@
 use std::{
     fmt::Debug,
+    future::Future,
     io::{self, Result},
     net::SocketAddr,
     pin::Pin,
     sync::Arc,
     task::{ready, Context, Poll},
+    time::Duration,
 };
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, Bytes, BytesMut};
+use rand::Rng;
 use tokio::{
     io::{AsyncRead, AsyncWrite, ReadBuf},
     sync::{mpsc, oneshot},
@@ -15,16 +18,24 @@ use tokio::{
 
 use crate::{
     envelope::{Protocol, Segment, Syn},
-    host::SequencedSegment,
+    host::{SequencedSegment, Timer},
     net::SocketPair,
+    top::SendOptions,
     world::World,
-    ToSocketAddrs, TRACING_TARGET,
+    Error, ToSocketAddrs, TRACING_TARGET,
 };
 
 use super::split_owned::{OwnedReadHalf, OwnedWriteHalf};
 
 /// A simulated TCP stream between a local and a remote socket.
 ///
+/// Pending reads and writes are woken as soon as the peer tears down the
+/// connection. A FIN, e.g. from a crashed peer, ends reads with EOF. A RST,
+/// e.g. from [`TcpStream::reset`], a drained peer, or a restarted peer without
+/// the connection, fails reads with `ConnectionReset` and writes with
+/// `BrokenPipe`. A killed peer sends neither, so reads stay pending until a
+/// timeout detects its death.
+///
 /// All methods must be called from a host within a Turmoil simulation.
 #[derive(Debug)]
 pub struct TcpStream {
@@ -32,6 +43,28 @@ pub struct TcpStream {
     write_half: WriteHalf,
 }
 
+/// Counters for a [`TcpStream`], returned by [`TcpStream::stats`].
+///
+/// Only data written and read by the application is counted, so connection
+/// setup and teardown, acks and retransmits are not included.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Bytes written to the stream.
+    pub bytes_sent: u64,
+
+    /// Data segments sent, one per successful write.
+    pub segments_sent: u64,
+
+    /// Bytes received by the stream, including those not yet read.
+    pub bytes_received: u64,
+
+    /// Data segments received.
+    pub segments_received: u64,
+
+    /// Bytes received but not yet read.
+    pub buffered: usize,
+}
+
 impl TcpStream {
     pub(crate) fn new(pair: SocketPair, receiver: mpsc::Receiver<SequencedSegment>) -> Self {
         let pair = Arc::new(pair);
@@ -40,13 +73,18 @@ impl TcpStream {
             rx: Rx {
                 recv: receiver,
                 buffer: None,
+                held: BytesMut::new(),
             },
             is_closed: false,
+            bytes_received: 0,
+            segments_received: 0,
         };
 
         let write_half = WriteHalf {
             pair,
             is_shutdown: false,
+            bytes_sent: 0,
+            segments_sent: 0,
         };
 
         Self {
@@ -56,32 +94,137 @@ impl TcpStream {
     }
 
     /// Opens a TCP connection to a remote host.
+    ///
+    /// Hostnames are resolved using the simulated DNS, whereas socket addresses
+    /// are routed by IP directly. Returns `HostUnreachable`, wrapping
+    /// [`Error::HostNotFound`], if no host owns the resolved IP.
+    ///
+    /// Connections always originate from a fresh ephemeral port, and only a
+    /// listener answers a SYN. Two hosts connecting to each other at the same
+    /// time, as in TCP's simultaneous open, therefore get two independent
+    /// connections, each accepted by the other host's listener, rather than
+    /// one shared connection. Which ports they use depends only on the seed.
     pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<TcpStream> {
         let (ack, syn_ack) = oneshot::channel();
 
-        let (pair, rx) = World::current(|world| {
-            let dst = addr.to_socket_addr(&world.dns);
+        let (pair, rx, retries, mut backoff, retransmit_delayed) = World::current(|world| {
+            let dst = world.resolve_socket_addr(addr);
+
+            // Addresses that are not owned by any host are unroutable
+            if !world.hosts.contains_key(&dst.ip()) {
+                return Err(io::Error::from(Error::HostNotFound(dst.ip())));
+            }
+
+            if let Some((n, window)) = world.tcp.reconnect_storm {
+                let count = world.current_host_mut().record_connect(dst.ip(), window);
+
+                assert!(
+                    count <= n,
+                    "reconnect storm: {} opened {count} connections to {} within {window:?}, more than {n}",
+                    world.dns.reverse(world.current.expect("current host")),
+                    world.dns.reverse(dst.ip()),
+                );
+            }
+
             let syn = Segment::Syn(Syn { ack });
 
             let host = world.current_host_mut();
-            let local_addr = (host.addr, host.assign_ephemeral_port()).into();
+            let local_addr = (host.addr, host.assign_tcp_port()?).into();
 
             let pair = SocketPair::new(local_addr, dst);
-            let rx = host.tcp.new_stream(pair);
+            let now = host.elapsed();
+            let rx = host.tcp.new_stream(pair, now);
+            world.reset_tcp_integrity(pair);
             world.send_message(local_addr, dst, Protocol::Tcp(syn));
 
-            (pair, rx)
-        });
-
-        syn_ack.await.map_err(|_| {
-            io::Error::new(io::ErrorKind::ConnectionRefused, pair.remote.to_string())
+            Ok((
+                pair,
+                rx,
+                world.tcp.syn_retries,
+                world.tcp.syn_backoff,
+                world.tcp.retransmit_delayed_syns,
+            ))
         })?;
 
+        // The SYN-ACK fails if the SYN is lost, which may be retransmitted.
+        // The SYN-ACK for any SYN sent so far completes the connect, as the
+        // server collapses duplicates.
+        let mut syn_acks = vec![syn_ack];
+
+        for retry in 0..=retries {
+            if retransmit_delayed && retry < retries {
+                let deadline = tokio::time::Instant::now() + backoff;
+                let _timer = Timer::new(backoff);
+
+                match tokio::time::timeout_at(deadline, any_syn_ack(&mut syn_acks)).await {
+                    Ok(true) => break,
+                    Ok(false) => tokio::time::sleep_until(deadline).await,
+                    Err(_) => {}
+                }
+            } else {
+                if any_syn_ack(&mut syn_acks).await {
+                    break;
+                }
+
+                if retry == retries {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionRefused,
+                        pair.remote.to_string(),
+                    ));
+                }
+
+                crate::sleep(backoff).await;
+            }
+
+            backoff *= 2;
+
+            let (ack, rx) = oneshot::channel();
+            syn_acks.push(rx);
+
+            World::current(|world| {
+                tracing::trace!(target: TRACING_TARGET, src = ?pair.local, dst = ?pair.remote, protocol = %"TCP SYN", "Retransmit");
+
+                let syn = Segment::Syn(Syn { ack });
+                world.send_message(pair.local, pair.remote, Protocol::Tcp(syn));
+            });
+        }
+
         tracing::trace!(target: TRACING_TARGET, dst = ?pair.local, src = ?pair.remote, protocol = %"TCP SYN-ACK", "Recv");
 
         Ok(TcpStream::new(pair, rx))
     }
 
+    /// Opens a TCP connection to a remote host, then simulates a secure
+    /// handshake on it, such as TLS, without encrypting anything.
+    ///
+    /// The handshake takes as many round trips as configured with
+    /// [`Builder::secure_handshake`](crate::Builder::secure_handshake), each
+    /// as long as the connect took, and may fail at the configured rate. A
+    /// failed handshake resets the connection, so the peer sees
+    /// `ConnectionReset`, and returns `InvalidData` wrapping
+    /// [`Error::HandshakeFailed`]. Failures are drawn from the host's random
+    /// number generator, so they are reproducible for a given seed.
+    pub async fn connect_secure<A: ToSocketAddrs>(addr: A) -> Result<TcpStream> {
+        let start = tokio::time::Instant::now();
+        let stream = TcpStream::connect(addr).await?;
+        let rtt = start.elapsed();
+
+        let (rtts, fail_rate) =
+            World::current(|world| (world.tcp.handshake_rtts, world.tcp.handshake_fail_rate));
+        let failed = fail_rate > 0.0 && crate::with_rng(|rng| rng.gen_bool(fail_rate));
+
+        crate::sleep(rtt * rtts).await;
+
+        if failed {
+            let peer = stream.read_half.pair.remote;
+            stream.reset();
+
+            return Err(Error::HandshakeFailed(peer).into());
+        }
+
+        Ok(stream)
+    }
+
     /// Returns the local address that this stream is bound to.
     pub fn local_addr(&self) -> Result<SocketAddr> {
         Ok(self.read_half.pair.local)
@@ -92,6 +235,84 @@ impl TcpStream {
         Ok(self.read_half.pair.remote)
     }
 
+    /// Sets latency added to segments sent on this stream, on top of the link
+    /// latency. Other streams on the same link are not affected.
+    ///
+    /// This models per connection overhead, such as a proxy in the path. Only
+    /// this side's writes are delayed; the peer may set its own extra latency.
+    /// The default is zero.
+    pub fn set_extra_latency(&self, value: Duration) {
+        World::current(|world| {
+            world
+                .current_host_mut()
+                .tcp
+                .set_extra_latency(*self.write_half.pair, value)
+        })
+    }
+
+    /// Sets latency added to segments in each direction of this connection, on
+    /// top of the link latency: `outbound` to the segments this side sends, and
+    /// `inbound` to those the peer sends.
+    ///
+    /// This models asymmetry specific to one connection, such as a slow return
+    /// path for responses, without affecting other streams on the same link.
+    /// Either side may set it, and both sides' settings, and any extra latency
+    /// or jitter, add up. Retransmitted segments are delayed too. The default
+    /// is zero in both directions.
+    pub fn set_directional_latency(&self, outbound: Duration, inbound: Duration) {
+        World::current(|world| {
+            world.current_host_mut().tcp.set_directional_latency(
+                *self.write_half.pair,
+                outbound,
+                inbound,
+            )
+        })
+    }
+
+    /// Returns the stream's counters, accumulated over its lifetime.
+    pub fn stats(&self) -> StreamStats {
+        StreamStats {
+            bytes_sent: self.write_half.bytes_sent,
+            segments_sent: self.write_half.segments_sent,
+            bytes_received: self.read_half.bytes_received,
+            segments_received: self.read_half.segments_received,
+            buffered: self.read_half.buffered(),
+        }
+    }
+
+    /// Sets the jitter of segments sent on this stream: each segment's latency
+    /// is increased by a random amount up to `value`, on top of the link and
+    /// extra latency.
+    ///
+    /// Segments still reach the peer's reader in order, so a delayed segment
+    /// holds back those after it, as on a real path with variable latency. The
+    /// amounts are drawn from the host's random number generator, so they are
+    /// reproducible for a given seed. Retransmitted segments aren't jittered.
+    /// The default is zero.
+    pub fn set_jitter(&self, value: Duration) {
+        World::current(|world| {
+            world
+                .current_host_mut()
+                .tcp
+                .set_jitter(*self.write_half.pair, value)
+        })
+    }
+
+    /// Abortively closes the stream, sending a RST to the peer rather than the
+    /// FIN sent on shutdown or drop.
+    ///
+    /// The peer's reads and writes fail with `ConnectionReset` and
+    /// `BrokenPipe` once the RST is delivered. Anything the peer sends after
+    /// that is discarded.
+    pub fn reset(self) {
+        World::current(|world| {
+            let pair = *self.write_half.pair;
+
+            self.write_half.send(world, Segment::Rst);
+            world.current_host_mut().tcp.remove_stream(pair);
+        })
+    }
+
     pub(crate) fn reunite(read_half: ReadHalf, write_half: WriteHalf) -> Self {
         Self {
             read_half,
@@ -118,11 +339,38 @@ impl TcpStream {
     }
 }
 
+/// Waits for the SYN-ACK of any of the SYNs sent for a connect, returning
+/// false once they have all failed.
+async fn any_syn_ack(syn_acks: &mut Vec<oneshot::Receiver<()>>) -> bool {
+    std::future::poll_fn(|cx| {
+        let mut i = 0;
+
+        while i < syn_acks.len() {
+            match Pin::new(&mut syn_acks[i]).poll(cx) {
+                Poll::Ready(Ok(())) => return Poll::Ready(true),
+                Poll::Ready(Err(_)) => {
+                    syn_acks.swap_remove(i);
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+
+        if syn_acks.is_empty() {
+            Poll::Ready(false)
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
 pub(crate) struct ReadHalf {
     pub(crate) pair: Arc<SocketPair>,
     rx: Rx,
     /// FIN received, EOF for reads
     is_closed: bool,
+    bytes_received: u64,
+    segments_received: u64,
 }
 
 struct Rx {
@@ -132,49 +380,101 @@ struct Rx {
     /// This is used to support read impls by stashing available bytes for
     /// subsequent reads.
     buffer: Option<Bytes>,
+    /// Received bytes held back from the reader until the configured read
+    /// boundary is reached.
+    held: BytesMut,
 }
 
 impl ReadHalf {
     fn poll_read_priv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf) -> Poll<Result<()>> {
-        if self.is_closed || buf.capacity() == 0 {
+        if buf.capacity() == 0 {
             return Poll::Ready(Ok(()));
         }
 
+        let (icmp, max) = World::current(|world| {
+            let icmp = world.current_host_mut().tcp.poll_icmp(*self.pair, cx);
+            (icmp, world.tcp.max_read_chunk.unwrap_or(usize::MAX))
+        });
+
+        if let Poll::Ready(icmp) = icmp {
+            return Poll::Ready(Err(icmp.error(self.pair.remote)));
+        }
+
         if let Some(bytes) = self.rx.buffer.take() {
-            self.rx.buffer = Self::put_slice(bytes, buf);
+            self.rx.buffer = Self::put_slice(bytes, buf, max);
 
             return Poll::Ready(Ok(()));
         }
 
-        match ready!(self.rx.recv.poll_recv(cx)) {
-            Some(seg) => {
-                tracing::trace!(target: TRACING_TARGET, dst = ?self.pair.local, src = ?self.pair.remote, protocol = %seg, "Recv");
+        if self.is_closed {
+            return Poll::Ready(Ok(()));
+        }
 
-                match seg {
-                    SequencedSegment::Data(bytes) => {
-                        self.rx.buffer = Self::put_slice(bytes, buf);
-                    }
-                    SequencedSegment::Fin => {
-                        self.is_closed = true;
+        loop {
+            match ready!(self.rx.recv.poll_recv(cx)) {
+                Some(seg) => {
+                    tracing::trace!(target: TRACING_TARGET, dst = ?self.pair.local, src = ?self.pair.remote, protocol = %seg, "Recv");
+
+                    match seg {
+                        SequencedSegment::Data(bytes) => {
+                            self.bytes_received += bytes.len() as u64;
+                            self.segments_received += 1;
+
+                            let boundary = World::current(|world| {
+                                world.tcp_read(*self.pair, &bytes);
+                                world.tcp.read_boundary
+                            });
+
+                            // Hold data until the boundary is reached
+                            if let Some(boundary) = boundary {
+                                self.rx.held.extend_from_slice(&bytes);
+
+                                if self.rx.held.len() < boundary {
+                                    continue;
+                                }
+
+                                let bytes = self.rx.held.split().freeze();
+                                self.rx.buffer = Self::put_slice(bytes, buf, max);
+                            } else {
+                                self.rx.buffer = Self::put_slice(bytes, buf, max);
+                            }
+                        }
+                        SequencedSegment::Fin => {
+                            self.is_closed = true;
+
+                            // Release any held data before EOF
+                            if !self.rx.held.is_empty() {
+                                let bytes = self.rx.held.split().freeze();
+                                self.rx.buffer = Self::put_slice(bytes, buf, max);
+                            }
+                        }
                     }
-                }
 
-                Poll::Ready(Ok(()))
+                    return Poll::Ready(Ok(()));
+                }
+                None => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        "Connection reset",
+                    )))
+                }
             }
-            None => Poll::Ready(Err(io::Error::new(
-                io::ErrorKind::ConnectionReset,
-                "Connection reset",
-            ))),
         }
     }
 
+    /// Bytes received but not yet read.
+    fn buffered(&self) -> usize {
+        self.rx.buffer.as_ref().map_or(0, Bytes::len) + self.rx.held.len()
+    }
+
     /// Put bytes in `buf` based on the minimum of `avail` and its remaining
     /// capacity.
     ///
     /// Returns an optional `Bytes` containing any remainder of `avail` that was
     /// not consumed.
-    fn put_slice(mut avail: Bytes, buf: &mut ReadBuf) -> Option<Bytes> {
-        let amt = std::cmp::min(avail.len(), buf.remaining());
+    /// Put up to `max` bytes of `avail` into `buf`, returning any left over.
+    fn put_slice(mut avail: Bytes, buf: &mut ReadBuf, max: usize) -> Option<Bytes> {
+        let amt = avail.len().min(buf.remaining()).min(max);
 
         buf.put_slice(&avail[..amt]);
         avail.advance(amt);
@@ -200,10 +500,12 @@ pub(crate) struct WriteHalf {
     pub(crate) pair: Arc<SocketPair>,
     /// FIN sent, closed for writes
     is_shutdown: bool,
+    bytes_sent: u64,
+    segments_sent: u64,
 }
 
 impl WriteHalf {
-    fn poll_write_priv(&self, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+    fn poll_write_priv(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
         if buf.remaining() == 0 {
             return Poll::Ready(Ok(0));
         }
@@ -215,17 +517,39 @@ impl WriteHalf {
             )));
         }
 
-        let res = World::current(|world| {
-            let bytes = Bytes::copy_from_slice(buf);
+        World::current(|world| {
+            if let Some(icmp) = world.current_host_mut().tcp.take_icmp(*self.pair) {
+                return Poll::Ready(Err(icmp.error(self.pair.remote)));
+            }
+
+            if world.tcp.fail_writes_on_partition
+                && world
+                    .topology
+                    .is_partitioned(self.pair.local.ip(), self.pair.remote.ip())
+            {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::HostUnreachable,
+                    self.pair.remote.to_string(),
+                )));
+            }
+
+            let window = ready!(world.current_host_mut().tcp.poll_window(*self.pair, cx));
+
+            let bytes = Bytes::copy_from_slice(&buf[..buf.len().min(window)]);
             let len = bytes.len();
 
-            let seq = self.seq(world)?;
+            let seq = match self.seq(world) {
+                Ok(seq) => seq,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            world.tcp_written(*self.pair, &bytes);
             self.send(world, Segment::Data(seq, bytes));
 
-            Ok(len)
-        });
+            self.bytes_sent += len as u64;
+            self.segments_sent += 1;
 
-        Poll::Ready(res)
+            Poll::Ready(Ok(len))
+        })
     }
 
     fn poll_shutdown_priv(&mut self) -> Poll<Result<()>> {
@@ -259,7 +583,28 @@ impl WriteHalf {
     }
 
     fn send(&self, world: &mut World, segment: Segment) {
-        world.send_message(self.pair.local, self.pair.remote, Protocol::Tcp(segment));
+        let pair = *self.pair;
+        let directional_latency = world.tcp_directional_latency(pair);
+        let host = world.current_host_mut();
+        let extra_latency = host.tcp_send_latency(pair) + directional_latency;
+
+        match &segment {
+            Segment::Data(seq, data) => {
+                host.tcp_sent(pair, *seq, SequencedSegment::Data(data.clone()))
+            }
+            Segment::Fin(seq) => host.tcp_sent(pair, *seq, SequencedSegment::Fin),
+            _ => {}
+        }
+
+        world.send_message_with(
+            pair.local,
+            pair.remote,
+            Protocol::Tcp(segment),
+            SendOptions {
+                extra_latency,
+                ..Default::default()
+            },
+        );
     }
 }
 
@@ -293,7 +638,11 @@ impl AsyncRead for TcpStream {
 }
 
 impl AsyncWrite for WriteHalf {
-    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
         self.poll_write_priv(cx, buf)
     }
 
@@ -2,14 +2,19 @@ use bytes::Bytes;
 use tokio::sync::{mpsc, Mutex};
 
 use crate::{
-    envelope::{Datagram, Protocol},
-    ToSocketAddrs, World, TRACING_TARGET,
+    envelope::{Datagram, Icmp, Protocol},
+    top::SendOptions,
+    Error, ToSocketAddrs, World, TRACING_TARGET,
 };
 
 use std::{
     cmp,
+    fmt::{self, Display},
+    future::poll_fn,
     io::{self, Result},
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    task::{Context, Poll},
 };
 
 /// A simulated UDP socket.
@@ -18,28 +23,48 @@ use std::{
 pub struct UdpSocket {
     local_addr: SocketAddr,
     rx: Mutex<Rx>,
+    priority: AtomicU8,
+    broadcast: AtomicBool,
+
+    /// The host's socket generation when bound. See [`Sim::reset_network`].
+    ///
+    /// [`Sim::reset_network`]: crate::Sim::reset_network
+    generation: u64,
+}
+
+/// A message queued on a socket.
+pub(crate) enum Queued {
+    Datagram(Datagram, SocketAddr),
+
+    /// The connected peer's port was unreachable.
+    Refused,
 }
 
 struct Rx {
-    recv: mpsc::Receiver<(Datagram, SocketAddr)>,
+    recv: mpsc::Receiver<Queued>,
     /// A buffered received message.
     ///
     /// This is used to support the `readable` method, as [`mpsc::Receiver`]
     /// doesn't expose a way to query channel readiness.
-    buffer: Option<(Datagram, SocketAddr)>,
+    buffer: Option<Queued>,
 }
 
 impl Rx {
     /// Tries to receive from either the buffered message or the mpsc channel
     pub fn try_recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, Datagram, SocketAddr)> {
-        let (datagram, origin) = if let Some(datagram) = self.buffer.take() {
-            datagram
+        let queued = if let Some(queued) = self.buffer.take() {
+            queued
         } else {
             self.recv.try_recv().map_err(|_| {
                 io::Error::new(io::ErrorKind::WouldBlock, "socket receive queue is empty")
             })?
         };
 
+        let (datagram, origin) = match queued {
+            Queued::Datagram(datagram, origin) => (datagram, origin),
+            Queued::Refused => return Err(refused()),
+        };
+
         let bytes = &datagram.0;
         let limit = cmp::min(buf.len(), bytes.len());
 
@@ -67,45 +92,83 @@ impl Rx {
             return Ok(());
         }
 
-        let datagram = self
-            .recv
-            .recv()
-            .await
-            .expect("sender should never be dropped");
+        // The sender is only dropped when the host's network is reset
+        let Some(datagram) = self.recv.recv().await else {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "socket was reset",
+            ));
+        };
 
         self.buffer = Some(datagram);
 
         Ok(())
     }
+
+    /// Takes a pending error, if it is next in the queue.
+    fn take_error(&mut self) -> Option<io::Error> {
+        if self.buffer.is_none() {
+            self.buffer = self.recv.try_recv().ok();
+        }
+
+        match self.buffer {
+            Some(Queued::Refused) => {
+                self.buffer = None;
+                Some(refused())
+            }
+            _ => None,
+        }
+    }
+}
+
+fn refused() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, "port unreachable")
+}
+
+impl Display for Queued {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Queued::Datagram(datagram, _) => Display::fmt(datagram, f),
+            Queued::Refused => Display::fmt(&Icmp::PortUnreachable, f),
+        }
+    }
 }
 
 impl UdpSocket {
-    pub(crate) fn new(local_addr: SocketAddr, rx: mpsc::Receiver<(Datagram, SocketAddr)>) -> Self {
+    pub(crate) fn new(local_addr: SocketAddr, rx: mpsc::Receiver<Queued>, generation: u64) -> Self {
         Self {
             local_addr,
             rx: Mutex::new(Rx {
                 recv: rx,
                 buffer: None,
             }),
+            priority: AtomicU8::new(0),
+            broadcast: AtomicBool::new(false),
+            generation,
         }
     }
 
     /// Create a new simulated UDP socket and attempt to bind it to the `addr`
     /// provided.
     ///
-    /// Only 0.0.0.0 is currently supported.
+    /// Only 0.0.0.0 is currently supported, other addresses fail with
+    /// [`Error::UnsupportedBind`].
     pub async fn bind<A: ToSocketAddrs>(addr: A) -> Result<UdpSocket> {
         World::current(|world| {
             let mut addr = addr.to_socket_addr(&world.dns);
             let host = world.current_host_mut();
 
             if !addr.ip().is_unspecified() {
-                panic!("{addr} is not supported");
+                return Err(Error::UnsupportedBind(addr).into());
             }
 
             // Unspecified -> host's IP
             addr.set_ip(host.addr);
 
+            if addr.port() == 0 {
+                addr.set_port(host.assign_udp_port()?);
+            }
+
             host.udp.bind(addr)
         })
     }
@@ -116,13 +179,16 @@ impl UdpSocket {
     /// Address type can be any implementor of [`ToSocketAddrs`] trait. See its
     /// documentation for concrete examples.
     ///
-    /// It is possible for `addr` to yield multiple addresses, but `send_to`
-    /// will only send data to the first address yielded by `addr`.
+    /// Hostnames, e.g. `"server:53"` or `("server", 53)`, are resolved using
+    /// the simulated DNS, in the same way as [`TcpStream::connect`].
     ///
     /// This will return an error when the IP version of the local socket does
-    /// not match that returned from [`ToSocketAddrs`].
+    /// not match that returned from [`ToSocketAddrs`]. While the socket's send
+    /// buffer is full, see [`Builder::udp_send_buffer`], this waits.
     ///
     /// [`ToSocketAddrs`]: crate::ToSocketAddrs
+    /// [`TcpStream::connect`]: crate::net::TcpStream::connect
+    /// [`Builder::udp_send_buffer`]: crate::Builder::udp_send_buffer
     ///
     /// # Cancel safety
     ///
@@ -130,17 +196,9 @@ impl UdpSocket {
     /// [`tokio::select!`](crate::select) statement and some other branch
     /// completes first, then it is guaranteed that the message was not sent.
     pub async fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], target: A) -> Result<usize> {
-        World::current(|world| {
-            let dst = target.to_socket_addr(&world.dns);
+        self.writable().await?;
 
-            world.send_message(
-                self.local_addr,
-                dst,
-                Protocol::Udp(Datagram(Bytes::copy_from_slice(buf))),
-            );
-
-            Ok(buf.len())
-        })
+        World::current(|world| self.send_message(world, buf, target))
     }
 
     /// Tries to send data on the socket to the given address, but if the send is
@@ -152,25 +210,57 @@ impl UdpSocket {
     ///
     /// If successful, returns the number of bytes sent
     ///
-    /// Users should ensure that when the remote cannot receive, the
-    /// [`ErrorKind::WouldBlock`] is properly handled. An error can also occur
-    /// if the IP version of the socket does not match that of `target`.
+    /// Fails with [`ErrorKind::WouldBlock`] while the socket's send buffer is
+    /// full, see [`Builder::udp_send_buffer`]. An error can also occur if the
+    /// IP version of the socket does not match that of `target`.
     ///
     /// [`ErrorKind::WouldBlock`]: std::io::ErrorKind::WouldBlock
+    /// [`Builder::udp_send_buffer`]: crate::Builder::udp_send_buffer
     pub fn try_send_to<A: ToSocketAddrs>(&self, buf: &[u8], target: A) -> Result<usize> {
         World::current(|world| {
-            let dst = target.to_socket_addr(&world.dns);
-
-            world.send_message(
-                self.local_addr,
-                dst,
-                Protocol::Udp(Datagram(Bytes::copy_from_slice(buf))),
-            );
+            if world.topology.is_send_buffer_full(self.local_addr) {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "socket send buffer is full",
+                ));
+            }
 
-            Ok(buf.len())
+            self.send_message(world, buf, target)
         })
     }
 
+    fn send_message(
+        &self,
+        world: &mut World,
+        buf: &[u8],
+        target: impl ToSocketAddrs,
+    ) -> Result<usize> {
+        let dst = world.resolve_socket_addr(target);
+
+        if let IpAddr::V4(ip) = dst.ip() {
+            if ip.is_broadcast() {
+                return self.broadcast_message(world, buf, dst.port());
+            }
+        }
+
+        // The peer may have migrated to a new address
+        let host = world.current_host_mut();
+        let (src, dst) = (host.addr, host.udp.route(dst));
+        let dst = world.anycast_route(src, dst);
+
+        world.send_message_with(
+            self.local_addr,
+            dst,
+            Protocol::Udp(Datagram(Bytes::copy_from_slice(buf))),
+            SendOptions {
+                priority: self.priority(),
+                ..Default::default()
+            },
+        );
+
+        Ok(buf.len())
+    }
+
     /// Waits for the socket to become writable.
     ///
     /// This function is usually paired with `try_send_to()`.
@@ -186,8 +276,30 @@ impl UdpSocket {
     /// consumed by an attempt to write that fails with `WouldBlock` or
     /// `Poll::Pending`.
     pub async fn writable(&self) -> Result<()> {
-        // UDP sockets currently don't have any backpressure mechanisms so the socket is always writable
-        Ok(())
+        poll_fn(|cx| self.poll_send_ready(cx)).await
+    }
+
+    /// Polls for write/send readiness.
+    ///
+    /// If the socket is not currently ready for sending, this method will
+    /// store a clone of the `Waker` from the provided `Context`. When the
+    /// socket becomes ready for sending, `Waker::wake` will be called on the
+    /// waker.
+    ///
+    /// The socket is ready while its send buffer has room, see
+    /// [`Builder::udp_send_buffer`]. By default, it's always ready.
+    ///
+    /// [`Builder::udp_send_buffer`]: crate::Builder::udp_send_buffer
+    pub fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        World::current(|world| {
+            if world.topology.is_send_buffer_full(self.local_addr) {
+                // Check again once the network has sent more
+                world.current_host_mut().wake_after_step(cx.waker());
+                return Poll::Pending;
+            }
+
+            Poll::Ready(Ok(()))
+        })
     }
 
     /// Receives a single datagram message on the socket. On success, returns
@@ -200,9 +312,7 @@ impl UdpSocket {
         let mut rx = self.rx.lock().await;
         rx.readable().await?;
 
-        let (limit, datagram, origin) = rx
-            .try_recv_from(buf)
-            .expect("queue should be ready after readable yields");
+        let (limit, datagram, origin) = rx.try_recv_from(buf)?;
 
         tracing::trace!(target: TRACING_TARGET, dst = ?self.local_addr, src = ?origin, protocol = %datagram, "Recv");
 
@@ -226,9 +336,7 @@ impl UdpSocket {
             )
         })?;
 
-        let (limit, datagram, origin) = rx.try_recv_from(buf).map_err(|_| {
-            io::Error::new(io::ErrorKind::WouldBlock, "socket receive queue is empty")
-        })?;
+        let (limit, datagram, origin) = rx.try_recv_from(buf)?;
 
         tracing::trace!(target: TRACING_TARGET, dst = ?self.local_addr, src = ?origin, protocol = %datagram, "Recv");
 
@@ -275,10 +383,152 @@ impl UdpSocket {
     pub fn local_addr(&self) -> Result<SocketAddr> {
         Ok(self.local_addr)
     }
+
+    /// Connects the socket to `addr`, so that [`UdpSocket::send`] sends to it
+    /// and only datagrams from it are received.
+    ///
+    /// If a datagram sent to the peer arrives at a port with no bound socket,
+    /// the next call to [`UdpSocket::recv`] or [`UdpSocket::send`] fails with
+    /// [`ErrorKind::ConnectionRefused`], modeling ICMP port unreachable. The
+    /// error arrives a round trip after the datagram was sent. Unconnected
+    /// sockets don't see these errors.
+    ///
+    /// [`ErrorKind::ConnectionRefused`]: std::io::ErrorKind::ConnectionRefused
+    pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<()> {
+        World::current(|world| {
+            let peer = world.resolve_socket_addr(addr);
+            world.current_host_mut().udp.connect(self.local_addr, peer);
+        });
+
+        Ok(())
+    }
+
+    /// Returns the address of the peer this socket is connected to.
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        World::current(|world| world.current_host_mut().udp.peer(self.local_addr))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "socket is not connected"))
+    }
+
+    /// Sends data on the socket to the connected peer. On success, returns the
+    /// number of bytes written.
+    ///
+    /// Fails with [`ErrorKind::ConnectionRefused`] if an earlier datagram found
+    /// the peer unreachable. See [`UdpSocket::connect`].
+    ///
+    /// [`ErrorKind::ConnectionRefused`]: std::io::ErrorKind::ConnectionRefused
+    pub async fn send(&self, buf: &[u8]) -> Result<usize> {
+        let peer = self.peer_addr()?;
+
+        if let Ok(mut rx) = self.rx.try_lock() {
+            if let Some(err) = rx.take_error() {
+                return Err(err);
+            }
+        }
+
+        self.writable().await?;
+
+        World::current(|world| self.send_message(world, buf, peer))
+    }
+
+    /// Receives a single datagram from the connected peer. On success, returns
+    /// the number of bytes read.
+    ///
+    /// Fails with [`ErrorKind::ConnectionRefused`] if an earlier datagram found
+    /// the peer unreachable. See [`UdpSocket::connect`].
+    ///
+    /// [`ErrorKind::ConnectionRefused`]: std::io::ErrorKind::ConnectionRefused
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        self.peer_addr()?;
+
+        let (len, _) = self.recv_from(buf).await?;
+        Ok(len)
+    }
+
+    /// Send `buf` to `port` on every other IPv4 host, as a datagram to the
+    /// limited broadcast address would be.
+    fn broadcast_message(&self, world: &mut World, buf: &[u8], port: u16) -> Result<usize> {
+        if !self.broadcast()? {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "permission denied: broadcast is not enabled on the socket",
+            ));
+        }
+
+        let src = world.current_host_mut().addr;
+        let dsts: Vec<_> = world
+            .hosts
+            .keys()
+            .filter(|addr| addr.is_ipv4() && **addr != src)
+            .copied()
+            .collect();
+
+        for dst in dsts {
+            world.send_message_with(
+                self.local_addr,
+                SocketAddr::new(dst, port),
+                Protocol::Udp(Datagram(Bytes::copy_from_slice(buf))),
+                SendOptions {
+                    priority: self.priority(),
+                    ..Default::default()
+                },
+            );
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Sets the priority of datagrams sent from this socket, modeling
+    /// DSCP-style prioritization.
+    ///
+    /// Datagrams queue on links and hosts with a limited bandwidth, see
+    /// [`Builder::bandwidth`] and [`Sim::set_host_bandwidth`], and when several
+    /// become deliverable to a host at the same time, e.g. when a held link is
+    /// released. Queued datagrams go highest priority first, and in send order
+    /// within a priority, but one already on the wire is never preempted. The
+    /// default priority is 0, which keeps delivery in send order.
+    ///
+    /// [`Builder::bandwidth`]: crate::Builder::bandwidth
+    /// [`Sim::set_host_bandwidth`]: crate::Sim::set_host_bandwidth
+    pub fn set_priority(&self, priority: u8) {
+        self.priority.store(priority, Ordering::Relaxed);
+    }
+
+    /// Gets the priority of datagrams sent from this socket. See
+    /// [`UdpSocket::set_priority`].
+    pub fn priority(&self) -> u8 {
+        self.priority.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether this socket may send to the broadcast address,
+    /// `255.255.255.255`. Defaults to `false`, as on Linux, where sending
+    /// without it fails with [`ErrorKind::PermissionDenied`].
+    ///
+    /// Once enabled, a datagram sent to the broadcast address is delivered to
+    /// the same port on every other IPv4 host in the simulation, subject to
+    /// each link's state.
+    ///
+    /// [`ErrorKind::PermissionDenied`]: std::io::ErrorKind::PermissionDenied
+    pub fn set_broadcast(&self, on: bool) -> Result<()> {
+        self.broadcast.store(on, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Gets the value of the broadcast option. See
+    /// [`UdpSocket::set_broadcast`].
+    pub fn broadcast(&self) -> Result<bool> {
+        Ok(self.broadcast.load(Ordering::Relaxed))
+    }
 }
 
 impl Drop for UdpSocket {
     fn drop(&mut self) {
-        World::current_if_set(|world| world.current_host_mut().udp.unbind(self.local_addr));
+        World::current_if_set(|world| {
+            let udp = &mut world.current_host_mut().udp;
+
+            // The host's network was reset since, which unbound the socket
+            if udp.is_current(self.generation) {
+                udp.unbind(self.local_addr);
+            }
+        });
     }
 }
@@ -0,0 +1,192 @@
+use bytes::Bytes;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::envelope::{Datagram, Protocol};
+use crate::{Delivery, ToIpAddr, World};
+
+/// The simulated network, driven directly rather than by host software.
+///
+/// This is a sans-io interface to turmoil's link model, for use with another
+/// executor or a synchronous simulator. Created with
+/// [`Builder::build_network`](crate::Builder::build_network).
+///
+/// The contract is:
+///
+/// * [`Network::send`] enqueues a datagram on the link between two registered
+///   hosts, where it is subject to the link's latency, loss and partitions.
+/// * [`Network::step`] advances time by one tick.
+/// * [`Network::recv`] takes every datagram that has arrived at a host.
+///
+/// The network does no I/O and runs no tasks, so the caller decides when
+/// hosts run relative to steps. A [`Sim`](crate::Sim) remains the front-end for
+/// tokio software, and for TCP.
+pub struct Network {
+    world: World,
+
+    /// How much simulated time elapses each step
+    tick: Duration,
+
+    /// Simulated time elapsed since the network was built
+    elapsed: Duration,
+}
+
+impl Network {
+    pub(crate) fn new(world: World, tick: Duration) -> Network {
+        Network {
+            world,
+            tick,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Register a host, returning its address. Links are created between the
+    /// host and every host already registered.
+    pub fn register(&mut self, host: impl ToIpAddr) -> IpAddr {
+        let addr = self.world.lookup(host);
+        self.world.register(addr);
+        addr
+    }
+
+    /// Lookup the IP address of a host, assigning one if it is new.
+    pub fn lookup(&mut self, host: impl ToIpAddr) -> IpAddr {
+        self.world.lookup(host)
+    }
+
+    /// How much simulated time has elapsed.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Send `payload` from `src` to `dst` as a datagram.
+    ///
+    /// Both addresses must be owned by different registered hosts.
+    pub fn send(&mut self, src: SocketAddr, dst: SocketAddr, payload: impl Into<Bytes>) {
+        for addr in [src, dst] {
+            assert!(
+                self.world.hosts.contains_key(&addr.ip()),
+                "{} is not a registered host",
+                addr.ip()
+            );
+        }
+        assert_ne!(src.ip(), dst.ip(), "a host can't send to itself");
+
+        let message = Protocol::Udp(Datagram(payload.into()));
+        self.world.send_message(src, dst, message);
+    }
+
+    /// Advance the network by one tick, making datagrams whose latency has
+    /// passed available to [`Network::recv`].
+    pub fn step(&mut self) {
+        self.world.topology.tick_by(&mut self.world.rng, self.tick);
+        self.elapsed += self.tick;
+    }
+
+    /// Take the datagrams that have arrived at `host`, in delivery order.
+    pub fn recv(&mut self, host: impl ToIpAddr) -> Vec<Delivery> {
+        let host = self.world.lookup(host);
+
+        self.world
+            .topology
+            .take_messages(&mut self.world.rng, host, None)
+            .iter()
+            .map(Delivery::new)
+            .collect()
+    }
+
+    /// Partition the link between `a` and `b`, dropping datagrams sent over it.
+    pub fn partition(&mut self, a: impl ToIpAddr, b: impl ToIpAddr) {
+        let a = self.world.lookup(a);
+        let b = self.world.lookup(b);
+        self.world.partition(a, b);
+    }
+
+    /// The opposite of [`Network::partition`].
+    pub fn repair(&mut self, a: impl ToIpAddr, b: impl ToIpAddr) {
+        let a = self.world.lookup(a);
+        let b = self.world.lookup(b);
+        self.world.repair(a, b);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use crate::{Builder, DeliveryKind, SameTickOrder};
+
+    #[test]
+    fn send_and_recv() {
+        let mut network = Builder::new()
+            .min_message_latency(Duration::from_millis(5))
+            .max_message_latency(Duration::from_millis(5))
+            .build_network();
+
+        let a = SocketAddr::from((network.register("a"), 1000));
+        let b = SocketAddr::from((network.register("b"), 2000));
+
+        network.send(a, b, "ping");
+
+        for _ in 0..4 {
+            network.step();
+            assert!(network.recv("b").is_empty());
+        }
+
+        network.step();
+        assert_eq!(Duration::from_millis(5), network.elapsed());
+
+        let received = network.recv("b");
+        assert_eq!(1, received.len());
+        assert_eq!((a, b), (received[0].src, received[0].dst));
+        assert_eq!(DeliveryKind::Udp, received[0].kind);
+        assert_eq!("ping", received[0].payload);
+
+        // Partitioned links drop datagrams
+        network.partition("a", "b");
+        network.send(b, a, "pong");
+
+        for _ in 0..10 {
+            network.step();
+        }
+        assert!(network.recv("a").is_empty());
+    }
+
+    /// Send from eight hosts to "server" in reverse registration order,
+    /// returning the order the datagrams are received in.
+    fn same_tick(order: SameTickOrder, seed: u64) -> Vec<SocketAddr> {
+        let mut network = Builder::from_master_seed(seed, 0)
+            .min_message_latency(Duration::from_millis(1))
+            .max_message_latency(Duration::from_millis(1))
+            .same_tick_order(order)
+            .build_network();
+
+        let server = SocketAddr::from((network.register("server"), 1000));
+        let clients = (0..8)
+            .map(|i| SocketAddr::from((network.register(format!("client-{i}")), 2000)))
+            .collect::<Vec<_>>();
+
+        for client in clients.iter().rev() {
+            network.send(*client, server, "ping");
+        }
+
+        network.step();
+        network.recv("server").iter().map(|d| d.src).collect()
+    }
+
+    #[test]
+    fn same_tick_order() {
+        let fifo = same_tick(SameTickOrder::Fifo, 0);
+        assert_eq!(8, fifo.len());
+
+        let mut sent = fifo.clone();
+        sent.sort();
+        sent.reverse();
+        assert_eq!(sent, fifo);
+
+        // Randomized, but reproducible with the same seed
+        let random = same_tick(SameTickOrder::Random, 0);
+        assert_ne!(fifo, random);
+        assert_eq!(random, same_tick(SameTickOrder::Random, 0));
+    }
+}
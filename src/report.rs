@@ -0,0 +1,123 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// A snapshot of the simulated network, returned by
+/// [`Sim::describe`](crate::Sim::describe).
+///
+/// Useful for validating a setup before running it. For example, a typo'd host
+/// name that was resolved with [`Sim::lookup`](crate::Sim::lookup) shows up as
+/// a DNS entry without a registered host. The report implements `Display` for
+/// printing.
+#[derive(Clone, Debug)]
+pub struct TopologyReport {
+    /// Registered hosts, in registration order.
+    pub hosts: Vec<HostReport>,
+
+    /// Links between registered hosts.
+    pub links: Vec<LinkReport>,
+
+    /// Every name that has been resolved, including those without a host.
+    pub dns: Vec<(String, IpAddr)>,
+}
+
+/// A registered host.
+#[derive(Clone, Debug)]
+pub struct HostReport {
+    pub name: String,
+    pub addr: IpAddr,
+}
+
+/// A link between two registered hosts, with its effective configuration.
+#[derive(Clone, Debug)]
+pub struct LinkReport {
+    /// Host names for the link, ordered by address.
+    pub pair: (String, String),
+    pub state: LinkState,
+    pub min_message_latency: Duration,
+    pub max_message_latency: Duration,
+    pub fail_rate: f64,
+    pub repair_rate: f64,
+}
+
+/// The state of a link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkState {
+    Healthy,
+
+    /// The link was partitioned, explicitly or randomly.
+    Partitioned,
+
+    /// Messages are being held.
+    Held,
+}
+
+impl TopologyReport {
+    /// Render the report as a Graphviz graph, with hosts as nodes and links as
+    /// edges labeled with their latency and fail rate.
+    ///
+    /// Healthy links are drawn black, partitioned links red and dashed, and
+    /// held links orange.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("graph turmoil {\n");
+
+        for host in &self.hosts {
+            dot.push_str(&format!(
+                "  {:?} [label=\"{}\\n{}\"];\n",
+                host.name, host.name, host.addr
+            ));
+        }
+
+        for link in &self.links {
+            let style = match link.state {
+                LinkState::Healthy => "color=black",
+                LinkState::Partitioned => "color=red, style=dashed",
+                LinkState::Held => "color=orange",
+            };
+
+            dot.push_str(&format!(
+                "  {:?} -- {:?} [label=\"{:?}..={:?}\\nfail {}\", {}];\n",
+                link.pair.0,
+                link.pair.1,
+                link.min_message_latency,
+                link.max_message_latency,
+                link.fail_rate,
+                style,
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl fmt::Display for TopologyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "hosts:")?;
+        for host in &self.hosts {
+            writeln!(f, "  {} ({})", host.name, host.addr)?;
+        }
+
+        writeln!(f, "links:")?;
+        for link in &self.links {
+            writeln!(
+                f,
+                "  {} <-> {}: {:?}, latency {:?}..={:?}, fail rate {}, repair rate {}",
+                link.pair.0,
+                link.pair.1,
+                link.state,
+                link.min_message_latency,
+                link.max_message_latency,
+                link.fail_rate,
+                link.repair_rate,
+            )?;
+        }
+
+        writeln!(f, "dns:")?;
+        for (name, addr) in &self.dns {
+            writeln!(f, "  {name} -> {addr}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,8 +1,14 @@
+use std::cell::Cell;
 use std::mem;
+use std::rc::Rc;
 
 use super::Result;
 use futures::Future;
-use std::pin::Pin;
+use std::future::{pending, poll_fn};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
 use tokio::runtime::Runtime;
 use tokio::task::JoinHandle;
 use tokio::task::LocalSet;
@@ -44,6 +50,19 @@ pub(crate) struct Rt<'a> {
     /// Optional handle to a host's software. When software finishes, the handle is
     /// consumed to check for error, which is propagated up to fail the simulation.
     handle: Option<JoinHandle<Result>>,
+
+    /// Set when a task is scheduled on the local task set, from outside of it.
+    woken: Arc<AtomicBool>,
+
+    /// Total time spent in ticks where no task was runnable.
+    blocked: Duration,
+
+    /// Whether no task was runnable in the last tick.
+    idle: bool,
+
+    /// Number of times the software's top level future was polled, across
+    /// restarts.
+    polls: Rc<Cell<u64>>,
 }
 
 impl<'a> Rt<'a> {
@@ -52,14 +71,21 @@ impl<'a> Rt<'a> {
         F: Future<Output = Result> + 'static,
     {
         let (tokio, local) = init();
+        let polls = Rc::default();
 
-        let handle = with(&tokio, &local, || tokio::task::spawn_local(client));
+        let handle = with(&tokio, &local, || {
+            tokio::task::spawn_local(counted(&polls, client))
+        });
 
         Self {
             kind: Kind::Client,
             tokio,
             local,
             handle: Some(handle),
+            woken: Arc::new(AtomicBool::new(true)),
+            blocked: Duration::ZERO,
+            idle: false,
+            polls,
         }
     }
 
@@ -70,14 +96,22 @@ impl<'a> Rt<'a> {
     {
         let (tokio, local) = init();
 
+        let polls = Rc::default();
+
         let software: Software = Box::new(move || Box::pin(software()));
-        let handle = with(&tokio, &local, || tokio::task::spawn_local(software()));
+        let handle = with(&tokio, &local, || {
+            tokio::task::spawn_local(counted(&polls, software()))
+        });
 
         Self {
             kind: Kind::Host { software },
             tokio,
             local,
             handle: Some(handle),
+            woken: Arc::new(AtomicBool::new(true)),
+            blocked: Duration::ZERO,
+            idle: false,
+            polls,
         }
     }
 
@@ -89,6 +123,10 @@ impl<'a> Rt<'a> {
             tokio,
             local,
             handle: None,
+            woken: Arc::new(AtomicBool::new(true)),
+            blocked: Duration::ZERO,
+            idle: false,
+            polls: Rc::default(),
         }
     }
 
@@ -131,16 +169,49 @@ impl<'a> Rt<'a> {
     // Returns whether the software has finished successfully or the error
     // that caused failure. Subsequent calls do not return the error as it is
     // expected to fail the simulation.
+    //
+    // Tasks on the `LocalSet` are polled with a waker that records whether
+    // they were scheduled, while the sleep is not. A tick in which no task was
+    // scheduled before the `LocalSet` was polled is one where the host was
+    // blocked, waiting on I/O or timers.
     pub(crate) fn tick(&mut self, duration: Duration) -> Result<bool> {
+        let woken = self.woken.clone();
+        let mut runnable = false;
+
         self.tokio.block_on(async {
-            self.local
-                .run_until(async {
-                    sleep(duration).await;
-                })
-                .await
+            let mut sleep = pin!(sleep(duration));
+            let mut local = pin!(self.local.run_until(pending::<()>()));
+
+            poll_fn(|cx| {
+                if sleep.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(());
+                }
+
+                runnable |= woken.swap(false, Ordering::Relaxed);
+
+                let waker = Waker::from(Arc::new(Tracked {
+                    inner: cx.waker().clone(),
+                    woken: woken.clone(),
+                }));
+                _ = local.as_mut().poll(&mut Context::from_waker(&waker));
+
+                Poll::Pending
+            })
+            .await
         });
 
-        // pull for software completion
+        if !runnable {
+            self.blocked += duration;
+        }
+
+        self.idle = !runnable;
+
+        self.poll_software()
+    }
+
+    /// Returns whether the software has finished, or the error that caused it
+    /// to fail.
+    pub(crate) fn poll_software(&mut self) -> Result<bool> {
         match &self.handle {
             Some(handle) if handle.is_finished() => {
                 // Consume handle to extract task result
@@ -159,6 +230,36 @@ impl<'a> Rt<'a> {
         }
     }
 
+    /// Total time spent in ticks where none of the software's tasks were
+    /// runnable.
+    pub(crate) fn blocked(&self) -> Duration {
+        self.blocked
+    }
+
+    /// Number of times the software's top level future has been polled.
+    pub(crate) fn polls(&self) -> u64 {
+        self.polls.get()
+    }
+
+    /// Whether none of the software's tasks were runnable in the last tick.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Run tasks that are ready on the runtime without advancing time.
+    ///
+    /// This is used to resume hosts that have yielded during a tick (see
+    /// [`crate::yield_now`]).
+    pub(crate) fn run_ready(&mut self) {
+        self.tokio.block_on(async {
+            self.local
+                .run_until(async {
+                    tokio::task::yield_now().await;
+                })
+                .await
+        });
+    }
+
     pub(crate) fn crash(&mut self) {
         if !self.is_host() {
             panic!("can only crash host's software");
@@ -178,9 +279,10 @@ impl<'a> Rt<'a> {
 
         if let Kind::Host { software } = &self.kind {
             let handle = with(&self.tokio, &self.local, || {
-                tokio::task::spawn_local(software())
+                tokio::task::spawn_local(counted(&self.polls, software()))
             });
             self.handle.replace(handle);
+            self.woken.store(true, Ordering::Relaxed);
         };
     }
 
@@ -200,6 +302,37 @@ impl<'a> Rt<'a> {
     }
 }
 
+/// Forwards wakes to `inner`, recording that they happened.
+struct Tracked {
+    inner: Waker,
+    woken: Arc<AtomicBool>,
+}
+
+impl Wake for Tracked {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Relaxed);
+        self.inner.wake_by_ref();
+    }
+}
+
+/// Wraps `fut`, counting each time it is polled in `polls`.
+fn counted<F>(polls: &Rc<Cell<u64>>, fut: F) -> impl Future<Output = Result>
+where
+    F: Future<Output = Result> + 'static,
+{
+    let polls = polls.clone();
+    let mut fut = Box::pin(fut);
+
+    poll_fn(move |cx| {
+        polls.set(polls.get() + 1);
+        fut.as_mut().poll(cx)
+    })
+}
+
 fn init() -> (Runtime, LocalSet) {
     let mut builder = tokio::runtime::Builder::new_current_thread();
 
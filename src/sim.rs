@@ -1,13 +1,40 @@
-use crate::{for_pairs, Config, LinksIter, Result, Rt, ToIpAddr, ToIpAddrs, World, TRACING_TARGET};
-
+use crate::envelope::{Datagram, Envelope, Protocol, Segment, Syn};
+use crate::net::{TcpListener, TcpStream};
+use crate::trace::{Packet, Trace};
+use crate::{
+    for_pairs, Config, DecisionLog, Delivery, EventMatcher, HostReport, Icmp, LinksIter, Result,
+    Rt, Tap, ToIpAddr, ToIpAddrs, ToSocketAddrs, TopologyReport, World, TRACING_TARGET,
+};
+
+use bytes::Bytes;
 use indexmap::IndexMap;
+use rand::seq::SliceRandom;
 use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::DerefMut;
-use std::time::UNIX_EPOCH;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 use tokio::time::Duration;
 
+/// Whether a host has runnable tasks, passed to the callback set with
+/// [`Builder::on_host_state`](crate::Builder::on_host_state).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostState {
+    /// At least one task was runnable in the last tick.
+    Busy,
+
+    /// All tasks are blocked, waiting on I/O or timers.
+    Idle,
+}
+
+/// The longest the `Sim` helpers that step until a condition holds wait,
+/// which bounds them when the simulation runs forever.
+const MAX_WAIT: Duration = Duration::from_secs(10 * 60);
+
 /// A handle for interacting with the simulation.
 pub struct Sim<'a> {
     /// Simulation configuration
@@ -21,27 +48,113 @@ pub struct Sim<'a> {
     /// Per simulated host runtimes
     rts: IndexMap<IpAddr, Rt<'a>>,
 
+    /// How many runtimes (in registration order) have been ticked at least
+    /// once.
+    started: usize,
+
     /// Simulation duration since unix epoch. Set when the simulation is
     /// created.
     since_epoch: Duration,
 
     /// Simulation elapsed time
     elapsed: Duration,
+
+    /// Packets waiting to be replayed, ordered by send time
+    replay: VecDeque<Packet>,
+
+    /// Real runtime for tasks spawned with [`Sim::spawn_external`], created on
+    /// first use.
+    external: Option<Runtime>,
+
+    /// Whether stepping is paused. See [`Sim::pause`].
+    paused: bool,
+
+    /// When each draining host is crashed. See [`Sim::drain_then_crash`].
+    drains: IndexMap<IpAddr, Duration>,
+
+    /// Active SYN floods. See [`Sim::syn_flood`].
+    floods: Vec<SynFlood>,
+
+    /// Actions waiting to fire, ordered by when. See [`Sim::schedule`].
+    scheduled: VecDeque<(Duration, FaultAction)>,
+}
+
+/// A fault, or the heal of one, applied at a scheduled time with
+/// [`Sim::schedule`]. Hosts are given by name.
+#[derive(Clone, Debug)]
+pub enum FaultAction {
+    /// See [`partition`](crate::partition).
+    Partition(String, String),
+
+    /// See [`repair`](crate::repair).
+    Repair(String, String),
+
+    /// See [`hold`](crate::hold).
+    Hold(String, String),
+
+    /// See [`release`](crate::release).
+    Release(String, String),
+
+    /// See [`Sim::crash`].
+    Crash(String),
+
+    /// See [`Sim::kill`].
+    Kill(String),
+
+    /// See [`Sim::bounce`].
+    Bounce(String),
+
+    /// See [`Sim::set_link_latency`].
+    SetLinkLatency(String, String, Duration),
+
+    /// See [`Sim::set_link_fail_rate`].
+    SetLinkFailRate(String, String, f64),
+}
+
+struct SynFlood {
+    from: IpAddr,
+    target: SocketAddr,
+
+    /// SYNs sent per second
+    rate: f64,
+
+    /// When the flood stops
+    until: Duration,
+
+    /// SYNs due, but not yet sent
+    owed: f64,
+
+    /// The source port of the next SYN
+    next_port: u16,
 }
 
 impl<'a> Sim<'a> {
-    pub(crate) fn new(config: Config, world: World) -> Self {
+    pub(crate) fn new(config: Config, mut world: World, replay: Trace) -> Self {
         let since_epoch = config
             .epoch
             .duration_since(UNIX_EPOCH)
             .expect("now must be >= UNIX_EPOCH");
 
+        if config.capture {
+            world.topology.captured = Some(vec![]);
+        }
+
+        world.topology.record_latencies = config.record_latencies;
+        world.topology.max_messages_per_tick = config.max_messages_per_tick;
+
         Self {
             config,
             world: RefCell::new(world),
             rts: IndexMap::new(),
+            started: 0,
             since_epoch,
             elapsed: Duration::ZERO,
+            replay: replay.into_packets().into(),
+            external: None,
+            paused: false,
+            drains: IndexMap::new(),
+            floods: vec![],
+            scheduled: VecDeque::new(),
         }
     }
 
@@ -50,6 +163,17 @@ impl<'a> Sim<'a> {
         self.elapsed
     }
 
+    /// The host whose software failed the last step, if any.
+    ///
+    /// A failing host's tick returns, or unwinds, before the current host is
+    /// unset, so it is still current.
+    pub(crate) fn failed_host(&self) -> Option<String> {
+        let world = self.world.borrow();
+        world
+            .current
+            .map(|addr| world.dns.reverse(addr).to_string())
+    }
+
     /// The logical duration from [`UNIX_EPOCH`] until now.
     ///
     /// On creation the simulation picks a `SystemTime` and calculates the
@@ -59,6 +183,49 @@ impl<'a> Sim<'a> {
         self.since_epoch + self.elapsed
     }
 
+    /// How much logical time remains before the simulation times out.
+    ///
+    /// Returns [`Duration::ZERO`] once the configured duration has passed, and
+    /// [`Duration::MAX`] with [`Builder::run_forever`](crate::Builder::run_forever).
+    pub fn remaining(&self) -> Duration {
+        if self.config.duration == Duration::MAX {
+            return Duration::MAX;
+        }
+
+        self.config.duration.saturating_sub(self.elapsed)
+    }
+
+    /// Extend how long the simulation runs for by `value`.
+    ///
+    /// This is safe to call between steps, and is useful when a test discovers
+    /// it needs more time based on observed progress.
+    pub fn extend(&mut self, value: Duration) {
+        self.config.duration = self.config.duration.saturating_add(value);
+    }
+
+    /// Freeze the simulation, e.g. to inspect state from a [`SimHandle`]
+    /// while debugging.
+    ///
+    /// While paused, [`Sim::step`] does nothing: time doesn't advance, so no
+    /// timers fire, no messages are delivered, and no software runs.
+    /// [`Sim::run`] fails rather than spinning forever.
+    ///
+    /// [`SimHandle`]: crate::SimHandle
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resume a simulation frozen with [`Sim::pause`]. It continues from
+    /// exactly where it was paused.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Whether the simulation is paused. See [`Sim::pause`].
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
     /// Register a client with the simulation.
     pub fn client<F>(&mut self, addr: impl ToIpAddr, client: F)
     where
@@ -103,6 +270,58 @@ impl<'a> Sim<'a> {
         self.rts.insert(addr, rt);
     }
 
+    /// Register a host that relays TCP connections accepted on `port` to
+    /// `upstream`, as a proxy would.
+    ///
+    /// Each accepted connection is forwarded over its own upstream connection,
+    /// so traffic crosses both links and is subject to the latency and faults
+    /// of each. When either side shuts down, the shutdown is forwarded to the
+    /// other, and if the upstream can't be reached, the accepted connection is
+    /// reset. The relay is a regular host, so it can be crashed or bounced.
+    pub fn add_relay<A>(&mut self, addr: impl ToIpAddr, port: u16, upstream: A)
+    where
+        A: ToSocketAddrs + Clone + 'static,
+    {
+        self.host(addr, move || relay(port, upstream.clone()));
+    }
+
+    /// Register a host that serves HTTP/1.1 on `port`, responding to every
+    /// request with its body.
+    ///
+    /// Connections are served concurrently, and kept alive unless the client
+    /// asks for them to be closed. Request bodies must be sized with
+    /// `Content-Length`; chunked requests get a `501 Not Implemented` and
+    /// malformed ones a `400 Bad Request`. The server is a regular host, so it
+    /// can be crashed or bounced.
+    #[cfg(feature = "http")]
+    pub fn add_http_echo(&mut self, addr: impl ToIpAddr, port: u16) {
+        self.host(addr, move || crate::http::echo(port));
+    }
+
+    /// Spawn a task on a real, multi-threaded runtime that runs alongside the
+    /// simulation.
+    ///
+    /// This is useful for bridging with external test infrastructure, e.g.
+    /// serving assertions over a real channel. External tasks run on real time
+    /// and are **not** deterministic. They must not use the simulated network
+    /// or any other turmoil API, as they run outside of the simulation, but may
+    /// observe state shared with hosts through `Send` types.
+    pub fn spawn_external<F>(&mut self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.external
+            .get_or_insert_with(|| {
+                tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(1)
+                    .enable_all()
+                    .build()
+                    .expect("failed to build external runtime")
+            })
+            .spawn(future)
+    }
+
     /// Crashes the resolved hosts. Nothing will be running on the matched hosts
     /// after this method. You can use [`Sim::bounce`] to start the hosts up
     /// again.
@@ -110,10 +329,150 @@ impl<'a> Sim<'a> {
         self.run_with_hosts(addrs, |addr, rt| {
             rt.crash();
 
+            World::current(|world| world.hosts[&addr].tcp.draining = false);
+
+            #[cfg(feature = "storage")]
+            World::current(|world| world.hosts[&addr].storage.crash());
+
             tracing::trace!(target: TRACING_TARGET, addr = ?addr, "Crash");
         });
     }
 
+    /// Crashes every host in the failure domain `name`, in the same step. See
+    /// [`Builder::failure_domain`](crate::Builder::failure_domain).
+    pub fn crash_domain(&mut self, name: &str) {
+        for host in self.domain(name) {
+            self.crash(host);
+        }
+    }
+
+    /// Partitions every host in the failure domain `name` from every
+    /// registered host outside of it. Links within the domain are unaffected.
+    ///
+    /// Hosts registered after this call are not partitioned.
+    pub fn partition_domain(&mut self, name: &str) {
+        let (members, others) = self.domain_split(name);
+        let world = self.world.get_mut();
+
+        for_pairs(&members, &others, |a, b| world.partition(a, b));
+    }
+
+    /// The opposite of [`Sim::partition_domain`].
+    pub fn repair_domain(&mut self, name: &str) {
+        let (members, others) = self.domain_split(name);
+        let world = self.world.get_mut();
+
+        for_pairs(&members, &others, |a, b| world.repair(a, b));
+    }
+
+    fn domain(&mut self, name: &str) -> Vec<IpAddr> {
+        let hosts = self
+            .config
+            .failure_domains
+            .get(name)
+            .unwrap_or_else(|| panic!("unknown failure domain `{name}`"));
+        let world = self.world.get_mut();
+
+        hosts
+            .iter()
+            .map(|host| world.lookup(host.as_str()))
+            .collect()
+    }
+
+    /// The hosts in failure domain `name`, and the registered hosts outside
+    /// of it.
+    fn domain_split(&mut self, name: &str) -> (Vec<IpAddr>, Vec<IpAddr>) {
+        let members = self.domain(name);
+        let others = self
+            .world
+            .get_mut()
+            .hosts
+            .keys()
+            .filter(|addr| !members.contains(addr))
+            .copied()
+            .collect();
+
+        (members, others)
+    }
+
+    /// Kills the resolved hosts, modeling a silent death such as power loss.
+    ///
+    /// Unlike [`Sim::crash`], sockets are discarded without notifying peers,
+    /// leaving them with half-open connections. Writes from peers are sent and
+    /// never acknowledged, and reads block until a timeout detects the death.
+    /// You can use [`Sim::bounce`] to start the hosts up again.
+    pub fn kill(&mut self, addrs: impl ToIpAddrs) {
+        let hosts = self.world.borrow_mut().lookup_many(addrs);
+        for h in hosts {
+            let rt = self.rts.get_mut(&h).expect("missing host");
+
+            // Crash outside of the world, which makes socket drops a no-op
+            rt.crash();
+
+            let mut world = self.world.borrow_mut();
+            world.hosts[&h].discard_sockets();
+            world.hosts[&h].discard_timers();
+            #[cfg(feature = "storage")]
+            world.hosts[&h].storage.crash();
+
+            tracing::trace!(target: TRACING_TARGET, addr = ?h, "Kill");
+        }
+    }
+
+    /// Resets the network stack of `host`, modeling e.g. a NIC reset, while
+    /// its software keeps running.
+    ///
+    /// All of the host's sockets are discarded and its peers' connections are
+    /// reset. Sockets the software still holds fail: reads and writes on
+    /// streams with [`io::ErrorKind::ConnectionReset`] or
+    /// [`io::ErrorKind::BrokenPipe`], and accepts and receives with
+    /// [`io::ErrorKind::ConnectionAborted`]. Their addresses are free to be
+    /// bound again. Unlike [`Sim::crash`], the host's in-memory state and
+    /// timers are kept.
+    ///
+    /// [`io::ErrorKind::ConnectionReset`]: std::io::ErrorKind::ConnectionReset
+    /// [`io::ErrorKind::BrokenPipe`]: std::io::ErrorKind::BrokenPipe
+    /// [`io::ErrorKind::ConnectionAborted`]: std::io::ErrorKind::ConnectionAborted
+    pub fn reset_network(&mut self, host: impl ToIpAddr) {
+        let world = self.world.get_mut();
+        let host = world.lookup(host);
+
+        let pairs = world
+            .hosts
+            .get_mut(&host)
+            .expect("missing host")
+            .reset_network();
+
+        for pair in pairs {
+            world.send_message(pair.local, pair.remote, Protocol::Tcp(Segment::Rst));
+        }
+
+        tracing::trace!(target: TRACING_TARGET, addr = ?host, "Reset network");
+    }
+
+    /// Drain `host`, then crash it after `grace`, modeling a deploy that
+    /// removes the host from service before stopping it.
+    ///
+    /// During the grace window new TCP connections to the host are refused,
+    /// while existing connections keep working. Once it passes, the host is
+    /// crashed abruptly: its peers' connections are reset, rather than closed
+    /// as with [`Sim::crash`]. You can use [`Sim::bounce`] to start the host up
+    /// again.
+    pub fn drain_then_crash(&mut self, host: impl ToIpAddr, grace: Duration) {
+        let world = self.world.get_mut();
+        let host = world.lookup(host);
+
+        world
+            .hosts
+            .get_mut(&host)
+            .expect("missing host")
+            .tcp
+            .draining = true;
+        self.drains.insert(host, self.elapsed + grace);
+
+        tracing::trace!(target: TRACING_TARGET, addr = ?host, ?grace, "Drain");
+    }
+
     /// Bounces the resolved hosts. The software is restarted.
     pub fn bounce(&mut self, addrs: impl ToIpAddrs) {
         self.run_with_hosts(addrs, |addr, rt| {
@@ -123,6 +482,48 @@ impl<'a> Sim<'a> {
         });
     }
 
+    /// Apply `action` once the simulation has run for `at`, at the start of
+    /// the first step from then.
+    ///
+    /// This describes a chaos experiment as a timeline of faults and heals,
+    /// rather than interleaving them with stepping. Actions scheduled for the
+    /// same time fire in the order they were scheduled, and actions scheduled
+    /// in the past fire on the next step.
+    pub fn schedule(&mut self, at: Duration, action: FaultAction) {
+        let index = self.scheduled.partition_point(|(when, _)| *when <= at);
+        self.scheduled.insert(index, (at, action));
+    }
+
+    /// Apply the scheduled actions that are due.
+    fn fire_scheduled(&mut self) {
+        while self
+            .scheduled
+            .front()
+            .is_some_and(|(at, _)| *at <= self.elapsed)
+        {
+            let (_, action) = self.scheduled.pop_front().unwrap();
+
+            tracing::trace!(target: TRACING_TARGET, ?action, "Scheduled");
+
+            match action {
+                FaultAction::Partition(a, b) => self.enter(|| crate::partition(a, b)),
+                FaultAction::Repair(a, b) => self.enter(|| crate::repair(a, b)),
+                FaultAction::Hold(a, b) => self.enter(|| crate::hold(a, b)),
+                FaultAction::Release(a, b) => self.enter(|| crate::release(a, b)),
+                FaultAction::Crash(host) => self.crash(host),
+                FaultAction::Kill(host) => self.kill(host),
+                FaultAction::Bounce(host) => self.bounce(host),
+                FaultAction::SetLinkLatency(a, b, value) => self.set_link_latency(a, b, value),
+                FaultAction::SetLinkFailRate(a, b, value) => self.set_link_fail_rate(a, b, value),
+            }
+        }
+    }
+
+    /// Run `f` with the world set, but no current host.
+    pub(crate) fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        World::enter(&self.world, f)
+    }
+
     /// Run `f` with the resolved hosts at `addrs` set on the world.
     fn run_with_hosts(&mut self, addrs: impl ToIpAddrs, mut f: impl FnMut(IpAddr, &mut Rt)) {
         let hosts = self.world.borrow_mut().lookup_many(addrs);
@@ -137,11 +538,408 @@ impl<'a> Sim<'a> {
         self.world.borrow_mut().current = None;
     }
 
+    /// Migrate the UDP peer at `old_peer` to `new_peer`, as seen from `host`.
+    ///
+    /// Datagrams the host sends to `old_peer` are rerouted to `new_peer`,
+    /// which allows testing that software follows the migration without
+    /// rebinding its sockets. Sockets connected to `old_peer` are connected to
+    /// `new_peer` instead, so they receive its replies.
+    pub fn migrate(
+        &mut self,
+        host: impl ToIpAddr,
+        old_peer: impl ToSocketAddrs,
+        new_peer: impl ToSocketAddrs,
+    ) {
+        let mut world = self.world.borrow_mut();
+        let host = world.lookup(host);
+        let old_peer = old_peer.to_socket_addr(&world.dns);
+        let new_peer = new_peer.to_socket_addr(&world.dns);
+
+        world
+            .hosts
+            .get_mut(&host)
+            .expect("missing host")
+            .udp
+            .migrate(old_peer, new_peer);
+    }
+
+    /// How long `host` has spent blocked, with none of its tasks runnable
+    /// while it waits on I/O or timers.
+    ///
+    /// Time is accumulated a tick at a time, across restarts of the host's
+    /// software. Comparing hosts helps to find the bottleneck in a protocol,
+    /// e.g. which node a slow consensus round is waiting on.
+    pub fn blocked_time(&self, host: impl ToIpAddr) -> Duration {
+        let host = self.world.borrow_mut().lookup(host);
+        self.rts.get(&host).expect("missing host").blocked()
+    }
+
+    /// How many times each host's software has been polled, keyed by host
+    /// name.
+    ///
+    /// Only the top level future passed to [`Sim::host`] or [`Sim::client`]
+    /// is counted, across restarts; tasks it spawns are not. Alongside
+    /// [`Sim::blocked_time`], this shows how often each host was scheduled
+    /// versus how long it waited.
+    pub fn poll_counts(&self) -> HashMap<String, u64> {
+        let world = self.world.borrow();
+
+        self.rts
+            .iter()
+            .map(|(addr, rt)| (world.dns.reverse(*addr).to_string(), rt.polls()))
+            .collect()
+    }
+
+    /// The deadlines of `host`'s pending [`turmoil::sleep`](crate::sleep) and
+    /// [`turmoil::timeout`](crate::timeout) calls, ordered from soonest to
+    /// latest.
+    ///
+    /// This is useful for explaining why a host isn't making progress. Timers
+    /// created directly with tokio, such as [`tokio::time::sleep`],
+    /// [`tokio::time::timeout`] or [`tokio::time::interval`], aren't included,
+    /// as tokio's timer wheel can't be inspected.
+    pub fn pending_timers(&self, host: impl ToIpAddr) -> Vec<SystemTime> {
+        let mut world = self.world.borrow_mut();
+        let host = world.lookup(host);
+        let now = UNIX_EPOCH + self.since_epoch();
+
+        world.hosts[&host]
+            .pending_timers()
+            .into_iter()
+            .map(|remaining| now + remaining)
+            .collect()
+    }
+
+    /// When the next scheduled event fires: the earliest of message delivery,
+    /// a pending [`sleep`](crate::sleep) or [`timeout`](crate::timeout) on any
+    /// host, a [`Sim::schedule`]d fault, or a
+    /// [`Builder::replay`](crate::Builder::replay)ed packet.
+    ///
+    /// External drivers can use this to sleep in real time, or skip ahead,
+    /// until there is work to do. Held messages aren't included. Returns
+    /// `None` if no deliveries, turmoil timers, faults or replayed packets are
+    /// pending. Timers created directly with tokio, such as
+    /// [`tokio::time::sleep`], [`tokio::time::timeout`] or
+    /// [`tokio::time::interval`], aren't visible, so a host may still have
+    /// work to do when this returns `None`.
+    pub fn next_event_time(&self) -> Option<SystemTime> {
+        let world = self.world.borrow();
+
+        // The network runs a tick ahead of hosts, so messages reach hosts a
+        // tick before they are deliverable on the link
+        let delivery = world
+            .topology
+            .next_delivery_in()
+            .map(|wait| wait.saturating_sub(self.config.tick));
+        let timers = world
+            .hosts
+            .values()
+            .filter_map(|host| host.pending_timers().first().copied());
+        let scheduled = self
+            .scheduled
+            .front()
+            .map(|(at, _)| at.saturating_sub(self.elapsed));
+        let replay = self
+            .replay
+            .front()
+            .map(|packet| packet.at.saturating_sub(self.elapsed));
+
+        let next = delivery
+            .into_iter()
+            .chain(timers)
+            .chain(scheduled)
+            .chain(replay)
+            .min()?;
+
+        Some(UNIX_EPOCH + self.since_epoch() + next)
+    }
+
+    /// The number of TCP connections `host` has accepted that are still open,
+    /// i.e. whose stream has not been dropped or reset.
+    ///
+    /// Connections `host` opened itself aren't counted.
+    pub fn connection_count(&self, host: impl ToIpAddr) -> usize {
+        let mut world = self.world.borrow_mut();
+        let host = world.lookup(host);
+
+        world.hosts[&host].tcp.accepted_count()
+    }
+
+    /// Drop packets `host` sends that `filter` rejects, modeling a host
+    /// firewall.
+    ///
+    /// The filter sees each packet as a [`Delivery`] as it is sent, including
+    /// replies from the host's network stack, e.g. TCP RSTs and ICMP errors.
+    /// Rejected packets are dropped as if lost on the link. Packets a host
+    /// sends to itself aren't filtered. Replaces any previous egress filter.
+    pub fn set_egress_filter(
+        &mut self,
+        host: impl ToIpAddr,
+        filter: impl Fn(&Delivery) -> bool + 'static,
+    ) {
+        let world = self.world.get_mut();
+        let host = world.lookup(host);
+
+        world
+            .hosts
+            .get_mut(&host)
+            .expect("missing host")
+            .egress_filter = Some(Box::new(filter));
+    }
+
+    /// Drop packets delivered to `host` that `filter` rejects, modeling a host
+    /// firewall.
+    ///
+    /// The filter runs as each packet arrives, before it's captured, so
+    /// rejected packets aren't recorded as delivered. Like
+    /// [`Sim::set_egress_filter`], packets a host sends to itself aren't
+    /// filtered. Replaces any previous ingress filter.
+    pub fn set_ingress_filter(
+        &mut self,
+        host: impl ToIpAddr,
+        filter: impl Fn(&Delivery) -> bool + 'static,
+    ) {
+        let world = self.world.get_mut();
+        let host = world.lookup(host);
+
+        world
+            .hosts
+            .get_mut(&host)
+            .expect("missing host")
+            .ingress_filter = Some(Box::new(filter));
+    }
+
+    /// Make `addr` an anycast address, served by the hosts in `members`.
+    ///
+    /// UDP datagrams sent to `addr` are delivered to the member that is
+    /// closest to the sender, i.e. with the lowest minimum link latency,
+    /// preferring members that aren't partitioned from it. Ties are broken by
+    /// the order of `members`. Members bind sockets as usual, and their replies
+    /// come from their own address.
+    ///
+    /// `addr` must not be owned by a host. Only UDP is supported.
+    pub fn anycast<A: ToIpAddr>(&mut self, addr: A, members: impl IntoIterator<Item = A>) {
+        let world = self.world.get_mut();
+        let addr = world.lookup(addr);
+        let members = members.into_iter().map(|m| world.lookup(m)).collect();
+
+        world.anycast(addr, members);
+    }
+
+    /// Resolve `name` to the closest of `replicas`, modeling GeoDNS.
+    ///
+    /// From within a host, [`lookup`](crate::lookup) and connecting or sending
+    /// to `name` use the replica closest to that host, i.e. with the lowest
+    /// minimum link latency, which accounts for zones. Replicas that aren't
+    /// partitioned from the host are preferred, and ties are broken by the
+    /// order of `replicas`. Outside of a host, `name` resolves to its first
+    /// registered replica.
+    ///
+    /// `name` is assigned an address like any other name, which must not be
+    /// owned by a host.
+    pub fn geo_dns<A: ToIpAddr>(&mut self, name: &str, replicas: impl IntoIterator<Item = A>) {
+        let world = self.world.get_mut();
+        let addr = world.lookup(name);
+        let replicas = replicas.into_iter().map(|r| world.lookup(r)).collect();
+
+        world.geo_dns(addr, replicas);
+    }
+
+    /// The simulated DNS table, as `(name, address)` entries in the order
+    /// names were assigned.
+    ///
+    /// This includes every host, and aliases added with [`Sim::add_dns`].
+    pub fn dns_entries(&self) -> Vec<(String, IpAddr)> {
+        self.world
+            .borrow()
+            .dns
+            .names()
+            .map(|(name, addr)| (name.to_string(), addr))
+            .collect()
+    }
+
+    /// Resolve `name` to `addr`, e.g. to alias a host as `"db"`.
+    ///
+    /// `addr` may itself be a name, which is resolved once, now. An existing
+    /// `name` is repointed, and later resolutions, from hosts or the sim, use
+    /// the new address immediately. Host names can't be repointed.
+    pub fn add_dns(&mut self, name: &str, addr: impl ToIpAddr) {
+        let world = self.world.get_mut();
+        let addr = world.lookup(addr);
+
+        assert!(!world.is_host_name(name), "`{name}` names a host");
+        world.dns.insert(name, addr);
+    }
+
+    /// Remove `name` from the simulated DNS table, e.g. to simulate a
+    /// record being deleted.
+    ///
+    /// Once removed, connecting or sending to `name` panics as for any
+    /// unknown name. Host names can't be removed.
+    pub fn remove_dns(&mut self, name: &str) {
+        let world = self.world.get_mut();
+
+        assert!(!world.is_host_name(name), "`{name}` names a host");
+        world.dns.remove(name);
+    }
+
+    /// Delay TCP connections to `host` by `value` between the SYN arriving and
+    /// it becoming available to `accept`, modeling a slow accept loop.
+    ///
+    /// Connecting clients wait for the delay, while pending connections build
+    /// up in the listener's backlog. The default is zero.
+    pub fn set_accept_delay(&mut self, host: impl ToIpAddr, value: Duration) {
+        let world = self.world.get_mut();
+        let host = world.lookup(host);
+
+        world
+            .hosts
+            .get_mut(&host)
+            .expect("missing host")
+            .tcp
+            .accept_delay = value;
+    }
+
+    /// Give `host` a coarse clock, so that the time its software observes with
+    /// [`elapsed`](crate::elapsed) only advances in steps of `value`, rounding
+    /// down.
+    ///
+    /// Only the host's time source is affected: timers, timeouts and message
+    /// delivery are scheduled as usual. By default, time advances with each
+    /// tick.
+    pub fn set_clock_resolution(&mut self, host: impl ToIpAddr, value: Duration) {
+        assert!(!value.is_zero(), "clock resolution must be positive");
+
+        let world = self.world.get_mut();
+        let host = world.lookup(host);
+
+        world
+            .hosts
+            .get_mut(&host)
+            .expect("missing host")
+            .clock_resolution = Some(value);
+    }
+
+    /// Flood `target` with TCP SYNs from `from`, at `rate` SYNs per second,
+    /// for `duration`.
+    ///
+    /// The SYNs are never acknowledged, but occupy the listener's backlog until
+    /// it accepts them. Once the backlog is full, further SYNs are dropped and
+    /// legitimate connects are refused. A listener that accepts as fast as the
+    /// flood arrives keeps up; combine with [`Sim::set_accept_delay`] to model
+    /// a slower one. Connects succeed again once the flood stops and the
+    /// backlog drains.
+    pub fn syn_flood(
+        &mut self,
+        target: impl ToSocketAddrs,
+        from: impl ToIpAddr,
+        rate: u32,
+        duration: Duration,
+    ) {
+        let world = self.world.get_mut();
+        let target = target.to_socket_addr(&world.dns);
+        let from = world.lookup(from);
+
+        assert!(world.hosts.contains_key(&from), "missing host");
+        assert_ne!(from, target.ip(), "a host can't flood itself");
+
+        self.floods.push(SynFlood {
+            from,
+            target,
+            rate: rate as f64,
+            until: self.elapsed + duration,
+            owed: 0.0,
+            next_port: 1024,
+        });
+    }
+
+    /// Set the latency of every storage operation on `host`. The default is
+    /// zero.
+    #[cfg(feature = "storage")]
+    pub fn set_storage_latency(&mut self, host: impl ToIpAddr, value: Duration) {
+        self.storage(host).latency = value;
+    }
+
+    /// Fail every storage operation on `host` until it is repaired with
+    /// [`Sim::repair_storage`].
+    #[cfg(feature = "storage")]
+    pub fn fail_storage(&mut self, host: impl ToIpAddr) {
+        self.storage(host).failing = true;
+    }
+
+    /// Repair storage on `host` that was failed with [`Sim::fail_storage`].
+    #[cfg(feature = "storage")]
+    pub fn repair_storage(&mut self, host: impl ToIpAddr) {
+        self.storage(host).failing = false;
+    }
+
+    #[cfg(feature = "storage")]
+    fn storage(&mut self, host: impl ToIpAddr) -> &mut crate::storage::Storage {
+        let world = self.world.get_mut();
+        let host = world.lookup(host);
+
+        &mut world.hosts.get_mut(&host).expect("missing host").storage
+    }
+
     /// Lookup an IP address by host name.
     pub fn lookup(&self, addr: impl ToIpAddr) -> IpAddr {
         self.world.borrow_mut().lookup(addr)
     }
 
+    /// The address of the registered host `name`, or `None` if no such host
+    /// has been registered.
+    ///
+    /// Unlike [`Sim::lookup`], this never assigns an address.
+    pub fn host_addr(&self, name: &str) -> Option<IpAddr> {
+        self.world.borrow().host_addr(name)
+    }
+
+    /// The name of the registered host that owns `addr`, or `None` if no host
+    /// has its IP, or nothing is bound to its port.
+    ///
+    /// A UDP socket, TCP listener or TCP connection using the port counts as
+    /// bound. Port 0 matches any port, so only the IP is checked. This tells
+    /// apart an unknown IP from a missing listener when debugging a refused
+    /// connection.
+    pub fn owner_of(&self, addr: SocketAddr) -> Option<String> {
+        let world = self.world.borrow();
+        let host = world.hosts.get(&addr.ip())?;
+
+        (addr.port() == 0 || host.is_port_assigned(addr.port()))
+            .then(|| world.dns.reverse(addr.ip()).to_string())
+    }
+
+    /// Describe the hosts, links and DNS entries of the simulation, without
+    /// running any host software.
+    pub fn describe(&self) -> TopologyReport {
+        let world = self.world.borrow();
+        let name = |addr| world.dns.reverse(addr).to_string();
+
+        TopologyReport {
+            hosts: world
+                .hosts
+                .keys()
+                .map(|&addr| HostReport {
+                    name: name(addr),
+                    addr,
+                })
+                .collect(),
+            links: world.topology.describe_links(name),
+            dns: world
+                .dns
+                .names()
+                .map(|(name, addr)| (name.to_string(), addr))
+                .collect(),
+        }
+    }
+
+    /// Render the hosts and links of the simulation as a Graphviz graph.
+    ///
+    /// See [`TopologyReport::to_dot`] for the format.
+    pub fn to_dot(&self) -> String {
+        self.describe().to_dot()
+    }
+
     /// Resolve host names for an [`IpAddr`] pair.
     ///
     /// Useful when interacting with network [links](#method.links).
@@ -181,418 +979,3015 @@ impl<'a> Sim<'a> {
         });
     }
 
-    /// Set the max message latency for any links matching `a` and `b`.
-    pub fn set_link_max_message_latency(
-        &self,
-        a: impl ToIpAddrs,
-        b: impl ToIpAddrs,
-        value: Duration,
-    ) {
+    /// Set the message latency of TCP segments for any links matching `a` and
+    /// `b`, e.g. to model TCP traffic taking a path through a proxy.
+    ///
+    /// This overrides the link latency for TCP only. UDP is unaffected.
+    pub fn set_link_tcp_latency(&self, a: impl ToIpAddrs, b: impl ToIpAddrs, value: Duration) {
         let mut world = self.world.borrow_mut();
         let a = world.lookup_many(a);
         let b = world.lookup_many(b);
 
         for_pairs(&a, &b, |a, b| {
-            world.topology.set_link_max_message_latency(a, b, value);
+            world.topology.set_link_tcp_latency(a, b, value);
         });
     }
 
-    /// Set the message latency distribution curve for all links.
+    /// Set the message latency of UDP datagrams for any links matching `a` and
+    /// `b`.
     ///
-    /// Message latency follows an exponential distribution curve. The `value`
-    /// is the lambda argument to the probability function.
-    pub fn set_message_latency_curve(&self, value: f64) {
-        self.world
-            .borrow_mut()
-            .topology
-            .set_message_latency_curve(value);
-    }
+    /// This overrides the link latency for UDP only. TCP is unaffected.
+    pub fn set_link_udp_latency(&self, a: impl ToIpAddrs, b: impl ToIpAddrs, value: Duration) {
+        let mut world = self.world.borrow_mut();
+        let a = world.lookup_many(a);
+        let b = world.lookup_many(b);
 
-    pub fn set_fail_rate(&mut self, value: f64) {
-        self.world.borrow_mut().topology.set_fail_rate(value);
+        for_pairs(&a, &b, |a, b| {
+            world.topology.set_link_udp_latency(a, b, value);
+        });
     }
 
-    pub fn set_link_fail_rate(&mut self, a: impl ToIpAddrs, b: impl ToIpAddrs, value: f64) {
+    /// Set the rate of any links matching `a` and `b`, in bytes per second.
+    /// See [`Builder::bandwidth`](crate::Builder::bandwidth).
+    pub fn set_link_bandwidth(&self, a: impl ToIpAddrs, b: impl ToIpAddrs, bytes_per_sec: u64) {
+        assert!(bytes_per_sec > 0, "bandwidth must be positive");
+
         let mut world = self.world.borrow_mut();
         let a = world.lookup_many(a);
         let b = world.lookup_many(b);
 
         for_pairs(&a, &b, |a, b| {
-            world.topology.set_link_fail_rate(a, b, value);
+            world.topology.set_link_bandwidth(a, b, bytes_per_sec);
         });
     }
 
-    /// Access a [`LinksIter`] to introspect inflight messages between hosts.
-    pub fn links(&self, f: impl FnOnce(LinksIter)) {
-        let top = &mut self.world.borrow_mut().topology;
+    /// Silently drop messages on any links matching `a` and `b` whose payload
+    /// is larger than `mtu` bytes, as a path MTU black hole would.
+    ///
+    /// Unlike a router that replies with [`Icmp::FragmentationNeeded`],
+    /// nothing tells the sender its message was dropped, so applications must
+    /// detect the failure with timeouts. This applies to TCP segments and UDP
+    /// datagrams alike; smaller messages are delivered as usual. By default,
+    /// links have no black hole, and `usize::MAX` removes one.
+    pub fn set_link_blackhole_mtu(&self, a: impl ToIpAddrs, b: impl ToIpAddrs, mtu: usize) {
+        let mut world = self.world.borrow_mut();
+        let a = world.lookup_many(a);
+        let b = world.lookup_many(b);
 
-        f(top.iter_mut())
+        for_pairs(&a, &b, |a, b| {
+            world.topology.set_link_blackhole_mtu(a, b, mtu);
+        });
     }
 
-    /// Run the simulation to completion.
+    /// Inject `icmp` into the TCP connections from hosts matching `a` to
+    /// hosts matching `b`, as if a router on the path replied with it.
     ///
-    /// Executes a simple event loop that calls [step](#method.step) each iteration,
-    /// returning early if any host software errors.
-    pub fn run(&mut self) -> Result {
-        loop {
-            let is_finished = self.step()?;
+    /// Unlike a partition, which silently drops messages, the next read or
+    /// write on each connection fails: with
+    /// [`ErrorKind::HostUnreachable`](std::io::ErrorKind::HostUnreachable)
+    /// for [`Icmp::HostUnreachable`],
+    /// [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput) for
+    /// [`Icmp::FragmentationNeeded`], and
+    /// [`ErrorKind::ConnectionRefused`](std::io::ErrorKind::ConnectionRefused)
+    /// for [`Icmp::PortUnreachable`]. The error is transient, and the
+    /// connection works as usual afterwards.
+    pub fn inject_icmp(&self, a: impl ToIpAddrs, b: impl ToIpAddrs, icmp: Icmp) {
+        let mut world = self.world.borrow_mut();
+        let a = world.lookup_many(a);
+        let b = world.lookup_many(b);
 
-            if is_finished {
-                return Ok(());
+        for_pairs(&a, &b, |a, b| {
+            if let Some(host) = world.hosts.get_mut(&a) {
+                host.tcp.inject_icmp(b, icmp);
             }
-        }
+        });
     }
 
-    /// Step the simulation.
+    /// Deliver a UDP datagram carrying `payload` to `dst`, claiming to come
+    /// from `src`, which may belong to another host or to no host at all.
     ///
-    /// Runs each host in the simulation a fixed duration configured by
-    /// `tick_duration` in the builder.
+    /// **This is a testing-only spoofing primitive**, for exercising source
+    /// address validation and source-based access control. The datagram
+    /// bypasses routing: it is delivered immediately, without a link, so link
+    /// latency, loss and partitions don't apply, though `dst`'s ingress filter
+    /// does. `recv_from` reports `src` as the sender. Replies to `src` are
+    /// routed as usual if a host owns it, and dropped otherwise.
     ///
-    /// The simulated network also steps, processing in flight messages, and
-    /// delivering them to their destination if appropriate.
+    /// Only UDP can be spoofed, as a TCP handshake can't complete without the
+    /// source's participation.
+    pub fn inject_spoofed(
+        &mut self,
+        src: SocketAddr,
+        dst: impl ToSocketAddrs,
+        payload: impl Into<Bytes>,
+    ) {
+        let world = self.world.get_mut();
+        let dst = world.resolve_socket_addr(dst);
+        let message = Protocol::Udp(Datagram(payload.into()));
+        let delivery = Delivery::of(src, dst, &message);
+
+        let host = world.hosts.get_mut(&dst.ip()).expect("missing host");
+
+        if let Some(filter) = &host.ingress_filter {
+            if !filter(&delivery) {
+                tracing::trace!(target: TRACING_TARGET, ?src, ?dst, protocol = %message, "Drop (Ingress filter)");
+                return;
+            }
+        }
+
+        if let Some(captured) = &mut world.topology.captured {
+            captured.push(delivery);
+        }
+
+        // Replies, e.g. port unreachable, have no link to return on
+        let _ = host.receive_from_network(Envelope { src, dst, message });
+    }
+
+    /// Cap the total outbound rate of the hosts matching `host`, in bytes per
+    /// second, modeling a saturated network card.
     ///
-    /// Returns whether or not all clients have completed.
-    pub fn step(&mut self) -> Result<bool> {
+    /// All messages a host sends share the cap, whatever their link, and
+    /// queue behind one another: each is delayed until the ones sent before
+    /// it have been sent, plus its size over the rate. Higher priority
+    /// datagrams go ahead of waiting ones, see
+    /// [`UdpSocket::set_priority`](crate::net::UdpSocket::set_priority). This
+    /// is on top of any link latency and bandwidth. By default, hosts are not
+    /// capped.
+    pub fn set_host_bandwidth(&self, host: impl ToIpAddrs, bytes_per_sec: u64) {
+        assert!(bytes_per_sec > 0, "bandwidth must be positive");
+
+        let mut world = self.world.borrow_mut();
+
+        for host in world.lookup_many(host) {
+            world.topology.set_host_bandwidth(host, bytes_per_sec);
+        }
+    }
+
+    /// Set the max message latency for any links matching `a` and `b`.
+    pub fn set_link_max_message_latency(
+        &self,
+        a: impl ToIpAddrs,
+        b: impl ToIpAddrs,
+        value: Duration,
+    ) {
+        let mut world = self.world.borrow_mut();
+        let a = world.lookup_many(a);
+        let b = world.lookup_many(b);
+
+        for_pairs(&a, &b, |a, b| {
+            world.topology.set_link_max_message_latency(a, b, value);
+        });
+    }
+
+    /// Set the message latency distribution curve for all links.
+    ///
+    /// Message latency follows an exponential distribution curve. The `value`
+    /// is the lambda argument to the probability function.
+    pub fn set_message_latency_curve(&self, value: f64) {
+        self.world
+            .borrow_mut()
+            .topology
+            .set_message_latency_curve(value);
+    }
+
+    pub fn set_fail_rate(&mut self, value: f64) {
+        self.world.borrow_mut().topology.set_fail_rate(value);
+    }
+
+    pub fn set_link_fail_rate(&mut self, a: impl ToIpAddrs, b: impl ToIpAddrs, value: f64) {
+        let mut world = self.world.borrow_mut();
+        let a = world.lookup_many(a);
+        let b = world.lookup_many(b);
+
+        for_pairs(&a, &b, |a, b| {
+            world.topology.set_link_fail_rate(a, b, value);
+        });
+    }
+
+    /// Gradually degrade any links matching `a` and `b`, modeling a slowly
+    /// failing cable or growing congestion.
+    ///
+    /// The fail rate and message latency change linearly from their current
+    /// values to `fail_rate` and `latency` over the window `over`, and then
+    /// hold at the targets. Both the min and max message latency move towards
+    /// `latency`.
+    pub fn degrade_link(
+        &mut self,
+        a: impl ToIpAddrs,
+        b: impl ToIpAddrs,
+        fail_rate: f64,
+        latency: Duration,
+        over: Duration,
+    ) {
+        let mut world = self.world.borrow_mut();
+        let a = world.lookup_many(a);
+        let b = world.lookup_many(b);
+
+        for_pairs(&a, &b, |a, b| {
+            world.topology.degrade_link(a, b, fail_rate, latency, over);
+        });
+    }
+
+    /// The probability that a message sent between `a` and `b` right now
+    /// would be dropped.
+    ///
+    /// This accounts for the link's current state, i.e. whether it is
+    /// partitioned, the chance of it being randomly partitioned or repaired,
+    /// and congestion drops given the messages currently in flight. Held
+    /// messages aren't dropped.
+    pub fn effective_loss(&self, a: impl ToIpAddr, b: impl ToIpAddr) -> f64 {
+        let mut world = self.world.borrow_mut();
+        let a = world.lookup(a);
+        let b = world.lookup(b);
+
+        world.topology.effective_loss(a, b)
+    }
+
+    /// The latencies of the messages delivered between `a` and `b`, in either
+    /// direction, as `(bucket, count)` pairs in ascending order.
+    ///
+    /// Each message's latency runs from when it was sent until it was
+    /// deliverable, so includes serialization and any time it was held, and is
+    /// rounded down to a 1ms bucket. Empty buckets are left out. Requires
+    /// [`Builder::record_latencies`](crate::Builder::record_latencies).
+    pub fn latency_histogram(&self, a: impl ToIpAddr, b: impl ToIpAddr) -> Vec<(Duration, u64)> {
+        let mut world = self.world.borrow_mut();
+        let a = world.lookup(a);
+        let b = world.lookup(b);
+
+        world
+            .topology
+            .latency_histogram(a, b)
+            .expect("latencies are not recorded, see `Builder::record_latencies`")
+    }
+
+    /// The number of messages sent onto the network during the current tick,
+    /// i.e. the last [`Sim::step`], across all hosts.
+    ///
+    /// Messages a host sends to itself don't use the network, so aren't
+    /// counted. With [`Builder::max_messages_per_tick`], this never exceeds
+    /// the limit, and messages deferred from the previous tick count towards
+    /// the tick they are sent in.
+    ///
+    /// [`Builder::max_messages_per_tick`]: crate::Builder::max_messages_per_tick
+    pub fn messages_this_tick(&self) -> usize {
+        self.world.borrow().topology.messages_this_tick
+    }
+
+    /// Access a [`LinksIter`] to introspect inflight messages between hosts.
+    pub fn links(&self, f: impl FnOnce(LinksIter)) {
+        let top = &mut self.world.borrow_mut().topology;
+
+        f(top.iter_mut())
+    }
+
+    /// Assert that at least one message from `from` to `to` matching
+    /// `predicate` was delivered.
+    ///
+    /// Requires [`Builder::capture`](crate::Builder::capture), and may be
+    /// called after the simulation has run, e.g. to check that a retry
+    /// actually went out.
+    pub fn assert_delivered(
+        &self,
+        from: impl ToIpAddr,
+        to: impl ToIpAddr,
+        predicate: impl Fn(&Delivery) -> bool,
+    ) {
+        let (from, to, found) = self.find_delivered(from, to, predicate);
+
+        assert!(found, "no matching message delivered from {from} to {to}");
+    }
+
+    /// Assert that no message from `from` to `to` matching `predicate` was
+    /// delivered. See [`Sim::assert_delivered`].
+    pub fn assert_not_delivered(
+        &self,
+        from: impl ToIpAddr,
+        to: impl ToIpAddr,
+        predicate: impl Fn(&Delivery) -> bool,
+    ) {
+        let (from, to, found) = self.find_delivered(from, to, predicate);
+
+        assert!(!found, "matching message delivered from {from} to {to}");
+    }
+
+    /// Assert that each of `events` was delivered, and that the first delivery
+    /// matching each one came in the given order.
+    ///
+    /// For example, that one host received a prepare before another received
+    /// the commit. Deliveries are ordered as they were captured, which follows
+    /// simulated time and the order of delivery within a tick. Requires
+    /// [`Builder::capture`](crate::Builder::capture), and is typically called
+    /// after the simulation has run.
+    pub fn assert_ordered(&self, events: &[EventMatcher]) {
+        let mut world = self.world.borrow_mut();
+
+        let resolved: Vec<_> = events
+            .iter()
+            .map(|event| {
+                let (from, to) = event.hosts();
+                (from.map(|h| world.lookup(h)), to.map(|h| world.lookup(h)))
+            })
+            .collect();
+
+        let captured = world
+            .topology
+            .captured
+            .as_ref()
+            .expect("capture is not enabled, see `Builder::capture`");
+
+        let mut prev: Option<(usize, usize)> = None;
+        for (i, (event, (from, to))) in events.iter().zip(resolved).enumerate() {
+            let pos = captured
+                .iter()
+                .position(|d| event.matches(d, from, to))
+                .unwrap_or_else(|| panic!("no delivery matches event {i}: {event:?}"));
+
+            if let Some((prev_i, prev_pos)) = prev {
+                assert!(
+                    prev_pos < pos,
+                    "event {i} was delivered before event {prev_i}: {:?} came first",
+                    captured[pos]
+                );
+            }
+
+            prev = Some((i, pos));
+        }
+    }
+
+    fn find_delivered(
+        &self,
+        from: impl ToIpAddr,
+        to: impl ToIpAddr,
+        predicate: impl Fn(&Delivery) -> bool,
+    ) -> (IpAddr, IpAddr, bool) {
+        let mut world = self.world.borrow_mut();
+        let from = world.lookup(from);
+        let to = world.lookup(to);
+
+        let captured = world
+            .topology
+            .captured
+            .as_ref()
+            .expect("capture is not enabled, see `Builder::capture`");
+
+        let found = captured
+            .iter()
+            .any(|d| d.src.ip() == from && d.dst.ip() == to && predicate(d));
+
+        (from, to, found)
+    }
+
+    /// Run the simulation with capture enabled, returning the result and every
+    /// delivered message. See [`assert_deterministic`](crate::assert_deterministic).
+    pub(crate) fn run_captured(&mut self) -> (Result, Vec<Delivery>) {
+        self.world
+            .get_mut()
+            .topology
+            .captured
+            .get_or_insert_with(Vec::new);
+
+        let result = self.run();
+        let captured = self.world.get_mut().topology.captured.take();
+
+        (result, captured.unwrap_or_default())
+    }
+
+    /// Start recording every loss and latency decision the network makes, for
+    /// replay with [`Builder::replay_decisions`](crate::Builder::replay_decisions).
+    ///
+    /// Call before the run, e.g. right after building. Recording continues
+    /// until the simulation is dropped; read the log with
+    /// [`Sim::recorded_decisions`].
+    pub fn record_decisions(&mut self) {
+        self.world
+            .get_mut()
+            .topology
+            .decisions
+            .recorded
+            .get_or_insert_with(DecisionLog::new);
+    }
+
+    /// The decisions recorded so far.
+    ///
+    /// Panics if recording wasn't started with [`Sim::record_decisions`].
+    pub fn recorded_decisions(&self) -> DecisionLog {
+        self.world
+            .borrow()
+            .topology
+            .decisions
+            .recorded
+            .clone()
+            .expect("decisions are not recorded, see `Sim::record_decisions`")
+    }
+
+    /// Tap the TCP connections between `a` and `b`, observing the bytes that
+    /// flow in each direction. See [`Tap`].
+    ///
+    /// Replaces any existing tap between the hosts.
+    pub fn tap(&mut self, a: impl ToIpAddr, b: impl ToIpAddr) -> Tap {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let world = self.world.get_mut();
+        let a = world.lookup(a);
+        let b = world.lookup(b);
+        world.topology.tap(a, b, tx);
+
+        Tap::new(rx)
+    }
+
+    /// Remove the tap between `a` and `b`, if any.
+    pub fn untap(&mut self, a: impl ToIpAddr, b: impl ToIpAddr) {
+        let world = self.world.get_mut();
+        let a = world.lookup(a);
+        let b = world.lookup(b);
+        world.topology.untap(a, b);
+    }
+
+    /// Run the simulation to completion.
+    ///
+    /// Executes a simple event loop that calls [step](#method.step) each iteration,
+    /// returning early if any host software errors.
+    pub fn run(&mut self) -> Result {
+        if self.paused {
+            return Err("Simulation is paused")?;
+        }
+
+        loop {
+            let is_finished = self.step()?;
+
+            if is_finished {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Step the simulation until every message currently in flight has been
+    /// delivered, so a test can let the network settle between phases.
+    ///
+    /// Messages are delivered in order, all in a single step that advances
+    /// time to the latest delivery, rounded up to a whole tick. Hosts aren't
+    /// stepped through each tick in between. Messages sent while flushing are
+    /// not waited for, and held messages, which aren't in flight, stay held.
+    /// Like [`Sim::step`], this does nothing while the simulation is paused.
+    pub fn flush(&mut self) -> Result {
+        if self.paused {
+            return Ok(());
+        }
+
+        let Some(wait) = self.world.get_mut().topology.flush() else {
+            return Ok(());
+        };
+
         let tick = self.config.tick;
+        let ticks = wait.as_nanos().div_ceil(tick.as_nanos()).max(1);
+        self.step_by(Duration::from_nanos((ticks * tick.as_nanos()) as u64))?;
 
-        let mut is_finished = true;
+        Ok(())
+    }
 
-        // Tick the networking, processing messages. This is done before
-        // ticking any other runtime, as they might be waiting on network
-        // IO. (It also might be waiting on something else, such as time.)
-        self.world.borrow_mut().topology.tick_by(tick);
+    /// Step the simulation until no messages are in flight, in either
+    /// direction, between `a` and `b`, returning how long that took.
+    ///
+    /// Unlike [`Sim::flush`], messages sent while stepping are waited for, so
+    /// this synchronizes a test with the end of an exchange between two hosts,
+    /// regardless of traffic elsewhere. Held messages aren't in flight, and
+    /// messages sent over a partitioned link are dropped, so neither is waited
+    /// for. Returns an error if traffic doesn't stop within the remaining
+    /// simulation duration, or 10 minutes, whichever is sooner.
+    pub fn await_quiescent_between(
+        &mut self,
+        a: impl ToIpAddr,
+        b: impl ToIpAddr,
+    ) -> Result<Duration> {
+        let a = self.lookup(a);
+        let b = self.lookup(b);
+        let start = self.elapsed;
+        let limit = self.remaining().min(MAX_WAIT);
 
-        // Tick each host runtimes with running software. If the software
-        // completes, extract the result and return early if an error is
-        // encountered.
-        for (&addr, rt) in self
-            .rts
-            .iter_mut()
-            .filter(|(_, rt)| rt.is_software_running())
-        {
-            {
-                let mut world = self.world.borrow_mut();
-                // We need to move deliverable messages off the network and
-                // into the dst host. This requires two mutable borrows.
-                let World {
-                    rng,
-                    topology,
-                    hosts,
-                    ..
-                } = world.deref_mut();
-                topology.deliver_messages(rng, hosts.get_mut(&addr).expect("missing host"));
+        loop {
+            if !self.world.borrow().topology.is_in_flight(a, b) {
+                return Ok(self.elapsed - start);
+            }
 
-                // Set the current host (see method docs)
-                world.current = Some(addr);
+            if self.elapsed - start > limit {
+                return Err(format!(
+                    "traffic between {a} and {b} did not stop within {limit:?}"
+                ))?;
+            }
 
-                world.current_host_mut().now(rt.now());
+            self.step()?;
+        }
+    }
+
+    /// Step the simulation until a TCP connection from `from` to `to` on
+    /// `port` would succeed, returning how long that took.
+    ///
+    /// `to` is reachable once a listener is bound to `port` and the link
+    /// between the hosts isn't partitioned. This is useful for waiting for a
+    /// server to be ready from test harness code, without sleeping in host
+    /// software. Returns an error if `to` is not reachable within the
+    /// remaining simulation duration, or 10 minutes, whichever is sooner.
+    pub fn wait_reachable(
+        &mut self,
+        from: impl ToIpAddr,
+        to: impl ToIpAddr,
+        port: u16,
+    ) -> Result<Duration> {
+        let from = self.lookup(from);
+        let to = self.lookup(to);
+        let start = self.elapsed;
+        let limit = self.remaining().min(MAX_WAIT);
+
+        loop {
+            if self.is_reachable(from, to, port) {
+                return Ok(self.elapsed - start);
             }
 
-            let is_software_finished = World::enter(&self.world, || rt.tick(tick))?;
+            if self.elapsed - start > limit {
+                return Err(format!(
+                    "{to}:{port} not reachable from {from} within {limit:?}"
+                ))?;
+            }
 
-            if rt.is_client() {
-                is_finished = is_finished && is_software_finished;
+            self.step()?;
+        }
+    }
+
+    fn is_reachable(&self, from: IpAddr, to: IpAddr, port: u16) -> bool {
+        let world = self.world.borrow();
+
+        let is_bound = world
+            .hosts
+            .get(&to)
+            .is_some_and(|host| host.tcp.is_bound((to, port).into()));
+
+        is_bound
+            && (from == to
+                || (world.hosts.contains_key(&from) && !world.topology.is_partitioned(from, to)))
+    }
+
+    /// Abruptly crash draining hosts whose grace window has passed.
+    fn finish_drains(&mut self) {
+        let elapsed = self.elapsed;
+        let due = self
+            .drains
+            .iter()
+            .filter(|(_, deadline)| **deadline <= elapsed)
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+
+        for h in due {
+            self.drains.shift_remove(&h);
+
+            // The host was crashed or killed during the grace window
+            if !self.world.borrow().hosts[&h].tcp.draining {
+                continue;
             }
 
-            // Unset the current host
+            let rt = self.rts.get_mut(&h).expect("missing host");
+
+            // Crash outside of the world, which makes socket drops a no-op
+            rt.crash();
+
             let mut world = self.world.borrow_mut();
-            world.current = None;
+            for pair in world.hosts[&h].tcp.stream_pairs() {
+                world.send_message(pair.local, pair.remote, Protocol::Tcp(Segment::Rst));
+            }
 
-            world.tick(addr, tick);
+            world.hosts[&h].discard_sockets();
+            world.hosts[&h].discard_timers();
+            #[cfg(feature = "storage")]
+            world.hosts[&h].storage.crash();
+
+            tracing::trace!(target: TRACING_TARGET, addr = ?h, "Drained");
         }
+    }
 
-        self.elapsed += tick;
+    /// Send any replayed packets that are due.
+    fn replay_packets(&mut self) {
+        let world = self.world.get_mut();
 
-        if self.elapsed > self.config.duration && !is_finished {
-            return Err(format!(
-                "Ran for {:?} without completing",
-                self.config.duration
-            ))?;
-        }
+        while let Some(packet) = self.replay.front() {
+            if packet.at > self.elapsed {
+                break;
+            }
 
-        Ok(is_finished)
+            let Packet {
+                src, dst, payload, ..
+            } = self.replay.pop_front().unwrap();
+
+            if src.ip() == dst.ip()
+                || !world.hosts.contains_key(&src.ip())
+                || !world.hosts.contains_key(&dst.ip())
+            {
+                tracing::warn!(target: TRACING_TARGET, ?src, ?dst, "Replay skipped (unroutable)");
+                continue;
+            }
+
+            world.send_message(src, dst, Protocol::Udp(Datagram(payload)));
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::{
-        net::{IpAddr, Ipv4Addr},
-        rc::Rc,
-        sync::{
-            atomic::{AtomicU64, Ordering},
-            Arc,
-        },
-        time::Duration,
-    };
+    /// Send the SYNs due this step for each active flood, dropping floods that
+    /// have finished.
+    fn send_floods(&mut self) {
+        let world = self.world.get_mut();
+        let elapsed = self.elapsed;
+        let tick = self.config.tick;
 
-    use std::future;
-    use tokio::{
+        self.floods.retain_mut(|flood| {
+            if elapsed >= flood.until {
+                return false;
+            }
+
+            flood.owed += flood.rate * tick.as_secs_f64();
+
+            while flood.owed >= 1.0 {
+                flood.owed -= 1.0;
+
+                let src = SocketAddr::from((flood.from, flood.next_port));
+                flood.next_port = flood.next_port.checked_add(1).unwrap_or(1024);
+
+                // Dropping the receiver means the SYN is never acknowledged
+                let (ack, _) = oneshot::channel();
+                world.send_message(src, flood.target, Protocol::Tcp(Segment::Syn(Syn { ack })));
+            }
+
+            true
+        });
+    }
+
+    /// The order in which runtimes are ticked this step.
+    ///
+    /// This is registration order, unless `shuffle_startup` is enabled, in which
+    /// case runtimes that have not yet been ticked are shuffled amongst
+    /// themselves.
+    fn tick_order(&mut self) -> Vec<usize> {
+        let mut order = (0..self.rts.len()).collect::<Vec<_>>();
+
+        if self.config.shuffle_startup {
+            let rng = &mut self.world.get_mut().rng;
+            order[self.started..].shuffle(rng);
+        }
+
+        self.started = self.rts.len();
+
+        order
+    }
+
+    /// Poll only `host`, running its ready tasks once without advancing time.
+    ///
+    /// Messages that are already deliverable to the host are delivered first.
+    /// Returns whether the host's software has completed.
+    ///
+    /// This is an advanced debugging API for constructing interleavings by
+    /// hand, e.g. alongside [`hold`](crate::hold) and
+    /// [`release`](crate::release). Other hosts and the network make no
+    /// progress, so misuse can create schedules that are impossible in
+    /// practice, such as a host observing a reply before the peer was ever
+    /// polled to send it. Use [`Sim::step`] for normal use.
+    pub fn step_host(&mut self, host: impl ToIpAddr) -> Result<bool> {
+        let addr = self.world.get_mut().lookup(host);
+        let rt = self.rts.get_mut(&addr).expect("missing host");
+
+        if !rt.is_software_running() {
+            return Ok(true);
+        }
+
+        {
+            let mut world = self.world.borrow_mut();
+            let World {
+                rng,
+                topology,
+                hosts,
+                ..
+            } = world.deref_mut();
+            topology.deliver_messages(rng, hosts.get_mut(&addr).expect("missing host"));
+
+            world.current = Some(addr);
+            world.current_host_mut().now(rt.now());
+        }
+
+        World::enter(&self.world, || rt.run_ready());
+        self.world.borrow_mut().current = None;
+
+        rt.poll_software()
+    }
+
+    /// Step the simulation.
+    ///
+    /// Runs each host in the simulation a fixed duration configured by
+    /// `tick_duration` in the builder.
+    ///
+    /// The simulated network also steps, processing in flight messages, and
+    /// delivering them to their destination if appropriate.
+    ///
+    /// Returns whether or not all clients have completed. If the simulation is
+    /// [paused](Sim::pause), nothing happens and `false` is returned.
+    pub fn step(&mut self) -> Result<bool> {
+        if self.paused {
+            return Ok(false);
+        }
+
+        self.step_by(self.config.tick)
+    }
+
+    /// Step the network and each host by `tick`.
+    fn step_by(&mut self, tick: Duration) -> Result<bool> {
+        // Hosts named by a config file are checked once, as they may be
+        // registered any time before the first step
+        for name in std::mem::take(&mut self.config.referenced_hosts) {
+            if self.world.get_mut().dns.get(&name).is_none() {
+                return Err(format!("Config file names unknown host `{name}`"))?;
+            }
+        }
+
+        let mut is_finished = true;
+
+        self.fire_scheduled();
+        self.replay_packets();
+        self.send_floods();
+        self.finish_drains();
+
+        // Tick the networking, processing messages. This is done before
+        // ticking any other runtime, as they might be waiting on network
+        // IO. (It also might be waiting on something else, such as time.)
+        let world = self.world.get_mut();
+        world.topology.tick_by(&mut world.rng, tick);
+        self.world.borrow_mut().retransmit_tcp();
+        self.world.borrow_mut().reap_idle_tcp();
+
+        // Tick each host runtimes with running software. If the software
+        // completes, extract the result and return early if an error is
+        // encountered.
+        for index in self.tick_order() {
+            let (&mut addr, rt) = self.rts.get_index_mut(index).expect("missing host");
+
+            if !rt.is_software_running() {
+                continue;
+            }
+
+            {
+                let mut world = self.world.borrow_mut();
+                // We need to move deliverable messages off the network and
+                // into the dst host. This requires two mutable borrows.
+                let World {
+                    rng,
+                    topology,
+                    hosts,
+                    ..
+                } = world.deref_mut();
+                topology.deliver_messages(rng, hosts.get_mut(&addr).expect("missing host"));
+
+                // Set the current host (see method docs)
+                world.current = Some(addr);
+
+                world.current_host_mut().now(rt.now());
+            }
+
+            let was_idle = rt.is_idle();
+            let is_software_finished = World::enter(&self.world, || rt.tick(tick))?;
+
+            if rt.is_client() {
+                is_finished = is_finished && is_software_finished;
+            }
+
+            // Unset the current host
+            let mut world = self.world.borrow_mut();
+            world.current = None;
+
+            world.tick(addr, tick);
+
+            if let Some(f) = &self.config.on_host_state {
+                if rt.is_idle() != was_idle {
+                    let state = if rt.is_idle() {
+                        HostState::Idle
+                    } else {
+                        HostState::Busy
+                    };
+
+                    f.borrow_mut()(world.dns.reverse(addr), state);
+                }
+            }
+        }
+
+        // Resume hosts that yielded during this tick, now that every other
+        // host has had a turn.
+        for (&addr, rt) in self
+            .rts
+            .iter_mut()
+            .filter(|(_, rt)| rt.is_software_running())
+        {
+            let yielded = {
+                let mut world = self.world.borrow_mut();
+                let yielded = world.hosts[&addr].take_yielded();
+
+                if yielded.is_empty() {
+                    continue;
+                }
+
+                world.current = Some(addr);
+                world.current_host_mut().now(rt.now());
+
+                yielded
+            };
+
+            for waker in yielded {
+                waker.wake();
+            }
+
+            World::enter(&self.world, || rt.run_ready());
+
+            self.world.borrow_mut().current = None;
+        }
+
+        self.elapsed += tick;
+
+        if self.elapsed > self.config.duration && !is_finished {
+            return Err(format!(
+                "Ran for {:?} without completing",
+                self.config.duration
+            ))?;
+        }
+
+        // Without a deadline, a simulation that can't make progress would
+        // otherwise hang
+        if self.config.duration == Duration::MAX && !is_finished && self.is_deadlocked() {
+            return Err(format!(
+                "Deadlocked after {:?}: every host is blocked, and nothing is scheduled",
+                self.elapsed
+            ))?;
+        }
+
+        Ok(is_finished)
+    }
+
+    /// Whether no host can make progress: every host was blocked for the last
+    /// tick, and nothing is scheduled that would wake one.
+    ///
+    /// Timers created directly with tokio aren't visible, so a host waiting
+    /// only on one looks deadlocked.
+    fn is_deadlocked(&self) -> bool {
+        self.rts
+            .values()
+            .filter(|rt| rt.is_software_running())
+            .all(|rt| rt.is_idle())
+            && self.floods.is_empty()
+            && self.drains.is_empty()
+            && self.world.borrow().is_quiescent()
+            && self.next_event_time().is_none()
+    }
+}
+
+/// Accept connections on `port`, forwarding each to `upstream`.
+async fn relay<A: ToSocketAddrs + Clone + 'static>(port: u16, upstream: A) -> Result {
+    let listener = TcpListener::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)).await?;
+
+    loop {
+        let (mut downstream, _) = listener.accept().await?;
+        let upstream = upstream.clone();
+
+        tokio::task::spawn_local(async move {
+            match TcpStream::connect(upstream).await {
+                Ok(mut upstream) => {
+                    // Both streams are closed on drop, including after errors
+                    let _ = tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await;
+                }
+                Err(_) => downstream.reset(),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::{Cell, RefCell},
+        net::{IpAddr, Ipv4Addr},
+        rc::Rc,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use rand::{rngs::SmallRng, SeedableRng};
+    use std::future;
+    use tokio::{
         io::{AsyncReadExt, AsyncWriteExt},
         sync::Semaphore,
         time::Instant,
     };
 
-    use crate::{
-        elapsed, hold,
-        net::{TcpListener, TcpStream},
-        Builder, Result,
-    };
+    use crate::{
+        backoff, elapsed, hold, lookup,
+        net::{TcpListener, TcpStream, UdpSocket},
+        random, sleep, yield_now, BandwidthSharing, Builder, Decision, DecisionLog, DeliveryKind,
+        EventMatcher, FaultAction, HostState, LatencyTrace, LinkState, Result, Sim,
+    };
+
+    #[test]
+    fn client_error() {
+        let mut sim = Builder::new().build();
+
+        sim.client("doomed", async { Err("An Error")? });
+
+        assert!(sim.run().is_err());
+    }
+
+    #[test]
+    fn timeout() {
+        let mut sim = Builder::new()
+            .simulation_duration(Duration::from_millis(500))
+            .build();
+
+        sim.client("timeout", async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            Ok(())
+        });
+
+        assert!(sim.run().is_err());
+    }
+
+    #[test]
+    fn run_forever() -> Result {
+        let mut sim = Builder::new()
+            .simulation_duration(Duration::from_secs(1))
+            .run_forever()
+            .build();
+
+        sim.client("client", async {
+            sleep(Duration::from_secs(60)).await;
+
+            Ok(())
+        });
+
+        assert_eq!(Duration::MAX, sim.remaining());
+
+        sim.extend(Duration::from_secs(1));
+        sim.run()?;
+
+        assert!(sim.elapsed() >= Duration::from_secs(60));
+        assert_eq!(Duration::MAX, sim.remaining());
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_forever_deadlock() {
+        let mut sim = Builder::new().run_forever().build();
+
+        sim.host("server", || async {
+            let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            sock.recv_from(&mut [0; 8]).await?;
+
+            Ok(())
+        });
+
+        // Waits for a reply that is never sent
+        sim.client("client", async {
+            let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            sock.send_to(&[0], "server:1234").await?;
+            sock.recv_from(&mut [0; 8]).await?;
+
+            Ok(())
+        });
+
+        let err = sim.run().unwrap_err();
+        assert!(err.to_string().starts_with("Deadlocked"), "{err}");
+    }
+
+    #[test]
+    fn run_forever_wait_is_bounded() {
+        let mut sim = Builder::new()
+            .run_forever()
+            .tick_duration(Duration::from_millis(100))
+            .build();
+
+        // Busy, but never listens
+        sim.host("server", || async {
+            loop {
+                sleep(Duration::from_secs(1)).await;
+            }
+        });
+
+        sim.client("client", future::pending());
+
+        assert!(sim.wait_reachable("client", "server", 1234).is_err());
+        assert!(sim.elapsed() > Duration::from_secs(10 * 60));
+    }
+
+    #[test]
+    fn extend_duration() -> Result {
+        let tick = Duration::from_millis(100);
+        let mut sim = Builder::new()
+            .simulation_duration(Duration::from_millis(500))
+            .tick_duration(tick)
+            .build();
+
+        sim.client("client", async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            Ok(())
+        });
+
+        sim.step()?;
+        assert_eq!(Duration::from_millis(400), sim.remaining());
+
+        sim.extend(Duration::from_secs(1));
+        assert_eq!(Duration::from_millis(1400), sim.remaining());
+
+        sim.run()?;
+        assert_eq!(Duration::from_millis(400), sim.remaining());
+
+        Ok(())
+    }
+
+    #[test]
+    fn remaining_is_zero_after_deadline() {
+        let mut sim = Builder::new()
+            .simulation_duration(Duration::from_millis(10))
+            .build();
+
+        sim.client("client", async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            Ok(())
+        });
+
+        assert!(sim.run().is_err());
+        assert_eq!(Duration::ZERO, sim.remaining());
+    }
+
+    #[test]
+    fn multiple_clients_all_finish() -> Result {
+        let how_many = 3;
+        let tick_ms = 10;
+
+        // N = how_many runs, each with a different client finishing immediately
+        for run in 0..how_many {
+            let mut sim = Builder::new()
+                .tick_duration(Duration::from_millis(tick_ms))
+                .build();
+
+            let ct = Rc::new(Semaphore::new(how_many));
+
+            for client in 0..how_many {
+                let ct = ct.clone();
+
+                sim.client(format!("client-{client}"), async move {
+                    let ms = if run == client { 0 } else { 2 * tick_ms };
+                    tokio::time::sleep(Duration::from_millis(ms)).await;
+
+                    let p = ct.acquire().await?;
+                    p.forget();
+
+                    Ok(())
+                });
+            }
+
+            sim.run()?;
+            assert_eq!(0, ct.available_permits());
+        }
+
+        Ok(())
+    }
+
+    /// This is a regression test that ensures host software completes when the
+    /// host crashes. Before this fix we simply dropped the LocalSet, which did
+    /// not ensure resources owned by spawned tasks were dropped. Now we drop
+    /// and replace both the tokio Runtime and the LocalSet.
+    #[test]
+    fn crash_blocks_until_complete() -> Result {
+        let ct = Arc::new(());
+
+        let mut sim = Builder::new().build();
+
+        sim.host("host", || {
+            let ct = ct.clone();
+
+            async move {
+                tokio::spawn(async move {
+                    let _into_task = ct;
+                    future::pending::<()>().await;
+                });
+
+                future::pending().await
+            }
+        });
+
+        sim.run()?;
+        assert_eq!(2, Arc::strong_count(&ct));
+
+        sim.crash("host");
+        assert_eq!(1, Arc::strong_count(&ct));
+
+        Ok(())
+    }
+
+    #[test]
+    fn elapsed_time() -> Result {
+        let tick = Duration::from_millis(5);
+        let mut sim = Builder::new().tick_duration(tick).build();
+
+        let duration = Duration::from_millis(500);
+
+        sim.client("c1", async move {
+            tokio::time::sleep(duration).await;
+            assert_eq!(duration, elapsed());
+
+            Ok(())
+        });
+
+        sim.client("c2", async move {
+            tokio::time::sleep(duration).await;
+            assert_eq!(duration, elapsed());
+
+            Ok(())
+        });
+
+        sim.run()?;
+
+        // sleep duration plus one tick to complete
+        assert_eq!(duration + tick, sim.elapsed());
+
+        let start = sim.elapsed();
+        sim.client("c3", async move {
+            assert_eq!(Duration::ZERO, elapsed());
+
+            Ok(())
+        });
+
+        sim.run()?;
+
+        // one tick to complete
+        assert_eq!(tick, sim.elapsed() - start);
+
+        Ok(())
+    }
+
+    #[test]
+    fn clock_resolution() -> Result {
+        let mut sim = Builder::new().build();
+
+        for host in ["coarse", "fine"] {
+            sim.client(host, async move {
+                let mut seen = vec![];
+                for _ in 0..4 {
+                    tokio::time::sleep(Duration::from_millis(7)).await;
+                    seen.push(elapsed().as_millis());
+                }
+
+                // Sleeps are scheduled as usual, only the observed time is coarse
+                match host {
+                    "coarse" => assert_eq!(vec![0, 10, 20, 20], seen),
+                    _ => assert_eq!(vec![7, 14, 21, 28], seen),
+                }
+
+                Ok(())
+            });
+        }
+
+        sim.set_clock_resolution("coarse", Duration::from_millis(10));
+
+        sim.run()
+    }
+
+    #[test]
+    fn elapsed_time_across_restarts() -> Result {
+        let tick_ms = 5;
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(tick_ms))
+            .build();
+
+        let clock = Arc::new(AtomicU64::new(0));
+        let actual = clock.clone();
+
+        sim.host("host", move || {
+            let clock = clock.clone();
+
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    clock.store(elapsed().as_millis() as u64, Ordering::SeqCst);
+                }
+            }
+        });
+
+        sim.run()?;
+        assert_eq!(tick_ms - 1, actual.load(Ordering::SeqCst));
+
+        sim.bounce("host");
+        sim.run()?;
+        assert_eq!((tick_ms * 2) - 1, actual.load(Ordering::SeqCst));
+
+        Ok(())
+    }
+
+    #[test]
+    fn yield_now_runs_other_hosts_first() -> Result {
+        let mut sim = Builder::new().build();
+
+        let order = Rc::new(RefCell::new(vec![]));
+
+        let a = order.clone();
+        sim.client("a", async move {
+            yield_now().await;
+            a.borrow_mut().push("a");
+
+            Ok(())
+        });
+
+        let b = order.clone();
+        sim.client("b", async move {
+            b.borrow_mut().push("b");
+
+            Ok(())
+        });
+
+        sim.step()?;
+        assert_eq!(vec!["b", "a"], *order.borrow());
+        assert_eq!(sim.elapsed(), Duration::from_millis(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn select_picks_first_ready_branch() -> Result {
+        let mut sim = Builder::new().build();
+
+        sim.client("client", async {
+            for _ in 0..100 {
+                let branch = crate::select! {
+                    _ = future::ready(()) => 1,
+                    _ = future::ready(()) => 2,
+                };
+                assert_eq!(1, branch);
+            }
+
+            Ok(())
+        });
+
+        sim.run()
+    }
+
+    #[test]
+    fn shuffle_startup() -> Result {
+        fn startup_order(seed: u64) -> Result<Vec<usize>> {
+            let mut sim = Builder::new()
+                .shuffle_startup(true)
+                .build_with_rng(Box::new(SmallRng::seed_from_u64(seed)));
+
+            let order = Rc::new(RefCell::new(vec![]));
+
+            for i in 0..5 {
+                let order = order.clone();
+                sim.client(format!("client-{i}"), async move {
+                    order.borrow_mut().push(i);
+
+                    Ok(())
+                });
+            }
+
+            sim.run()?;
+
+            let order = order.borrow().clone();
+            Ok(order)
+        }
+
+        // Reproducible from the seed
+        assert_eq!(startup_order(1)?, startup_order(1)?);
+
+        // Not always registration order
+        assert!((0..10).any(|seed| startup_order(seed).unwrap() != vec![0, 1, 2, 3, 4]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn host_addr() -> Result {
+        let mut sim = Builder::new().build();
+
+        assert_eq!(None, sim.host_addr("client"));
+
+        sim.client("client", async {
+            assert_eq!(Some(lookup("client")), crate::host_addr("client"));
+            assert_eq!(None, crate::host_addr("unknown"));
+
+            Ok(())
+        });
+
+        assert_eq!(Some(sim.lookup("client")), sim.host_addr("client"));
+
+        // Looking up a name does not register a host
+        sim.lookup("unregistered");
+        assert_eq!(None, sim.host_addr("unregistered"));
+
+        sim.run()
+    }
+
+    #[test]
+    fn random_is_deterministic() -> Result {
+        fn ids(seed: u64) -> Result<Vec<u64>> {
+            let mut sim = Builder::new().build_with_rng(Box::new(SmallRng::seed_from_u64(seed)));
+
+            let ids = Rc::new(RefCell::new(vec![]));
+
+            for i in 0..2 {
+                let ids = ids.clone();
+                sim.client(format!("client-{i}"), async move {
+                    ids.borrow_mut().push(random());
+
+                    Ok(())
+                });
+            }
+
+            sim.run()?;
+
+            let ids = ids.borrow().clone();
+            Ok(ids)
+        }
+
+        let first = ids(1)?;
+        assert_eq!(first, ids(1)?);
+        assert_ne!(first[0], first[1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn random_ignores_registration_order() -> Result {
+        fn ids(names: [&'static str; 2]) -> Result<Vec<(&'static str, Vec<u64>)>> {
+            let mut sim = Builder::from_master_seed(7, 0).build();
+
+            let ids = Rc::new(RefCell::new(vec![]));
+
+            for name in names {
+                let ids = ids.clone();
+                sim.client(name, async move {
+                    let values = (0..3).map(|_| random()).collect();
+                    ids.borrow_mut().push((name, values));
+
+                    Ok(())
+                });
+            }
+
+            sim.run()?;
+
+            let mut ids = ids.borrow().clone();
+            ids.sort();
+            Ok(ids)
+        }
+
+        assert_eq!(ids(["a", "b"])?, ids(["b", "a"])?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn backoff_is_deterministic() -> Result {
+        fn backoffs(seed: u64) -> Result<Vec<Duration>> {
+            let mut sim = Builder::new().build_with_rng(Box::new(SmallRng::seed_from_u64(seed)));
+
+            let backoffs = Rc::new(RefCell::new(vec![]));
+
+            let set = backoffs.clone();
+            sim.client("client", async move {
+                for attempt in 0..10 {
+                    set.borrow_mut().push(backoff(attempt));
+                }
+
+                Ok(())
+            });
+
+            sim.run()?;
+
+            let backoffs = backoffs.borrow().clone();
+            Ok(backoffs)
+        }
+
+        let first = backoffs(1)?;
+        assert_eq!(first, backoffs(1)?);
+        assert_ne!(first, backoffs(2)?);
+
+        // Doubles from 100ms up to 10s, with up to half of it as jitter
+        for (attempt, backoff) in first.into_iter().enumerate() {
+            let max = Duration::from_millis(100 << attempt).min(Duration::from_secs(10));
+            assert!((max / 2..=max).contains(&backoff), "{attempt}: {backoff:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn spawn_external() -> Result {
+        let mut sim = Builder::new().build();
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let sum = sim.spawn_external(async move {
+            let mut sum = 0;
+            while let Some(n) = rx.recv().await {
+                sum += n;
+            }
+            sum
+        });
+
+        sim.client("client", async move {
+            for n in 1..=3 {
+                tx.send(n)?;
+            }
+
+            Ok(())
+        });
+
+        sim.run()?;
+
+        assert_eq!(6, futures::executor::block_on(sum)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pending_timers() -> Result {
+        let mut sim = Builder::new().build();
+        let epoch = UNIX_EPOCH + sim.since_epoch();
+
+        sim.host("host", || async {
+            // Not seen, as it isn't a turmoil timer
+            tokio::spawn(tokio::time::sleep(Duration::from_secs(5)));
+            tokio::spawn(crate::timeout(
+                Duration::from_secs(20),
+                future::pending::<()>(),
+            ));
+
+            sleep(Duration::from_secs(10)).await;
+
+            future::pending().await
+        });
+
+        sim.step()?;
+        assert_eq!(
+            vec![
+                epoch + Duration::from_secs(10),
+                epoch + Duration::from_secs(20)
+            ],
+            sim.pending_timers("host")
+        );
+
+        while sim.elapsed() <= Duration::from_secs(10) {
+            sim.step()?;
+        }
+        assert_eq!(
+            vec![epoch + Duration::from_secs(20)],
+            sim.pending_timers("host")
+        );
+
+        sim.bounce("host");
+        sim.step()?;
+        assert_eq!(2, sim.pending_timers("host").len());
+
+        sim.crash("host");
+        assert!(sim.pending_timers("host").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn next_event_time() -> Result {
+        let latency = Duration::from_millis(50);
+        let mut sim = Builder::new()
+            .min_message_latency(latency)
+            .max_message_latency(latency)
+            .build();
+        let epoch = UNIX_EPOCH + sim.since_epoch();
+        let sent = Rc::new(Cell::new(Duration::ZERO));
+
+        let sent_at = sent.clone();
+        sim.host("a", move || {
+            let sent_at = sent_at.clone();
+            async move {
+                sleep(Duration::from_secs(10)).await;
+                let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                sock.send_to(b"ping", ("b", 1234)).await?;
+                sent_at.set(elapsed());
+
+                future::pending().await
+            }
+        });
+
+        sim.host("b", || async {
+            let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            sock.recv_from(&mut [0; 8]).await?;
+
+            future::pending().await
+        });
+
+        sim.schedule(
+            Duration::from_secs(15),
+            FaultAction::Partition("a".to_string(), "b".to_string()),
+        );
+
+        sim.step()?;
+        assert_eq!(Some(epoch + Duration::from_secs(10)), sim.next_event_time());
+
+        while sim.elapsed() <= Duration::from_secs(10) {
+            sim.step()?;
+        }
+        assert_eq!(Some(epoch + sent.get() + latency), sim.next_event_time());
+
+        while sim.elapsed() <= sent.get() + latency {
+            sim.step()?;
+        }
+        assert_eq!(Some(epoch + Duration::from_secs(15)), sim.next_event_time());
+
+        while sim.elapsed() <= Duration::from_secs(15) {
+            sim.step()?;
+        }
+        assert_eq!(None, sim.next_event_time());
+
+        Ok(())
+    }
+
+    #[test]
+    fn describe() {
+        let mut sim = Builder::new().build();
+
+        sim.host("server", future::pending);
+        sim.client("client", future::pending());
+
+        sim.set_link_latency("server", "client", Duration::from_millis(5));
+        sim.lookup("clinet");
+
+        let report = sim.describe();
+
+        let hosts = report.hosts.iter().map(|h| &h.name[..]).collect::<Vec<_>>();
+        assert_eq!(vec!["server", "client"], hosts);
+
+        assert_eq!(1, report.links.len());
+        let link = &report.links[0];
+        assert_eq!(("server".to_string(), "client".to_string()), link.pair);
+        assert_eq!(LinkState::Healthy, link.state);
+        assert_eq!(Duration::from_millis(5), link.min_message_latency);
+        assert_eq!(Duration::from_millis(5), link.max_message_latency);
+
+        // The typo resolves, but has no host
+        assert_eq!(3, report.dns.len());
+        assert_eq!("clinet", report.dns[2].0);
+
+        assert!(report.to_string().contains("server <-> client"));
+    }
+
+    #[test]
+    fn owner_of() -> Result {
+        let mut sim = Builder::new().build();
+
+        sim.host("server", || async {
+            let _socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            future::pending().await
+        });
+        sim.step()?;
+
+        let server = sim.lookup("server");
+        let owner = |port| sim.owner_of((server, port).into());
+
+        assert_eq!(Some("server".to_string()), owner(1234));
+        assert_eq!(Some("server".to_string()), owner(0));
+        assert_eq!(None, owner(4321));
+
+        let unknown = sim.lookup("unknown");
+        assert_eq!(None, sim.owner_of((unknown, 0).into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut sim = Builder::new().build();
+
+        sim.host("server", future::pending);
+        sim.client("client", future::pending());
+
+        sim.set_link_latency("server", "client", Duration::from_millis(5));
+        sim.enter(|| crate::partition("server", "client"));
+
+        let dot = sim.to_dot();
+
+        assert!(dot.starts_with("graph turmoil {"));
+        assert!(dot.contains("\"client\" [label=\"client\\n"));
+        assert!(dot.contains(
+            "\"server\" -- \"client\" [label=\"5ms..=5ms\\nfail 0\", color=red, style=dashed];"
+        ));
+    }
+
+    #[test]
+    fn wait_reachable() -> Result {
+        let mut sim = Builder::new()
+            .simulation_duration(Duration::from_secs(1))
+            .build();
+
+        sim.host("server", || async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let listener = TcpListener::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            loop {
+                let _ = listener.accept().await?;
+            }
+        });
+        sim.host("client", future::pending);
+
+        sim.enter(|| crate::partition("client", "server"));
+        assert!(sim.wait_reachable("client", "server", 1234).is_err());
+
+        let mut sim = Builder::new().build();
+
+        sim.host("server", || async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let listener = TcpListener::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            loop {
+                let _ = listener.accept().await?;
+            }
+        });
+        sim.host("client", future::pending);
+
+        // The listener is bound on the tick after the sleep completes
+        let took = sim.wait_reachable("client", "server", 1234)?;
+        assert_eq!(Duration::from_millis(101), took);
+
+        sim.client("connect", async {
+            TcpStream::connect("server:1234").await?;
+
+            Ok(())
+        });
+
+        sim.run()
+    }
+
+    #[test]
+    fn step_host() -> Result {
+        let mut sim = Builder::new().build();
+        let order = Rc::new(RefCell::new(vec![]));
+
+        for name in ["a", "b"] {
+            let order = order.clone();
+            sim.client(name, async move {
+                order.borrow_mut().push(name);
+                Ok(())
+            });
+        }
+
+        assert!(sim.step_host("b")?);
+        assert_eq!(vec!["b"], *order.borrow());
+
+        assert!(sim.step_host("a")?);
+        assert_eq!(vec!["b", "a"], *order.borrow());
+        assert_eq!(Duration::ZERO, sim.elapsed());
+
+        Ok(())
+    }
+
+    #[test]
+    fn blocked_time() -> Result {
+        let mut sim = Builder::new().build();
+
+        sim.client("sleeper", async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(())
+        });
+
+        // Runnable every tick
+        sim.client("ticker", async {
+            for _ in 0..100 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            Ok(())
+        });
+
+        sim.run()?;
+
+        assert_eq!(Duration::from_millis(99), sim.blocked_time("sleeper"));
+        assert_eq!(Duration::ZERO, sim.blocked_time("ticker"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn poll_counts() -> Result {
+        let mut sim = Builder::new().build();
+
+        sim.client("sleeper", async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(())
+        });
+
+        sim.client("ticker", async {
+            for _ in 0..100 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            Ok(())
+        });
+
+        sim.run()?;
+
+        let counts = sim.poll_counts();
+        assert_eq!(2, counts["sleeper"]);
+        assert_eq!(101, counts["ticker"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush() -> Result {
+        let latency = Duration::from_millis(50);
+        let mut sim = Builder::new()
+            .min_message_latency(latency)
+            .max_message_latency(latency)
+            .build();
+
+        let received = Rc::new(Cell::new(0));
+
+        for peer in ["server", "held"] {
+            let received = received.clone();
+            sim.client(peer, async move {
+                let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+                loop {
+                    socket.recv_from(&mut [0; 64]).await?;
+                    received.set(received.get() + 1);
+                }
+            });
+        }
+
+        sim.client("client", async {
+            hold("client", "held");
+
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            for peer in ["server", "server", "held"] {
+                socket.send_to(&[0], (peer, 1234)).await?;
+            }
+
+            future::pending().await
+        });
+
+        sim.step()?;
+        sim.flush()?;
+
+        assert_eq!(2, received.get());
+        assert_eq!(Duration::from_millis(51), sim.elapsed());
+
+        // Nothing left in flight
+        sim.flush()?;
+        assert_eq!(Duration::from_millis(51), sim.elapsed());
+
+        Ok(())
+    }
+
+    #[test]
+    fn await_quiescent_between() -> Result {
+        let latency = Duration::from_millis(10);
+        let mut sim = Builder::new()
+            .min_message_latency(latency)
+            .max_message_latency(latency)
+            .build();
+
+        // Echoes once
+        sim.client("b", async {
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            let (_, from) = socket.recv_from(&mut [0; 64]).await?;
+            socket.send_to(&[0], from).await?;
+
+            Ok(())
+        });
+
+        sim.client("c", async {
+            let _socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            future::pending().await
+        });
+
+        sim.client("a", async {
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            socket.send_to(&[0], "b:1234").await?;
+            socket.send_to(&[0], "c:1234").await?;
+
+            future::pending().await
+        });
+
+        sim.set_link_latency("a", "c", Duration::from_millis(100));
+        sim.step()?;
+
+        // There and back, while the message to c is still in flight
+        assert_eq!(
+            Duration::from_millis(20),
+            sim.await_quiescent_between("a", "b")?
+        );
+        assert_eq!(
+            Duration::from_millis(80),
+            sim.await_quiescent_between("a", "c")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn connection_count() -> Result {
+        let mut sim = Builder::new()
+            .max_message_latency(Duration::from_millis(1))
+            .build();
+
+        sim.host("server", || async {
+            let listener = TcpListener::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            loop {
+                let (mut s, _) = listener.accept().await?;
+
+                // Drop the stream once the client closes it
+                tokio::task::spawn_local(async move {
+                    let _ = s.read_u8().await;
+                });
+            }
+        });
+
+        sim.client("client", async {
+            let mut streams = vec![];
+            for _ in 0..3 {
+                streams.push(TcpStream::connect("server:1234").await?);
+            }
+
+            sleep(Duration::from_millis(50)).await;
+            streams.pop();
+
+            future::pending().await
+        });
+
+        while sim.elapsed() < Duration::from_millis(25) {
+            sim.step()?;
+        }
+        assert_eq!(3, sim.connection_count("server"));
+        assert_eq!(0, sim.connection_count("client"));
+
+        while sim.elapsed() < Duration::from_millis(100) {
+            sim.step()?;
+        }
+        assert_eq!(2, sim.connection_count("server"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pause() -> Result {
+        let mut sim = Builder::new().build();
+
+        let count = Rc::new(RefCell::new(0));
+        let counted = count.clone();
+
+        sim.client("client", async move {
+            for _ in 0..3 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                *counted.borrow_mut() += 1;
+            }
+
+            Ok(())
+        });
+
+        sim.step()?;
+        sim.pause();
+        assert!(sim.is_paused());
+
+        let elapsed = sim.elapsed();
+        let seen = *count.borrow();
+        for _ in 0..10 {
+            assert!(!sim.step()?);
+        }
+        assert_eq!(elapsed, sim.elapsed());
+        assert_eq!(seen, *count.borrow());
+        assert!(sim.run().is_err());
+
+        sim.resume();
+        sim.run()?;
+        assert_eq!(3, *count.borrow());
+
+        Ok(())
+    }
+
+    #[test]
+    fn assert_delivered() -> Result {
+        let mut sim = Builder::new().capture(true).build();
+
+        sim.client("server", async {
+            let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            sock.recv_from(&mut [0; 8]).await?;
+
+            Ok(())
+        });
+
+        sim.client("client", async {
+            let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            sock.send_to(b"retry", "server:1234").await?;
+
+            Ok(())
+        });
+
+        sim.run()?;
+
+        sim.assert_delivered("client", "server", |d| {
+            d.kind == DeliveryKind::Udp && d.payload == "retry"
+        });
+        sim.assert_not_delivered("client", "server", |d| d.kind != DeliveryKind::Udp);
+        sim.assert_not_delivered("server", "client", |_| true);
+
+        Ok(())
+    }
+
+    fn two_phase_commit() -> Result<Sim<'static>> {
+        let mut sim = Builder::new()
+            .capture(true)
+            .min_message_latency(Duration::from_millis(1))
+            .max_message_latency(Duration::from_millis(10))
+            .build();
+
+        for host in ["b", "c"] {
+            sim.client(host, async {
+                let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                for _ in 0..2 {
+                    sock.recv_from(&mut [0; 8]).await?;
+                }
+
+                Ok(())
+            });
+        }
+
+        sim.client("a", async {
+            let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            for msg in ["prepare", "commit"] {
+                for host in ["b", "c"] {
+                    sock.send_to(msg.as_bytes(), (host, 1234)).await?;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+
+            Ok(())
+        });
+
+        sim.run()?;
+        Ok(sim)
+    }
+
+    #[test]
+    fn assert_ordered() -> Result {
+        let sim = two_phase_commit()?;
+
+        let prepare = EventMatcher::new().kind(DeliveryKind::Udp).size(7..=7);
+        let commit = EventMatcher::new().kind(DeliveryKind::Udp).size(..7);
+
+        sim.assert_ordered(&[prepare.clone().to("b"), commit.clone().to("c")]);
+        sim.assert_ordered(&[prepare.from("a").to("c"), commit.to("b")]);
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "event 1 was delivered before event 0")]
+    fn assert_ordered_out_of_order() {
+        let sim = two_phase_commit().unwrap();
+
+        sim.assert_ordered(&[
+            EventMatcher::new().to("c").size(6..=6),
+            EventMatcher::new().to("b").size(7..=7),
+        ]);
+    }
+
+    #[test]
+    fn failure_domain() -> Result {
+        let mut sim = Builder::new().failure_domain("rack", ["a", "b"]).build();
+
+        for host in ["a", "b", "c"] {
+            sim.host(host, future::pending);
+        }
+        sim.step()?;
+
+        let partitioned = |sim: &Sim, a: &str, b: &str| {
+            let (a, b) = (sim.lookup(a), sim.lookup(b));
+            sim.world.borrow().topology.is_partitioned(a, b)
+        };
+
+        sim.partition_domain("rack");
+        assert!(partitioned(&sim, "a", "c"));
+        assert!(partitioned(&sim, "b", "c"));
+        assert!(!partitioned(&sim, "a", "b"));
+
+        sim.repair_domain("rack");
+        assert!(!partitioned(&sim, "a", "c"));
+
+        sim.crash_domain("rack");
+        for (host, running) in [("a", false), ("b", false), ("c", true)] {
+            let addr = sim.lookup(host);
+            assert_eq!(running, sim.rts[&addr].is_software_running());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn assert_deterministic() {
+        crate::assert_deterministic(|| {
+            let mut sim = Builder::from_master_seed(7, 0).build();
+
+            sim.client("server", async {
+                let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                sock.recv_from(&mut [0; 8]).await?;
+
+                Ok(())
+            });
+
+            sim.client("client", async {
+                let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                sock.send_to(&random::<u64>().to_be_bytes(), "server:1234")
+                    .await?;
+
+                Ok(())
+            });
+
+            sim
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "runs diverged at delivery 0")]
+    fn assert_deterministic_diverged() {
+        let run = std::cell::Cell::new(0u8);
+
+        crate::assert_deterministic(|| {
+            let mut sim = Builder::from_master_seed(7, 0).build();
+
+            // Stands in for e.g. the thread rng, which differs across runs
+            run.set(run.get() + 1);
+            let payload = [run.get()];
+
+            sim.client("server", async {
+                let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                sock.recv_from(&mut [0; 8]).await?;
+
+                Ok(())
+            });
+
+            sim.client("client", async move {
+                let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                sock.send_to(&payload, "server:1234").await?;
+
+                Ok(())
+            });
+
+            sim
+        });
+    }
+
+    #[test]
+    fn from_master_seed() -> Result {
+        fn id(index: u64) -> Result<u64> {
+            let mut sim = Builder::from_master_seed(42, index).build();
+
+            let id = Rc::new(RefCell::new(0));
+            let set = id.clone();
+
+            sim.client("client", async move {
+                *set.borrow_mut() = random();
+
+                Ok(())
+            });
+
+            sim.run()?;
+
+            let id = *id.borrow();
+            Ok(id)
+        }
+
+        assert_eq!(id(0)?, id(0)?);
+        assert_ne!(id(0)?, id(1)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn config_file() -> Result {
+        let path = std::env::temp_dir().join("turmoil-config-file.json");
+        let latency = Duration::from_millis(50);
+
+        let mut builder = Builder::from_master_seed(7, 0);
+        builder
+            .zone("east", ["a", "b"])
+            .zone_latency("east", "east", latency, latency)
+            .bandwidth_sharing(BandwidthSharing::Fair)
+            .link_latency_trace("a", "b", LatencyTrace::new([(latency, latency)]).looped())
+            .failure_domain("rack", ["a"]);
+        builder.to_config_file(&path)?;
+
+        let run = |builder: &Builder| -> Result<(u64, Duration)> {
+            let mut sim = builder.build();
+            let value = Rc::new(RefCell::new(0));
+            let set = value.clone();
+
+            sim.host("a", future::pending);
+            sim.client("b", async move {
+                *set.borrow_mut() = random();
+                Ok(())
+            });
+            sim.run()?;
+
+            let value = *value.borrow();
+            Ok((value, sim.describe().links[0].min_message_latency))
+        };
+
+        let loaded = Builder::from_config_file(&path)?;
+        assert_eq!(run(&builder)?, run(&loaded)?);
+        assert_eq!(latency, run(&loaded)?.1);
+        assert_eq!(vec!["a"], loaded.build().config.failure_domains["rack"]);
+
+        // Hosts named by the file must be registered by the first step
+        let mut sim = loaded.build();
+        sim.host("a", future::pending);
+        let err = sim.step().unwrap_err();
+        assert!(err.to_string().contains("`b`"));
+
+        // Zone latencies must name zones with hosts
+        let json = std::fs::read_to_string(&path)?.replace("\"b\": \"east\"", "\"b\": \"west\"");
+        std::fs::write(&path, json)?;
+
+        let err = Builder::from_config_file(&path).err().unwrap();
+        assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+        assert!(err.to_string().contains("`west`"));
+
+        // Latency traces are loaded through `LatencyTrace::new`
+        let ms = |ms| serde_json::json!({ "secs": 0, "nanos": ms * 1_000_000 });
+        let trace = |samples| serde_json::json!({ "samples": samples, "interpolate": false, "looped": false });
+
+        let empty = serde_json::from_value::<LatencyTrace>(trace(serde_json::json!([])));
+        assert!(empty.unwrap_err().to_string().contains("no samples"));
+
+        let unsorted = serde_json::json!([[ms(50), ms(30)], [ms(0), ms(10)]]);
+        let loaded = serde_json::from_value::<LatencyTrace>(trace(unsorted))?;
+        assert_eq!(
+            Duration::from_millis(10),
+            loaded.latency_at(Duration::from_millis(20))
+        );
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn effective_loss() {
+        let mut sim = Builder::new().build();
+
+        sim.host("a", future::pending);
+        sim.host("b", future::pending);
+
+        assert_eq!(0.0, sim.effective_loss("a", "b"));
+
+        sim.set_fail_rate(0.25);
+        assert_eq!(0.25, sim.effective_loss("a", "b"));
+
+        sim.enter(|| crate::partition("a", "b"));
+        assert_eq!(1.0, sim.effective_loss("a", "b"));
+
+        sim.enter(|| crate::hold("a", "b"));
+        assert_eq!(0.0, sim.effective_loss("a", "b"));
+    }
+
+    #[test]
+    fn host_finishes_with_error() {
+        let mut sim = Builder::new().build();
+
+        sim.host("host", || async {
+            Err("Host software finished unexpectedly")?
+        });
+
+        assert!(sim.run().is_err());
+    }
+
+    #[test]
+    fn manual_message_delivery() -> Result {
+        let mut sim = Builder::new().build();
+
+        sim.host("a", || async {
+            let l = TcpListener::bind("0.0.0.0:1234").await?;
+
+            _ = l.accept().await?;
+
+            Ok(())
+        });
+
+        sim.client("b", async {
+            hold("a", "b");
+
+            _ = TcpStream::connect("a:1234").await?;
+
+            Ok(())
+        });
+
+        assert!(!sim.step()?);
+
+        sim.links(|mut l| {
+            let a_to_b = l.next().unwrap();
+            a_to_b.deliver_all();
+        });
+
+        assert!(sim.step()?);
+
+        Ok(())
+    }
+
+    /// This is a regression test that ensures JoinError::Cancelled is not
+    /// propagated to the test when the host crashes, which was causing
+    /// incorrect test failure.
+    #[test]
+    fn run_after_host_crashes() -> Result {
+        let mut sim = Builder::new().build();
+
+        sim.host("h", || async { future::pending().await });
+
+        sim.crash("h");
+
+        sim.run()
+    }
+
+    #[test]
+    fn restart_host_after_crash() -> Result {
+        let mut sim = Builder::new().build();
+
+        let data = Arc::new(AtomicU64::new(0));
+        let data_cloned = data.clone();
+
+        sim.host("h", move || {
+            let data_cloned = data_cloned.clone();
+            async move {
+                data_cloned.store(data_cloned.load(Ordering::SeqCst) + 1, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+
+        // crash and step to execute the err handling logic
+        sim.crash("h");
+        sim.step()?;
+
+        // restart and step to ensure the host software runs
+        sim.bounce("h");
+        sim.step()?;
+        // check that software actually runs
+        assert_eq!(1, data.load(Ordering::SeqCst));
+
+        Ok(())
+    }
+
+    #[test]
+    fn override_link_latency() -> Result {
+        let global = Duration::from_millis(2);
+
+        let mut sim = Builder::new()
+            .min_message_latency(global)
+            .max_message_latency(global)
+            .build();
+
+        sim.host("server", || async {
+            let listener = TcpListener::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            while let Ok((mut s, _)) = listener.accept().await {
+                assert!(s.write_u8(9).await.is_ok());
+            }
+
+            Ok(())
+        });
+
+        sim.client("client", async move {
+            let mut s = TcpStream::connect("server:1234").await?;
+
+            let start = Instant::now();
+            s.read_u8().await?;
+            assert_eq!(global, start.elapsed());
+
+            Ok(())
+        });
+
+        sim.run()?;
+
+        let degraded = Duration::from_millis(10);
+
+        sim.client("client2", async move {
+            let mut s = TcpStream::connect("server:1234").await?;
+
+            let start = Instant::now();
+            s.read_u8().await?;
+            assert_eq!(degraded, start.elapsed());
+
+            Ok(())
+        });
+
+        sim.set_link_latency("client2", "server", degraded);
+
+        sim.run()
+    }
+
+    #[test]
+    fn protocol_latency() -> Result {
+        let tcp = Duration::from_millis(2);
+        let udp = Duration::from_millis(10);
+
+        let mut sim = Builder::new()
+            .min_message_latency(tcp)
+            .max_message_latency(tcp)
+            .build();
+
+        sim.host("server", || async {
+            let listener = TcpListener::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            let (mut s, _) = listener.accept().await?;
+            s.write_u8(9).await?;
+
+            let mut buf = [0; 1];
+            let (_, peer) = socket.recv_from(&mut buf).await?;
+            socket.send_to(&buf, peer).await?;
+
+            future::pending().await
+        });
+
+        sim.client("client", async move {
+            let mut s = TcpStream::connect("server:1234").await?;
+
+            let start = Instant::now();
+            s.read_u8().await?;
+            assert_eq!(tcp, start.elapsed());
+
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            let start = Instant::now();
+            socket.send_to(&[1], "server:1234").await?;
+            socket.recv_from(&mut [0; 1]).await?;
+            assert_eq!(udp * 2, start.elapsed());
+
+            Ok(())
+        });
+
+        sim.set_link_udp_latency("client", "server", udp);
+
+        sim.run()
+    }
+
+    #[test]
+    fn min_latency_floor() -> Result {
+        let floor = Duration::from_millis(5);
+
+        let mut sim = Builder::new()
+            .max_message_latency(Duration::ZERO)
+            .min_latency_floor(floor)
+            .build();
+
+        sim.host("server", || async {
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            let mut buf = [0; 1];
+            let (_, peer) = socket.recv_from(&mut buf).await?;
+            socket.send_to(&buf, peer).await?;
+
+            future::pending().await
+        });
+
+        sim.client("client", async move {
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            let start = Instant::now();
+            socket.send_to(&[1], "server:1234").await?;
+            socket.recv_from(&mut [0; 1]).await?;
+            assert_eq!(floor * 2, start.elapsed());
+
+            Ok(())
+        });
+
+        // Link overrides below the floor are clamped up
+        sim.set_link_latency("client", "server", Duration::ZERO);
+
+        sim.run()
+    }
+
+    #[test]
+    fn host_bandwidth() -> Result {
+        let mut sim = Builder::new().max_message_latency(Duration::ZERO).build();
+
+        sim.client("server", async {
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            // Fanning out, each datagram queues behind the last
+            for peer in ["a", "b"] {
+                socket.send_to(&[0; 10], (peer, 1234)).await?;
+            }
+
+            Ok(())
+        });
+
+        for (peer, expected) in [("a", 10), ("b", 20)] {
+            sim.client(peer, async move {
+                let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+                socket.recv_from(&mut [0; 64]).await?;
+                assert_eq!(Duration::from_millis(expected), crate::elapsed());
+
+                Ok(())
+            });
+        }
+
+        sim.set_host_bandwidth("server", 1000);
+
+        sim.run()
+    }
+
+    #[test]
+    fn link_latency_trace() -> Result {
+        let ms = Duration::from_millis;
+
+        let mut sim = Builder::new()
+            .link_latency_trace(
+                "server",
+                "step",
+                LatencyTrace::new([(ms(50), ms(30)), (ms(0), ms(10))]),
+            )
+            .link_latency_trace(
+                "server",
+                "lerp",
+                LatencyTrace::new([(ms(0), ms(10)), (ms(100), ms(30))]).interpolate(),
+            )
+            .build();
+
+        sim.client("server", async move {
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            // The network times sends at the end of their tick, so the send
+            // at 49ms takes the latency at 50ms
+            for expected in [10, 69, 90] {
+                socket.recv_from(&mut [0; 64]).await?;
+                assert_eq!(ms(expected), crate::elapsed());
+            }
+
+            Ok(())
+        });
+
+        for (client, sends) in [("step", vec![0, 60]), ("lerp", vec![49])] {
+            sim.client(client, async move {
+                let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+                for at in sends {
+                    sleep(ms(at) - crate::elapsed()).await;
+                    socket.send_to(&[0], "server:1234").await?;
+                }
+
+                Ok(())
+            });
+        }
+
+        sim.run()
+    }
+
+    #[test]
+    fn bandwidth() -> Result {
+        let mut sim = Builder::new()
+            .max_message_latency(Duration::ZERO)
+            .bandwidth(10)
+            .build();
+
+        sim.client("server", async {
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            // Each message is delayed by its size over the rate, and queues
+            // behind the last
+            for (expected, at) in [(40, 40), (10, 50)] {
+                let (len, _) = socket.recv_from(&mut [0; 64]).await?;
+                assert_eq!(expected, len);
+                assert_eq!(Duration::from_millis(at), crate::elapsed());
+            }
+
+            Ok(())
+        });
+
+        sim.client("client", async move {
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+            socket.send_to(&[0; 40], "server:1234").await?;
+            socket.send_to(&[0; 10], "server:1234").await?;
+
+            Ok(())
+        });
+
+        // Overrides the builder's rate
+        sim.set_link_bandwidth("client", "server", 1000);
+
+        sim.run()
+    }
+
+    #[test]
+    fn bandwidth_sharing() -> Result {
+        let ping_arrival = |sharing| -> Result<Duration> {
+            let mut sim = Builder::new()
+                .max_message_latency(Duration::ZERO)
+                .bandwidth(1000)
+                .bandwidth_sharing(sharing)
+                .build();
+
+            let arrival = Rc::new(Cell::new(Duration::ZERO));
+            let set = arrival.clone();
+
+            sim.client("server", async move {
+                let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+
+                for _ in 0..6 {
+                    let (len, _) = socket.recv_from(&mut [0; 128]).await?;
+                    if len == 10 {
+                        set.set(crate::elapsed());
+                    }
+                }
+
+                Ok(())
+            });
+
+            sim.client("client", async move {
+                let bulk = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1)).await?;
+                let ping = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 2)).await?;
+
+                for _ in 0..5 {
+                    bulk.send_to(&[0; 100], "server:1234").await?;
+                }
+                ping.send_to(&[0; 10], "server:1234").await?;
+
+                Ok(())
+            });
+
+            sim.run()?;
+            Ok(arrival.get())
+        };
+
+        // Behind the bulk transfer
+        assert_eq!(
+            Duration::from_millis(510),
+            ping_arrival(BandwidthSharing::Fifo)?
+        );
+
+        // At half the rate, alongside the bulk transfer
+        assert_eq!(
+            Duration::from_millis(20),
+            ping_arrival(BandwidthSharing::Fair)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn replay_decisions() -> Result {
+        let run =
+            |seed: u64, log: Option<DecisionLog>| -> Result<(Vec<(Duration, u8)>, DecisionLog)> {
+                let mut builder = Builder::new();
+                builder
+                    .fail_rate(0.3)
+                    .repair_rate(0.5)
+                    .min_message_latency(Duration::from_millis(1))
+                    .max_message_latency(Duration::from_millis(50));
+                if let Some(log) = log {
+                    builder.replay_decisions(log);
+                }
+                let mut sim = builder.build_with_rng(Box::new(SmallRng::seed_from_u64(seed)));
+                sim.record_decisions();
+
+                let arrivals = Rc::new(RefCell::new(vec![]));
+                let recv = arrivals.clone();
+
+                sim.client("server", async move {
+                    let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                    let mut buf = [0; 1];
+                    while let Ok(res) =
+                        tokio::time::timeout(Duration::from_secs(1), sock.recv_from(&mut buf)).await
+                    {
+                        res?;
+                        recv.borrow_mut().push((elapsed(), buf[0]));
+                    }
+
+                    Ok(())
+                });
+
+                sim.client("client", async {
+                    let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                    for i in 0..20 {
+                        sock.send_to(&[i], "server:1234").await?;
+                        sleep(Duration::from_millis(1)).await;
+                    }
+
+                    Ok(())
+                });
+
+                sim.run()?;
 
-    #[test]
-    fn client_error() {
-        let mut sim = Builder::new().build();
+                let arrivals = arrivals.borrow().clone();
+                Ok((arrivals, sim.recorded_decisions()))
+            };
 
-        sim.client("doomed", async { Err("An Error")? });
+        let (recorded, log) = run(1, None)?;
+        assert!(!log.is_empty());
+        assert!(recorded.len() < 20);
 
-        assert!(sim.run().is_err());
-    }
+        // A different seed makes different decisions, unless they're replayed
+        let (other, _) = run(2, None)?;
+        assert_ne!(recorded, other);
 
-    #[test]
-    fn timeout() {
-        let mut sim = Builder::new()
-            .simulation_duration(Duration::from_millis(500))
-            .build();
+        let (replayed, replayed_log) = run(2, Some(log.clone()))?;
+        assert_eq!(recorded, replayed);
+        assert_eq!(log, replayed_log);
 
-        sim.client("timeout", async {
-            tokio::time::sleep(Duration::from_secs(1)).await;
+        let is_drop = |decision: &&Decision| matches!(decision, Decision::Drop(_));
 
-            Ok(())
-        });
+        // A kind missing from the log falls back to the random number
+        // generator, without shifting the other kinds
+        let mut missing = DecisionLog::new();
+        for decision in log.iter().filter(|d| !is_drop(d)) {
+            missing.push(*decision);
+        }
+        let (replayed, _) = run(2, Some(missing))?;
+        assert_eq!(recorded, replayed);
+
+        // Neither does a kind logged in a different order, with extras
+        let mut reordered = DecisionLog::new();
+        for decision in log
+            .iter()
+            .filter(|d| !is_drop(d))
+            .chain(log.iter().filter(is_drop))
+        {
+            reordered.push(*decision);
+        }
+        reordered.push(Decision::Drop(true));
+        let (replayed, _) = run(2, Some(reordered))?;
+        assert_eq!(recorded, replayed);
 
-        assert!(sim.run().is_err());
+        Ok(())
     }
 
     #[test]
-    fn multiple_clients_all_finish() -> Result {
-        let how_many = 3;
-        let tick_ms = 10;
-
-        // N = how_many runs, each with a different client finishing immediately
-        for run in 0..how_many {
-            let mut sim = Builder::new()
-                .tick_duration(Duration::from_millis(tick_ms))
-                .build();
+    fn latency_histogram() -> Result {
+        let mut sim = Builder::new()
+            .record_latencies(true)
+            .min_message_latency(Duration::from_millis(1))
+            .max_message_latency(Duration::from_millis(50))
+            .build();
 
-            let ct = Rc::new(Semaphore::new(how_many));
+        for host in ["b", "c"] {
+            sim.host(host, || async {
+                let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                loop {
+                    sock.recv_from(&mut [0; 8]).await?;
+                }
+            });
+        }
 
-            for client in 0..how_many {
-                let ct = ct.clone();
+        sim.client("a", async {
+            let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            for _ in 0..100 {
+                sock.send_to(b"ping", ("b", 1234)).await?;
+                sock.send_to(b"ping", ("c", 1234)).await?;
+                sleep(Duration::from_millis(1)).await;
+            }
 
-                sim.client(format!("client-{client}"), async move {
-                    let ms = if run == client { 0 } else { 2 * tick_ms };
-                    tokio::time::sleep(Duration::from_millis(ms)).await;
+            // Let the last messages arrive
+            sleep(Duration::from_millis(100)).await;
 
-                    let p = ct.acquire().await?;
-                    p.forget();
+            Ok(())
+        });
 
-                    Ok(())
-                });
-            }
+        sim.set_link_latency("a", "c", Duration::from_millis(20));
+        sim.run()?;
 
-            sim.run()?;
-            assert_eq!(0, ct.available_permits());
-        }
+        let histogram = sim.latency_histogram("a", "b");
+        assert_eq!(100, histogram.iter().map(|(_, n)| n).sum::<u64>());
+        assert!(histogram.len() > 1);
+        assert!(histogram.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(histogram
+            .iter()
+            .all(|(l, _)| (1..=50).contains(&l.as_millis())));
+
+        // Overrides apply
+        assert_eq!(
+            vec![(Duration::from_millis(20), 100)],
+            sim.latency_histogram("c", "a")
+        );
+        assert!(sim.latency_histogram("b", "c").is_empty());
 
         Ok(())
     }
 
-    /// This is a regression test that ensures host software completes when the
-    /// host crashes. Before this fix we simply dropped the LocalSet, which did
-    /// not ensure resources owned by spawned tasks were dropped. Now we drop
-    /// and replace both the tokio Runtime and the LocalSet.
     #[test]
-    fn crash_blocks_until_complete() -> Result {
-        let ct = Arc::new(());
+    fn max_messages_per_tick() -> Result {
+        let latency = Duration::from_millis(10);
+        let received = Rc::new(RefCell::new(vec![]));
 
-        let mut sim = Builder::new().build();
+        let mut sim = Builder::new()
+            .max_messages_per_tick(3)
+            .min_message_latency(latency)
+            .max_message_latency(latency)
+            .build();
 
-        sim.host("host", || {
-            let ct = ct.clone();
+        let set = received.clone();
+        sim.host("server", move || {
+            let set = set.clone();
 
             async move {
-                tokio::spawn(async move {
-                    let _into_task = ct;
-                    future::pending::<()>().await;
-                });
+                let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+                loop {
+                    let mut buf = [0; 1];
+                    sock.recv_from(&mut buf).await?;
+                    set.borrow_mut().push(buf[0]);
+                }
+            }
+        });
 
-                future::pending().await
+        sim.client("client", async move {
+            let sock = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+            for i in 0..8 {
+                sock.send_to(&[i], ("server", 1234)).await?;
             }
+
+            sleep(Duration::from_millis(100)).await;
+
+            Ok(())
         });
 
+        // The burst is spread over three ticks
+        for expected in [3, 3, 2, 0] {
+            sim.step()?;
+            assert_eq!(expected, sim.messages_this_tick());
+        }
+
         sim.run()?;
-        assert_eq!(2, Arc::strong_count(&ct));
 
-        sim.crash("host");
-        assert_eq!(1, Arc::strong_count(&ct));
+        // Deferred messages keep their order
+        assert_eq!((0..8).collect::<Vec<_>>(), *received.borrow());
 
         Ok(())
     }
 
     #[test]
-    fn elapsed_time() -> Result {
-        let tick = Duration::from_millis(5);
-        let mut sim = Builder::new().tick_duration(tick).build();
+    fn packet_filters() -> Result {
+        let mut sim = Builder::new().capture(true).build();
 
-        let duration = Duration::from_millis(500);
+        sim.client("server", async {
+            let socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
 
-        sim.client("c1", async move {
-            tokio::time::sleep(duration).await;
-            assert_eq!(duration, elapsed());
+            let mut buf = [0; 64];
+            let (len, _) = socket.recv_from(&mut buf).await?;
+            assert_eq!(b"allowed", &buf[..len]);
 
             Ok(())
         });
 
-        sim.client("c2", async move {
-            tokio::time::sleep(duration).await;
-            assert_eq!(duration, elapsed());
+        sim.client("client", async {
+            let blocked = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1)).await?;
+            let allowed = UdpSocket::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 2)).await?;
+
+            allowed.send_to(b"dropped on egress", "server:1234").await?;
+            blocked
+                .send_to(b"dropped on ingress", "server:1234")
+                .await?;
+            allowed.send_to(b"allowed", "server:1234").await?;
 
             Ok(())
         });
 
-        sim.run()?;
-
-        // sleep duration plus one tick to complete
-        assert_eq!(duration + tick, sim.elapsed());
-
-        let start = sim.elapsed();
-        sim.client("c3", async move {
-            assert_eq!(Duration::ZERO, elapsed());
-
-            Ok(())
+        sim.set_egress_filter("client", |packet| {
+            &packet.payload[..] != b"dropped on egress"
         });
+        sim.set_ingress_filter("server", |packet| packet.src.port() != 1);
 
         sim.run()?;
 
-        // one tick to complete
-        assert_eq!(tick, sim.elapsed() - start);
+        sim.assert_delivered("client", "server", |d| &d.payload[..] == b"allowed");
+        sim.assert_not_delivered("client", "server", |d| &d.payload[..] != b"allowed");
 
         Ok(())
     }
 
     #[test]
-    fn elapsed_time_across_restarts() -> Result {
-        let tick_ms = 5;
+    fn degrade_link() -> Result {
+        let latency = Duration::from_millis(10);
+
         let mut sim = Builder::new()
-            .tick_duration(Duration::from_millis(tick_ms))
+            .min_message_latency(latency)
+            .max_message_latency(latency)
             .build();
 
-        let clock = Arc::new(AtomicU64::new(0));
-        let actual = clock.clone();
+        sim.host("server", future::pending);
+        sim.host("client", future::pending);
 
-        sim.host("host", move || {
-            let clock = clock.clone();
+        sim.degrade_link(
+            "client",
+            "server",
+            0.5,
+            Duration::from_millis(110),
+            Duration::from_secs(1),
+        );
 
-            async move {
-                loop {
-                    tokio::time::sleep(Duration::from_millis(1)).await;
-                    clock.store(elapsed().as_millis() as u64, Ordering::SeqCst);
-                }
-            }
-        });
+        let link = |sim: &Sim| sim.describe().links.remove(0);
 
-        sim.run()?;
-        assert_eq!(tick_ms - 1, actual.load(Ordering::SeqCst));
+        while sim.elapsed() < Duration::from_millis(500) {
+            sim.step()?;
+        }
 
-        sim.bounce("host");
-        sim.run()?;
-        assert_eq!((tick_ms * 2) - 1, actual.load(Ordering::SeqCst));
+        let halfway = link(&sim);
+        assert_eq!(Duration::from_millis(60), halfway.min_message_latency);
+        assert_eq!(Duration::from_millis(60), halfway.max_message_latency);
+        assert_eq!(0.25, halfway.fail_rate);
+
+        while sim.elapsed() < Duration::from_secs(2) {
+            sim.step()?;
+        }
+
+        let done = link(&sim);
+        assert_eq!(Duration::from_millis(110), done.min_message_latency);
+        assert_eq!(0.5, done.fail_rate);
 
         Ok(())
     }
 
     #[test]
-    fn host_finishes_with_error() {
+    fn schedule() -> Result {
         let mut sim = Builder::new().build();
 
-        sim.host("host", || async {
-            Err("Host software finished unexpectedly")?
+        let starts = Rc::new(Cell::new(0));
+        let count = starts.clone();
+
+        sim.host("server", move || {
+            count.set(count.get() + 1);
+            future::pending()
         });
+        sim.host("client", future::pending);
+
+        // Scheduled out of order, fired by time
+        sim.schedule(
+            Duration::from_millis(20),
+            FaultAction::Repair("client".into(), "server".into()),
+        );
+        sim.schedule(
+            Duration::from_millis(10),
+            FaultAction::Partition("client".into(), "server".into()),
+        );
+        sim.schedule(
+            Duration::from_millis(30),
+            FaultAction::Crash("server".into()),
+        );
+        sim.schedule(
+            Duration::from_millis(30),
+            FaultAction::Bounce("server".into()),
+        );
+
+        let state = |sim: &Sim| sim.describe().links[0].state;
+
+        while sim.elapsed() < Duration::from_millis(15) {
+            sim.step()?;
+        }
+        assert_eq!(LinkState::Partitioned, state(&sim));
 
-        assert!(sim.run().is_err());
+        while sim.elapsed() < Duration::from_millis(25) {
+            sim.step()?;
+        }
+        assert_eq!(LinkState::Healthy, state(&sim));
+        assert_eq!(1, starts.get());
+
+        while sim.elapsed() < Duration::from_millis(35) {
+            sim.step()?;
+        }
+        assert_eq!(2, starts.get());
+
+        Ok(())
     }
 
     #[test]
-    fn manual_message_delivery() -> Result {
-        let mut sim = Builder::new().build();
+    fn geo_dns() -> Result {
+        let intra = Duration::from_millis(1);
+        let inter = Duration::from_millis(20);
 
-        sim.host("a", || async {
-            let l = TcpListener::bind("0.0.0.0:1234").await?;
+        let mut sim = Builder::new()
+            .zone("us-east", ["us-replica", "us-client"])
+            .zone("eu-west", ["eu-replica", "eu-client"])
+            .zone_latency("us-east", "us-east", intra, intra)
+            .zone_latency("eu-west", "eu-west", intra, intra)
+            .zone_latency("eu-west", "us-east", inter, inter)
+            .build();
 
-            _ = l.accept().await?;
+        for (replica, id) in [("eu-replica", 1), ("us-replica", 2)] {
+            sim.host(replica, move || async move {
+                let listener = TcpListener::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
 
-            Ok(())
-        });
+                while let Ok((mut s, _)) = listener.accept().await {
+                    s.write_u8(id).await?;
+                }
 
-        sim.client("b", async {
-            hold("a", "b");
+                Ok(())
+            });
+        }
 
-            _ = TcpStream::connect("a:1234").await?;
+        for (client, replica, id) in [
+            ("us-client", "us-replica", 2),
+            ("eu-client", "eu-replica", 1),
+        ] {
+            sim.client(client, async move {
+                assert_eq!(lookup(replica), lookup("cdn"));
 
-            Ok(())
-        });
+                let mut s = TcpStream::connect("cdn:1234").await?;
+                assert_eq!(id, s.read_u8().await?);
 
-        assert!(!sim.step()?);
+                Ok(())
+            });
+        }
 
-        sim.links(|mut l| {
-            let a_to_b = l.next().unwrap();
-            a_to_b.deliver_all();
-        });
+        sim.geo_dns("cdn", ["eu-replica", "us-replica"]);
 
-        assert!(sim.step()?);
+        // Outside of a host, the first replica is used
+        sim.enter(|| assert_eq!(lookup("eu-replica"), lookup("cdn")));
 
-        Ok(())
+        sim.run()
     }
 
-    /// This is a regression test that ensures JoinError::Cancelled is not
-    /// propagated to the test when the host crashes, which was causing
-    /// incorrect test failure.
     #[test]
-    fn run_after_host_crashes() -> Result {
+    fn dns_entries() -> Result {
         let mut sim = Builder::new().build();
 
-        sim.host("h", || async { future::pending().await });
+        for (replica, id) in [("replica-1", 1), ("replica-2", 2)] {
+            sim.host(replica, move || async move {
+                let listener = TcpListener::bind((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1234)).await?;
 
-        sim.crash("h");
+                while let Ok((mut s, _)) = listener.accept().await {
+                    s.write_u8(id).await?;
+                }
 
-        sim.run()
-    }
+                Ok(())
+            });
+        }
 
-    #[test]
-    fn restart_host_after_crash() -> Result {
-        let mut sim = Builder::new().build();
+        sim.client("client", async {
+            let mut s = TcpStream::connect("db:1234").await?;
+            assert_eq!(1, s.read_u8().await?);
 
-        let data = Arc::new(AtomicU64::new(0));
-        let data_cloned = data.clone();
+            tokio::time::sleep(Duration::from_secs(1)).await;
 
-        sim.host("h", move || {
-            let data_cloned = data_cloned.clone();
-            async move {
-                data_cloned.store(data_cloned.load(Ordering::SeqCst) + 1, Ordering::SeqCst);
-                Ok(())
-            }
+            let mut s = TcpStream::connect("db:1234").await?;
+            assert_eq!(2, s.read_u8().await?);
+
+            Ok(())
         });
 
-        // crash and step to execute the err handling logic
-        sim.crash("h");
-        sim.step()?;
+        sim.add_dns("db", "replica-1");
+        assert_eq!(
+            vec![
+                ("replica-1".to_string(), sim.lookup("replica-1")),
+                ("replica-2".to_string(), sim.lookup("replica-2")),
+                ("client".to_string(), sim.lookup("client")),
+                ("db".to_string(), sim.lookup("replica-1")),
+            ],
+            sim.dns_entries()
+        );
+
+        while sim.elapsed() < Duration::from_millis(500) {
+            sim.step()?;
+        }
 
-        // restart and step to ensure the host software runs
-        sim.bounce("h");
-        sim.step()?;
-        // check that software actually runs
-        assert_eq!(1, data.load(Ordering::SeqCst));
+        // Fail over to the other replica
+        sim.add_dns("db", "replica-2");
+        sim.run()?;
+
+        sim.remove_dns("db");
+        assert!(sim.dns_entries().iter().all(|(name, _)| name != "db"));
 
         Ok(())
     }
 
     #[test]
-    fn override_link_latency() -> Result {
-        let global = Duration::from_millis(2);
+    #[should_panic(expected = "`client` names a host")]
+    fn remove_dns_host_name() {
+        let mut sim = Builder::new().build();
+        sim.client("client", async { Ok(()) });
+
+        sim.remove_dns("client");
+    }
+
+    #[test]
+    fn zone_latency() -> Result {
+        let intra = Duration::from_millis(1);
+        let inter = Duration::from_millis(20);
 
         let mut sim = Builder::new()
-            .min_message_latency(global)
-            .max_message_latency(global)
+            .zone("us-east", ["server", "near"])
+            .zone("eu-west", ["far"])
+            .zone_latency("us-east", "us-east", intra, intra)
+            .zone_latency("eu-west", "us-east", inter, inter)
             .build();
 
         sim.host("server", || async {
@@ -605,31 +4000,89 @@ mod test {
             Ok(())
         });
 
-        sim.client("client", async move {
-            let mut s = TcpStream::connect("server:1234").await?;
+        for (client, expected) in [("near", intra), ("far", inter)] {
+            sim.client(client, async move {
+                let mut s = TcpStream::connect("server:1234").await?;
 
-            let start = Instant::now();
-            s.read_u8().await?;
-            assert_eq!(global, start.elapsed());
+                let start = Instant::now();
+                s.read_u8().await?;
+                assert_eq!(expected, start.elapsed());
 
+                Ok(())
+            });
+        }
+
+        sim.run()
+    }
+
+    #[test]
+    fn on_host_state() -> Result {
+        let states = Rc::new(RefCell::new(vec![]));
+
+        let mut sim = Builder::new()
+            .on_host_state({
+                let states = states.clone();
+                move |host, state| states.borrow_mut().push((host.to_string(), state))
+            })
+            .build();
+
+        sim.client("client", async {
+            sleep(Duration::from_millis(10)).await;
             Ok(())
         });
 
         sim.run()?;
 
-        let degraded = Duration::from_millis(10);
+        assert_eq!(
+            vec![
+                ("client".to_string(), HostState::Idle),
+                ("client".to_string(), HostState::Busy),
+            ],
+            *states.borrow()
+        );
 
-        sim.client("client2", async move {
-            let mut s = TcpStream::connect("server:1234").await?;
+        Ok(())
+    }
 
-            let start = Instant::now();
-            s.read_u8().await?;
-            assert_eq!(degraded, start.elapsed());
+    #[test]
+    #[cfg(feature = "http")]
+    fn http_echo() -> Result {
+        let mut sim = Builder::new().build();
+        sim.add_http_echo("server", 80);
 
-            Ok(())
-        });
+        async fn request(stream: &mut TcpStream, req: &str) -> Result<String> {
+            stream.write_all(req.as_bytes()).await?;
+            let mut buf = vec![0; 1024];
+            let n = stream.read(&mut buf).await?;
+            Ok(String::from_utf8(buf[..n].to_vec())?)
+        }
 
-        sim.set_link_latency("client2", "server", degraded);
+        for client in ["a", "b"] {
+            sim.client(client, async move {
+                let mut stream = TcpStream::connect("server:80").await?;
+
+                // Kept alive across requests
+                for body in ["hello", "world"] {
+                    let req = format!(
+                        "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let res = request(&mut stream, &req).await?;
+                    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+                    assert!(res.ends_with(&format!("\r\n\r\n{body}")));
+                }
+
+                let res = request(
+                    &mut stream,
+                    "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+                )
+                .await?;
+                assert!(res.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+                assert_eq!(0, stream.read(&mut [0; 8]).await?);
+
+                Ok(())
+            });
+        }
 
         sim.run()
     }
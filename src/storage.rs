@@ -0,0 +1,224 @@
+//! Simulated, per host storage.
+//!
+//! Each host has an in-memory store of files. Operations take the host's
+//! configured storage latency on the simulated clock, and fail while the host's
+//! storage is failed with [`Sim::fail_storage`](crate::Sim::fail_storage).
+//!
+//! Writes are volatile until synced. When a host is crashed or killed, each file
+//! is reverted to its contents as of the last [`File::sync_all`], which makes
+//! durability logic, e.g. for a write-ahead log, testable.
+
+use indexmap::IndexMap;
+use std::io::{self, Result};
+use std::time::Duration;
+
+use crate::world::World;
+
+/// Storage state for a host.
+#[derive(Default)]
+pub(crate) struct Storage {
+    files: IndexMap<String, Contents>,
+
+    /// How long each operation takes
+    pub(crate) latency: Duration,
+
+    /// Whether operations fail
+    pub(crate) failing: bool,
+}
+
+#[derive(Default)]
+struct Contents {
+    /// Contents as seen by readers, including unsynced writes
+    current: Vec<u8>,
+
+    /// Contents as of the last sync, which survive a crash
+    durable: Vec<u8>,
+}
+
+impl Storage {
+    /// Discard unsynced writes, as when the host loses power.
+    pub(crate) fn crash(&mut self) {
+        for contents in self.files.values_mut() {
+            contents.current = contents.durable.clone();
+        }
+    }
+}
+
+/// A file in the current host's simulated storage.
+///
+/// All methods must be called from a host within a Turmoil simulation.
+#[derive(Debug)]
+pub struct File {
+    path: String,
+}
+
+impl File {
+    /// Opens the file at `path`, creating it if it doesn't exist.
+    pub async fn open(path: impl Into<String>) -> Result<File> {
+        let path = path.into();
+
+        op(|storage| {
+            storage.files.entry(path.clone()).or_default();
+            Ok(())
+        })
+        .await?;
+
+        Ok(File { path })
+    }
+
+    /// Appends `buf` to the file. The write is volatile until
+    /// [`File::sync_all`] is called.
+    pub async fn write_all(&self, buf: &[u8]) -> Result<()> {
+        op(|storage| {
+            contents(storage, &self.path)?
+                .current
+                .extend_from_slice(buf);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Reads the file's contents, including unsynced writes.
+    pub async fn read_to_end(&self) -> Result<Vec<u8>> {
+        op(|storage| Ok(contents(storage, &self.path)?.current.clone())).await
+    }
+
+    /// Truncates or extends the file to `size` bytes, filling with zeros. The
+    /// change is volatile until [`File::sync_all`] is called.
+    pub async fn set_len(&self, size: usize) -> Result<()> {
+        op(|storage| {
+            contents(storage, &self.path)?.current.resize(size, 0);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Makes all writes to the file durable, so they survive a crash.
+    pub async fn sync_all(&self) -> Result<()> {
+        op(|storage| {
+            let contents = contents(storage, &self.path)?;
+            contents.durable = contents.current.clone();
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Removes the file at `path` from the current host's storage.
+///
+/// Must be called from within a Turmoil simulation.
+pub async fn remove_file(path: &str) -> Result<()> {
+    op(|storage| {
+        storage
+            .files
+            .shift_remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    })
+    .await
+}
+
+/// Run `f` against the current host's storage after the storage latency.
+async fn op<R>(f: impl FnOnce(&mut Storage) -> Result<R>) -> Result<R> {
+    let latency = World::current(|world| world.current_host_mut().storage.latency);
+    crate::sleep(latency).await;
+
+    World::current(|world| {
+        let storage = &mut world.current_host_mut().storage;
+
+        if storage.failing {
+            return Err(io::Error::other("storage failure"));
+        }
+
+        f(storage)
+    })
+}
+
+fn contents<'a>(storage: &'a mut Storage, path: &str) -> Result<&'a mut Contents> {
+    storage.files.get_mut(path).ok_or_else(|| not_found(path))
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, path.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, io, rc::Rc, time::Duration};
+
+    use crate::{storage::File, Builder, Result};
+
+    #[test]
+    fn unsynced_writes_lost_on_crash() -> Result {
+        let mut sim = Builder::new().build();
+
+        let recovered = Rc::new(Cell::new(false));
+        let set = recovered.clone();
+
+        sim.host("db", move || {
+            let set = set.clone();
+
+            async move {
+                let wal = File::open("wal").await?;
+                let contents = wal.read_to_end().await?;
+
+                if contents.is_empty() {
+                    wal.write_all(b"synced").await?;
+                    wal.sync_all().await?;
+                    wal.write_all(b" unsynced").await?;
+                } else {
+                    assert_eq!(b"synced", &contents[..]);
+                    set.set(true);
+                }
+
+                std::future::pending().await
+            }
+        });
+
+        for _ in 0..10 {
+            sim.step()?;
+        }
+
+        sim.crash("db");
+        sim.bounce("db");
+
+        for _ in 0..10 {
+            sim.step()?;
+        }
+
+        assert!(recovered.get());
+
+        Ok(())
+    }
+
+    #[test]
+    fn latency_and_faults() -> Result {
+        let latency = Duration::from_millis(5);
+
+        let mut sim = Builder::new().build();
+
+        sim.client("db", async move {
+            let start = tokio::time::Instant::now();
+            let file = File::open("data").await?;
+            file.write_all(b"data").await?;
+            assert_eq!(latency * 2, start.elapsed());
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            let err = file.sync_all().await.unwrap_err();
+            assert_eq!(io::ErrorKind::Other, err.kind());
+
+            Ok(())
+        });
+
+        sim.set_storage_latency("db", latency);
+
+        // Fail storage after the write, while the client is sleeping
+        for _ in 0..20 {
+            sim.step()?;
+        }
+
+        sim.fail_storage("db");
+        sim.run()
+    }
+}
@@ -1,29 +1,167 @@
-use crate::envelope::{Envelope, Protocol};
-use crate::host::Host;
+use crate::capture::{Delivery, Tapped};
+use crate::decisions::Decisions;
+use crate::envelope::{Datagram, Envelope, Frame, Protocol, Segment};
+use crate::host::{Host, PacketFilter};
+use crate::report::{LinkReport, LinkState};
 use crate::rt::Rt;
-use crate::{config, TRACING_TARGET};
+use crate::{config, BandwidthSharing, LatencyTrace, SameTickOrder, TRACING_TARGET};
 
 use indexmap::IndexMap;
 use rand::{Rng, RngCore};
 use rand_distr::{Distribution, Exp};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
+use tokio::sync::mpsc;
 use tokio::time::Instant;
 
 /// Describes the network topology.
 pub(crate) struct Topology {
     config: config::Link,
 
+    /// Zone configuration, by hostname.
+    zones: config::Zones,
+
+    /// The zone of each registered host that belongs to one.
+    host_zones: IndexMap<IpAddr, String>,
+
+    /// Latency traces, by hostname.
+    latency_traces: config::LatencyTraces,
+
+    /// The name of each registered host, for finding its latency traces.
+    host_names: IndexMap<IpAddr, String>,
+
     /// Specific configuration overrides between specific hosts.
     links: IndexMap<Pair, Link>,
 
+    /// Messages delivered to hosts, if capture is enabled.
+    pub(crate) captured: Option<Vec<Delivery>>,
+
+    /// Random decisions made by links, recorded or replayed.
+    pub(crate) decisions: Decisions,
+
+    /// Whether links record the latency of each message they deliver.
+    pub(crate) record_latencies: bool,
+
+    /// If set, the most messages sent onto the network each tick. Excess
+    /// messages are deferred to the next tick.
+    pub(crate) max_messages_per_tick: Option<usize>,
+
+    /// Messages sent onto the network since the last tick.
+    pub(crate) messages_this_tick: usize,
+
+    /// Messages deferred by the per tick limit, in send order.
+    deferred: VecDeque<(SocketAddr, SocketAddr, Protocol, SendOptions)>,
+
+    /// The send order of the next message.
+    next_seq: u64,
+
+    /// Hosts with a capped NIC, which all their outbound messages share.
+    nics: IndexMap<IpAddr, Nic>,
+
+    /// When the network started, for timestamping tapped bytes.
+    epoch: Instant,
+
     /// We don't use a Rt for async. Right now, we just use it to tick time
     /// forward in the same way we do it elsewhere. We'd like to represent
     /// network state with async in the future.
     rt: Rt<'static>,
 }
 
+/// A host's network card, which sends one message at a time.
+struct Nic {
+    bytes_per_sec: u64,
+
+    /// Messages queued on the card
+    wire: Wire,
+}
+
+/// Messages being put on, or waiting to be put on, a rate limited wire, in the
+/// order they are sent.
+#[derive(Default)]
+struct Wire {
+    slots: VecDeque<Slot>,
+}
+
+/// When a message is put on a [`Wire`].
+struct Slot {
+    src: SocketAddr,
+    priority: u8,
+    seq: u64,
+    len: usize,
+    start: Instant,
+    end: Instant,
+}
+
+impl Wire {
+    /// Queue `message`, sent from `src`, which takes `duration` to put on the
+    /// wire, returning when it has been sent, and the send order of the waiting
+    /// messages it pushed back by `duration`.
+    ///
+    /// Higher priority messages go ahead of waiting ones, and messages of the
+    /// same priority keep send order. A message on the wire is never preempted.
+    fn push(
+        &mut self,
+        now: Instant,
+        src: SocketAddr,
+        message: &Protocol,
+        priority: u8,
+        seq: u64,
+        duration: Duration,
+    ) -> (Instant, Vec<u64>) {
+        self.slots.retain(|slot| slot.end > now);
+
+        let at = self
+            .slots
+            .iter()
+            .position(|slot| slot.start > now && slot.priority < priority)
+            .unwrap_or(self.slots.len());
+        let start = match at.checked_sub(1) {
+            Some(prev) => self.slots[prev].end.max(now),
+            None => now,
+        };
+        let end = start + duration;
+
+        let mut pushed_back = vec![];
+        for slot in self.slots.range_mut(at..) {
+            slot.start += duration;
+            slot.end += duration;
+            pushed_back.push(slot.seq);
+        }
+
+        self.slots.insert(
+            at,
+            Slot {
+                src,
+                priority,
+                seq,
+                len: payload_len(message),
+                start,
+                end,
+            },
+        );
+
+        (end, pushed_back)
+    }
+
+    /// Whether messages are still being put on the wire at `now`.
+    fn is_busy(&self, now: Instant) -> bool {
+        self.slots.back().is_some_and(|slot| slot.end > now)
+    }
+}
+
+/// Per-message options, set on the sending socket.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SendOptions {
+    /// Added on top of the link latency.
+    pub(crate) extra_latency: Duration,
+
+    /// Messages queued on a rate limited link or NIC, or that become
+    /// deliverable to a host together, go highest priority first, and in send
+    /// order within a priority.
+    pub(crate) priority: u8,
+}
+
 /// This type is used as the key in the [`Topology::links`] map. See [`new`]
 /// which orders the addrs, such that this type uniquely identifies the link
 /// between two hosts on the network.
@@ -141,11 +279,44 @@ struct Link {
     /// or are on hold.
     sent: VecDeque<Sent>,
 
-    /// Messages that are ready to be delivered.
-    deliverable: IndexMap<IpAddr, VecDeque<Envelope>>,
+    /// Messages that are ready to be delivered, with their priority and send
+    /// order.
+    deliverable: IndexMap<IpAddr, VecDeque<(u8, u64, Envelope)>>,
 
     /// The current network time, moved forward with [`Link::tick`].
     now: Instant,
+
+    /// A gradual change to the link's latency and loss, in progress.
+    degradation: Option<Degradation>,
+
+    /// Receives copies of TCP payloads delivered over the link.
+    tap: Option<mpsc::UnboundedSender<Tapped>>,
+
+    /// Recorded latency replayed in place of the configured latency, with
+    /// when the network started.
+    latency_trace: Option<(Instant, LatencyTrace)>,
+
+    /// Messages queued on the link, by connection, or by direction when the
+    /// rate isn't shared fairly.
+    wire: IndexMap<(SocketAddr, SocketAddr), Wire>,
+
+    /// Messages with a larger payload are silently dropped, if set.
+    blackhole_mtu: Option<usize>,
+
+    /// How many delivered messages had each latency, in 1ms buckets, if
+    /// recorded.
+    latencies: Option<BTreeMap<Duration, u64>>,
+}
+
+/// Linearly interpolates a link's latency and fail rate from their values at
+/// `start` to the targets over `over`.
+struct Degradation {
+    start: Instant,
+    over: Duration,
+    from_latency: (Duration, Duration),
+    from_fail_rate: f64,
+    target_latency: Duration,
+    target_fail_rate: f64,
 }
 
 enum State {
@@ -163,18 +334,68 @@ enum State {
 }
 
 impl Topology {
-    pub(crate) fn new(config: config::Link) -> Topology {
+    pub(crate) fn new(
+        config: config::Link,
+        zones: config::Zones,
+        latency_traces: config::LatencyTraces,
+    ) -> Topology {
+        let rt = Rt::no_software();
+
         Topology {
             config,
+            zones,
+            host_zones: IndexMap::new(),
+            latency_traces,
+            host_names: IndexMap::new(),
             links: IndexMap::new(),
-            rt: Rt::no_software(),
+            captured: None,
+            decisions: Decisions::default(),
+            record_latencies: false,
+            max_messages_per_tick: None,
+            messages_this_tick: 0,
+            deferred: VecDeque::new(),
+            next_seq: 0,
+            nics: IndexMap::new(),
+            epoch: rt.now(),
+            rt,
+        }
+    }
+
+    /// Register a host, placing it in its configured zone.
+    pub(crate) fn register_host(&mut self, addr: IpAddr, hostname: &str) {
+        if let Some(zone) = self.zones.hosts.get(hostname) {
+            self.host_zones.insert(addr, zone.clone());
         }
+
+        self.host_names.insert(addr, hostname.to_string());
     }
 
     /// Register a link between two hosts
     pub(crate) fn register(&mut self, a: IpAddr, b: IpAddr) {
         let pair = Pair::new(a, b);
-        assert!(self.links.insert(pair, Link::new(self.rt.now())).is_none());
+        let mut link = Link::new(self.rt.now());
+        if self.record_latencies {
+            link.latencies = Some(BTreeMap::new());
+        }
+
+        // Links between zones inherit the zone latency
+        if let (Some(za), Some(zb)) = (self.host_zones.get(&a), self.host_zones.get(&b)) {
+            if let Some((min, max)) = self.zones.latency(za, zb) {
+                let latency = link.latency(self.config.latency());
+                latency.min_message_latency = min;
+                latency.max_message_latency = max;
+            }
+        }
+
+        if let (Some(na), Some(nb)) = (self.host_names.get(&a), self.host_names.get(&b)) {
+            let key = if na < nb { (na, nb) } else { (nb, na) };
+
+            if let Some(trace) = self.latency_traces.get(&(key.0.clone(), key.1.clone())) {
+                link.latency_trace = Some((self.epoch, trace.clone()));
+            }
+        }
+
+        assert!(self.links.insert(pair, link).is_none());
     }
 
     pub(crate) fn set_max_message_latency(&mut self, value: Duration) {
@@ -187,6 +408,30 @@ impl Topology {
         latency.max_message_latency = value;
     }
 
+    pub(crate) fn set_link_tcp_latency(&mut self, a: IpAddr, b: IpAddr, value: Duration) {
+        self.links[&Pair::new(a, b)].config.tcp_latency = Some(self.fixed_latency(value));
+    }
+
+    pub(crate) fn set_link_udp_latency(&mut self, a: IpAddr, b: IpAddr, value: Duration) {
+        self.links[&Pair::new(a, b)].config.udp_latency = Some(self.fixed_latency(value));
+    }
+
+    pub(crate) fn set_link_bandwidth(&mut self, a: IpAddr, b: IpAddr, bytes_per_sec: u64) {
+        self.links[&Pair::new(a, b)].config.bandwidth = Some(bytes_per_sec);
+    }
+
+    pub(crate) fn set_link_blackhole_mtu(&mut self, a: IpAddr, b: IpAddr, mtu: usize) {
+        self.links[&Pair::new(a, b)].blackhole_mtu = Some(mtu);
+    }
+
+    /// The global latency config, with min and max set to `value`.
+    fn fixed_latency(&self, value: Duration) -> config::Latency {
+        let mut latency = self.config.latency().clone();
+        latency.min_message_latency = value;
+        latency.max_message_latency = value;
+        latency
+    }
+
     pub(crate) fn set_link_max_message_latency(&mut self, a: IpAddr, b: IpAddr, value: Duration) {
         self.links[&Pair::new(a, b)]
             .latency(self.config.latency())
@@ -207,6 +452,33 @@ impl Topology {
             .fail_rate = value;
     }
 
+    pub(crate) fn degrade_link(
+        &mut self,
+        a: IpAddr,
+        b: IpAddr,
+        fail_rate: f64,
+        latency: Duration,
+        over: Duration,
+    ) {
+        let link = &mut self.links[&Pair::new(a, b)];
+        let from_latency = link.latency(self.config.latency());
+        let from_latency = (
+            from_latency.min_message_latency,
+            from_latency.max_message_latency,
+        );
+        let from_fail_rate = link.message_loss(self.config.message_loss()).fail_rate;
+
+        link.degradation = Some(Degradation {
+            start: link.now,
+            over,
+            from_latency,
+            from_fail_rate,
+            target_latency: latency,
+            target_fail_rate: fail_rate,
+        });
+        link.degrade();
+    }
+
     // Send a `message` from `src` to `dst`. This method returns immediately,
     // and message delivery happens at a later time (or never, if the link is
     // broken).
@@ -216,18 +488,257 @@ impl Topology {
         src: SocketAddr,
         dst: SocketAddr,
         message: Protocol,
+        options: SendOptions,
+    ) {
+        if let Some(max) = self.max_messages_per_tick {
+            // Queue behind already deferred messages to keep send order
+            if self.messages_this_tick >= max || !self.deferred.is_empty() {
+                tracing::trace!(target: TRACING_TARGET, ?src, ?dst, protocol = %message, "Defer (Tick limit)");
+
+                self.deferred.push_back((src, dst, message, options));
+                return;
+            }
+        }
+
+        self.send_message(rand, src, dst, message, options);
+    }
+
+    fn send_message(
+        &mut self,
+        rand: &mut dyn RngCore,
+        src: SocketAddr,
+        dst: SocketAddr,
+        message: Protocol,
+        mut options: SendOptions,
     ) {
+        self.messages_this_tick += 1;
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        options.extra_latency += self.transmit(src, &message, options.priority, seq);
+
         let link = &mut self.links[&Pair::new(src.ip(), dst.ip())];
-        link.enqueue_message(&self.config, rand, src, dst, message);
+        let envelope = Envelope { src, dst, message };
+        link.enqueue_message(
+            &self.config,
+            &mut self.decisions,
+            rand,
+            envelope,
+            options,
+            seq,
+        );
+    }
+
+    /// Make every message in flight, i.e. sent and not held, deliverable now,
+    /// keeping their order. Returns how long until the last of them would
+    /// have been deliverable, or `None` if there were none.
+    pub(crate) fn flush(&mut self) -> Option<Duration> {
+        let mut last = None;
+
+        for link in self.links.values_mut() {
+            let now = link.now;
+
+            for sent in &mut link.sent {
+                if let DeliveryStatus::DeliverAfter(at) = sent.status {
+                    last = last.max(Some(at.saturating_duration_since(now)));
+                    sent.deliver(now);
+                }
+            }
+
+            link.process_deliverables();
+        }
+
+        last
+    }
+
+    /// How long until the next message in flight is deliverable, or `None` if
+    /// there are none. Messages that are already deliverable are due now.
+    pub(crate) fn next_delivery_in(&self) -> Option<Duration> {
+        let now = self.rt.now();
+
+        // Deferred messages are sent on the next tick
+        if !self.deferred.is_empty()
+            || self
+                .links
+                .values()
+                .flat_map(|link| link.deliverable.values())
+                .any(|queue| !queue.is_empty())
+        {
+            return Some(Duration::ZERO);
+        }
+
+        self.links
+            .values()
+            .flat_map(|link| &link.sent)
+            .filter_map(|sent| match sent.status {
+                DeliveryStatus::DeliverAfter(at) => Some(at.saturating_duration_since(now)),
+                DeliveryStatus::Hold => None,
+            })
+            .min()
+    }
+
+    /// The latencies of messages delivered between `a` and `b`, if recorded.
+    pub(crate) fn latency_histogram(&self, a: IpAddr, b: IpAddr) -> Option<Vec<(Duration, u64)>> {
+        let latencies = self.links[&Pair::new(a, b)].latencies.as_ref()?;
+
+        Some(latencies.iter().map(|(l, n)| (*l, *n)).collect())
+    }
+
+    /// Whether messages are in flight, in either direction, between `a` and
+    /// `b`. Held messages aren't in flight, and neither is loopback, which is
+    /// delivered immediately.
+    pub(crate) fn is_in_flight(&self, a: IpAddr, b: IpAddr) -> bool {
+        if a == b {
+            return false;
+        }
+
+        let link = &self.links[&Pair::new(a, b)];
+
+        link.sent
+            .iter()
+            .any(|sent| matches!(sent.status, DeliveryStatus::DeliverAfter(_)))
+            || link.deliverable.values().any(|queue| !queue.is_empty())
+            || self
+                .deferred
+                .iter()
+                .any(|(src, dst, ..)| Pair::new(src.ip(), dst.ip()) == Pair::new(a, b))
+    }
+
+    /// Whether `src` has as many bytes of datagrams waiting on bandwidth
+    /// limited links and NICs as its send buffer holds.
+    pub(crate) fn is_send_buffer_full(&self, src: SocketAddr) -> bool {
+        let Some(limit) = self.config.udp_send_buffer else {
+            return false;
+        };
+
+        let now = self.rt.now();
+        let wires = self
+            .nics
+            .values()
+            .map(|nic| &nic.wire)
+            .chain(self.links.values().flat_map(|link| link.wire.values()));
+
+        // A message may wait on both its host's NIC and its link
+        let mut waiting = IndexMap::new();
+        for slot in wires.flat_map(|wire| &wire.slots) {
+            if slot.src == src && slot.end > now {
+                waiting.insert(slot.seq, slot.len);
+            }
+        }
+
+        waiting.values().sum::<usize>() >= limit
+    }
+
+    /// Cap the outbound rate of `host`, across all of its links.
+    pub(crate) fn set_host_bandwidth(&mut self, host: IpAddr, bytes_per_sec: u64) {
+        self.nics
+            .entry(host)
+            .or_insert(Nic {
+                bytes_per_sec,
+                wire: Wire::default(),
+            })
+            .bytes_per_sec = bytes_per_sec;
+    }
+
+    /// Queue `message` on the NIC of `src`, if capped, returning how long
+    /// until it has been sent. Lower priority messages still waiting on the
+    /// NIC are delivered later.
+    fn transmit(
+        &mut self,
+        src: SocketAddr,
+        message: &Protocol,
+        priority: u8,
+        seq: u64,
+    ) -> Duration {
+        let Some(nic) = self.nics.get_mut(&src.ip()) else {
+            return Duration::ZERO;
+        };
+
+        let now = self.rt.now();
+        let duration = serialization_delay(message, nic.bytes_per_sec);
+        let (sent_at, pushed_back) = nic.wire.push(now, src, message, priority, seq, duration);
+
+        if !pushed_back.is_empty() {
+            for link in self.links.values_mut() {
+                link.postpone(&pushed_back, duration);
+            }
+        }
+
+        sent_at - now
     }
 
     // Move messages from any network links to the `dst` host.
     pub(crate) fn deliver_messages(&mut self, rand: &mut dyn RngCore, dst: &mut Host) {
-        for (pair, link) in &mut self.links {
-            if pair.0 == dst.addr || pair.1 == dst.addr {
-                link.deliver_messages(&self.config, rand, dst);
+        for message in self.take_messages(rand, dst.addr, dst.ingress_filter.as_ref()) {
+            let (src, dst_addr) = (message.src, message.dst);
+            if let Err(message) = dst.receive_from_network(message) {
+                if dst.permits_egress(dst_addr, src, &message) {
+                    self.enqueue_message(rand, dst_addr, src, message, SendOptions::default());
+                }
+            }
+        }
+    }
+
+    /// Take the messages deliverable to `dst`, without delivering them to a
+    /// host.
+    ///
+    /// Messages from each link keep their order, and messages from different
+    /// links are interleaved by the configured [`SameTickOrder`]. Messages
+    /// `filter` rejects are dropped.
+    pub(crate) fn take_messages(
+        &mut self,
+        rand: &mut dyn RngCore,
+        dst: IpAddr,
+        filter: Option<&PacketFilter>,
+    ) -> Vec<Envelope> {
+        let elapsed = self.rt.now().duration_since(self.epoch);
+
+        let mut queues = self
+            .links
+            .iter_mut()
+            .filter(|(pair, _)| pair.0 == dst || pair.1 == dst)
+            .map(|(_, link)| link.take_deliverable(dst, elapsed))
+            .filter(|queue| !queue.is_empty())
+            .collect::<Vec<_>>();
+
+        let mut messages = vec![];
+
+        while !queues.is_empty() {
+            let next = match self.config.same_tick_order {
+                SameTickOrder::Fifo => (0..queues.len())
+                    .min_by_key(|&i| queues[i].front().map(|(seq, _)| *seq))
+                    .unwrap(),
+                SameTickOrder::Random => rand.gen_range(0..queues.len()),
+            };
+
+            let (_, message) = queues[next].pop_front().unwrap();
+
+            if queues[next].is_empty() {
+                queues.remove(next);
             }
+
+            if filter.is_some_and(|filter| !filter(&Delivery::new(&message))) {
+                tracing::trace!(target: TRACING_TARGET, src = ?message.src, dst = ?message.dst, protocol = %message.message, "Drop (Ingress filter)");
+                continue;
+            }
+
+            if let Some(captured) = self.captured.as_mut() {
+                captured.push(Delivery::new(&message));
+            }
+
+            messages.push(message);
         }
+
+        messages
+    }
+
+    pub(crate) fn tap(&mut self, a: IpAddr, b: IpAddr, tx: mpsc::UnboundedSender<Tapped>) {
+        self.links[&Pair::new(a, b)].tap = Some(tx);
+    }
+
+    pub(crate) fn untap(&mut self, a: IpAddr, b: IpAddr) {
+        self.links[&Pair::new(a, b)].tap = None;
     }
 
     pub(crate) fn hold(&mut self, a: IpAddr, b: IpAddr) {
@@ -246,14 +757,84 @@ impl Topology {
         self.links[&Pair::new(a, b)].explicit_repair();
     }
 
-    pub(crate) fn tick_by(&mut self, duration: Duration) {
+    /// Whether the link between `a` and `b` is currently partitioned.
+    ///
+    /// A host is never partitioned from itself, as loopback doesn't use a
+    /// link.
+    pub(crate) fn is_partitioned(&self, a: IpAddr, b: IpAddr) -> bool {
+        a != b
+            && matches!(
+                self.links[&Pair::new(a, b)].state,
+                State::ExplicitPartition | State::RandPartition
+            )
+    }
+
+    /// Describe each link, resolving host names with `name`.
+    pub(crate) fn describe_links(&self, name: impl Fn(IpAddr) -> String) -> Vec<LinkReport> {
+        self.links
+            .iter()
+            .map(|(pair, link)| {
+                let latency = link.config.latency.as_ref();
+                let latency = latency.unwrap_or(self.config.latency());
+                let message_loss = link.config.message_loss.as_ref();
+                let message_loss = message_loss.unwrap_or(self.config.message_loss());
+
+                LinkReport {
+                    pair: (name(pair.0), name(pair.1)),
+                    state: match link.state {
+                        State::Healthy => LinkState::Healthy,
+                        State::ExplicitPartition | State::RandPartition => LinkState::Partitioned,
+                        State::Hold => LinkState::Held,
+                    },
+                    min_message_latency: latency.min_message_latency,
+                    max_message_latency: latency.max_message_latency,
+                    fail_rate: message_loss.fail_rate,
+                    repair_rate: message_loss.repair_rate,
+                }
+            })
+            .collect()
+    }
+
+    /// The probability that a message sent between `a` and `b` right now is
+    /// dropped.
+    pub(crate) fn effective_loss(&self, a: IpAddr, b: IpAddr) -> f64 {
+        // Loopback is never lost
+        if a == b {
+            return 0.0;
+        }
+
+        self.links[&Pair::new(a, b)].effective_loss(self.config.message_loss())
+    }
+
+    /// The minimum message latency between `a` and `b`.
+    pub(crate) fn min_message_latency(&self, a: IpAddr, b: IpAddr) -> Duration {
+        let link = &self.links[&Pair::new(a, b)];
+        let latency = link.config.latency.as_ref();
+
+        latency.unwrap_or(self.config.latency()).min_message_latency
+    }
+
+    pub(crate) fn tick_by(&mut self, rand: &mut dyn RngCore, duration: Duration) {
         let _ = self.rt.tick(duration);
+        self.messages_this_tick = 0;
+
+        // Send deferred messages, up to the limit
+        if let Some(max) = self.max_messages_per_tick {
+            while self.messages_this_tick < max {
+                let Some((src, dst, message, options)) = self.deferred.pop_front() else {
+                    break;
+                };
+
+                self.send_message(rand, src, dst, message, options);
+            }
+        }
+
         for link in self.links.values_mut() {
             link.tick(self.rt.now());
         }
     }
 
-    pub(crate) fn iter_mut(&mut self) -> LinksIter {
+    pub(crate) fn iter_mut(&mut self) -> LinksIter<'_> {
         LinksIter {
             iter: self.links.iter_mut(),
         }
@@ -265,6 +846,13 @@ struct Sent {
     dst: SocketAddr,
     status: DeliveryStatus,
     protocol: Protocol,
+    priority: u8,
+
+    /// Send order across the network
+    seq: u64,
+
+    /// When the message was sent
+    sent_at: Instant,
 }
 
 impl Sent {
@@ -286,21 +874,29 @@ impl Link {
             sent: VecDeque::new(),
             deliverable: IndexMap::new(),
             now,
+            degradation: None,
+            tap: None,
+            latency_trace: None,
+            wire: IndexMap::new(),
+            blackhole_mtu: None,
+            latencies: None,
         }
     }
 
     fn enqueue_message(
         &mut self,
         global_config: &config::Link,
+        decisions: &mut Decisions,
         rand: &mut dyn RngCore,
-        src: SocketAddr,
-        dst: SocketAddr,
-        message: Protocol,
+        envelope: Envelope,
+        options: SendOptions,
+        seq: u64,
     ) {
+        let Envelope { src, dst, message } = &envelope;
         tracing::trace!(target: TRACING_TARGET, ?src, ?dst, protocol = %message, "Send");
 
-        self.rand_partition_or_repair(global_config, rand);
-        self.enqueue(global_config, rand, src, dst, message);
+        self.rand_partition_or_repair(global_config, decisions, rand);
+        self.enqueue(global_config, decisions, rand, envelope, options, seq);
         self.process_deliverables();
     }
 
@@ -312,14 +908,35 @@ impl Link {
     fn enqueue(
         &mut self,
         global_config: &config::Link,
+        decisions: &mut Decisions,
         rand: &mut dyn RngCore,
-        src: SocketAddr,
-        dst: SocketAddr,
-        message: Protocol,
+        envelope: Envelope,
+        options: SendOptions,
+        seq: u64,
     ) {
+        let Envelope { src, dst, message } = envelope;
+
+        if self
+            .blackhole_mtu
+            .is_some_and(|mtu| payload_len(&message) > mtu)
+        {
+            tracing::trace!(target: TRACING_TARGET,?src, ?dst, protocol = %message, "Drop (MTU black hole)");
+
+            return;
+        }
+
         let status = match self.state {
+            State::Healthy
+                if self.congestion_drop(global_config.message_loss(), decisions, rand) =>
+            {
+                tracing::trace!(target: TRACING_TARGET,?src, ?dst, protocol = %message, "Drop (Congestion)");
+
+                return;
+            }
             State::Healthy => {
-                let delay = self.delay(global_config.latency(), rand);
+                let delay = self.delay(global_config, decisions, rand, &message)
+                    + self.serialize(global_config, src, dst, &message, options.priority, seq)
+                    + options.extra_latency;
                 DeliveryStatus::DeliverAfter(self.now + delay)
             }
             State::Hold => {
@@ -339,6 +956,9 @@ impl Link {
             dst,
             status,
             protocol: message,
+            priority: options.priority,
+            seq,
+            sent_at: self.now,
         };
 
         self.sent.push_back(sent);
@@ -346,9 +966,45 @@ impl Link {
 
     fn tick(&mut self, now: Instant) {
         self.now = now;
+        self.degrade();
         self.process_deliverables();
     }
 
+    /// Apply any degradation in progress for the current time.
+    fn degrade(&mut self) {
+        let Some(degradation) = &self.degradation else {
+            return;
+        };
+
+        let elapsed = self.now.duration_since(degradation.start);
+        let t = if elapsed >= degradation.over {
+            1.0
+        } else {
+            elapsed.as_secs_f64() / degradation.over.as_secs_f64()
+        };
+
+        let (min, max) = degradation.from_latency;
+        let target = degradation.target_latency;
+        let fail_rate = degradation.from_fail_rate
+            + (degradation.target_fail_rate - degradation.from_fail_rate) * t;
+
+        let latency = self.config.latency.as_mut().expect("`Latency` missing");
+        latency.min_message_latency = lerp(min, target, t);
+        latency.max_message_latency = lerp(max, target, t);
+
+        let message_loss = self
+            .config
+            .message_loss
+            .as_mut()
+            .expect("`MessageLoss` missing");
+        message_loss.fail_rate = fail_rate;
+
+        // Hold at the target once the window has passed
+        if t >= 1.0 {
+            self.degradation = None;
+        }
+    }
+
     fn process_deliverables(&mut self) {
         // TODO: `drain_filter` is not yet stable, and so we have a low quality
         // implementation here that avoids clones.
@@ -359,57 +1015,85 @@ impl Link {
             if let DeliveryStatus::DeliverAfter(time) = sent.status {
                 if time <= self.now {
                     let sent = self.sent.remove(index).unwrap();
+
+                    if let Some(latencies) = &mut self.latencies {
+                        let latency = time - sent.sent_at;
+                        let bucket = Duration::from_millis(latency.as_millis() as u64);
+                        *latencies.entry(bucket).or_default() += 1;
+                    }
+
                     let envelope = Envelope {
                         src: sent.src,
                         dst: sent.dst,
                         message: sent.protocol,
                     };
-                    self.deliverable
-                        .entry(sent.dst.ip())
-                        .or_default()
-                        .push_back(envelope);
+                    let queue = self.deliverable.entry(sent.dst.ip()).or_default();
+
+                    // Higher priority messages jump ahead, keeping send order
+                    // within a priority
+                    let at = queue.partition_point(|(p, _, _)| *p >= sent.priority);
+                    queue.insert(at, (sent.priority, sent.seq, envelope));
                     deliverable += 1;
                 }
             }
         }
     }
 
-    // FIXME: This implementation does not respect message delivery order. If
-    // host A and host B are ordered (by addr), and B sends before A, then this
-    // method will deliver A's message before B's.
-    fn deliver_messages(
-        &mut self,
-        global_config: &config::Link,
-        rand: &mut dyn RngCore,
-        host: &mut Host,
-    ) {
+    // Take the messages deliverable to `dst`, with their send order, in the
+    // order they are to be delivered.
+    fn take_deliverable(&mut self, dst: IpAddr, elapsed: Duration) -> VecDeque<(u64, Envelope)> {
         let deliverable = self
             .deliverable
-            .entry(host.addr)
+            .entry(dst)
             .or_default()
             .drain(..)
-            .collect::<Vec<Envelope>>();
+            .map(|(_, seq, envelope)| (seq, envelope))
+            .collect::<VecDeque<_>>();
+
+        for (_, message) in &deliverable {
+            self.tap(message, elapsed);
+        }
+
+        deliverable
+    }
+
+    // Send a copy of TCP data to the tap, removing it if the receiver is gone.
+    fn tap(&mut self, message: &Envelope, elapsed: Duration) {
+        let Some(tx) = &self.tap else {
+            return;
+        };
+
+        if let Protocol::Tcp(Segment::Data(_, data)) = &message.message {
+            let tapped = Tapped {
+                src: message.src,
+                dst: message.dst,
+                elapsed,
+                data: data.clone(),
+            };
 
-        for message in deliverable {
-            let (src, dst) = (message.src, message.dst);
-            if let Err(message) = host.receive_from_network(message) {
-                self.enqueue_message(global_config, rand, dst, src, message);
+            if tx.send(tapped).is_err() {
+                self.tap = None;
             }
         }
     }
 
     // Randomly break or repair this link.
-    fn rand_partition_or_repair(&mut self, global_config: &config::Link, rand: &mut dyn RngCore) {
+    fn rand_partition_or_repair(
+        &mut self,
+        global_config: &config::Link,
+        decisions: &mut Decisions,
+        rand: &mut dyn RngCore,
+    ) {
         match self.state {
-            State::Healthy => {
-                if self.rand_partition(global_config.message_loss(), rand) {
-                    self.state = State::RandPartition;
-                }
+            State::Healthy
+                if self.rand_partition(global_config.message_loss(), decisions, rand) =>
+            {
+                self.state = State::RandPartition;
             }
-            State::RandPartition => {
-                if self.rand_repair(global_config.message_loss(), rand) {
-                    self.release();
-                }
+            State::RandPartition
+                if self.rand_repair(global_config.message_loss(), decisions, rand) =>
+            {
+                self.release();
             }
             _ => {}
         }
@@ -438,27 +1122,147 @@ impl Link {
         self.state = State::Healthy;
     }
 
+    /// The probability that the next message is dropped, accounting for the
+    /// chance of the link being randomly partitioned or repaired first, and
+    /// for congestion.
+    fn effective_loss(&self, global: &config::MessageLoss) -> f64 {
+        let config = self.config.message_loss.as_ref().unwrap_or(global);
+        let congestion = match &config.congestion_drop {
+            Some(congestion_drop) => congestion_drop.drop_rate(self.sent.len()),
+            None => 0.0,
+        };
+
+        match self.state {
+            State::Healthy => config.fail_rate + (1.0 - config.fail_rate) * congestion,
+            State::RandPartition => (1.0 - config.repair_rate) + config.repair_rate * congestion,
+            State::ExplicitPartition => 1.0,
+            State::Hold => 0.0,
+        }
+    }
+
     /// Should the link be randomly partitioned
-    fn rand_partition(&self, global: &config::MessageLoss, rand: &mut dyn RngCore) -> bool {
+    fn rand_partition(
+        &self,
+        global: &config::MessageLoss,
+        decisions: &mut Decisions,
+        rand: &mut dyn RngCore,
+    ) -> bool {
         let config = self.config.message_loss.as_ref().unwrap_or(global);
         let fail_rate = config.fail_rate;
-        fail_rate > 0.0 && rand.gen_bool(fail_rate)
+        decisions.partition(|| fail_rate > 0.0 && rand.gen_bool(fail_rate))
     }
 
-    fn rand_repair(&self, global: &config::MessageLoss, rand: &mut dyn RngCore) -> bool {
+    fn rand_repair(
+        &self,
+        global: &config::MessageLoss,
+        decisions: &mut Decisions,
+        rand: &mut dyn RngCore,
+    ) -> bool {
         let config = self.config.message_loss.as_ref().unwrap_or(global);
         let repair_rate = config.repair_rate;
-        repair_rate > 0.0 && rand.gen_bool(repair_rate)
+        decisions.repair(|| repair_rate > 0.0 && rand.gen_bool(repair_rate))
+    }
+
+    /// Should the message be dropped due to congestion on the link
+    fn congestion_drop(
+        &self,
+        global: &config::MessageLoss,
+        decisions: &mut Decisions,
+        rand: &mut dyn RngCore,
+    ) -> bool {
+        let config = self.config.message_loss.as_ref().unwrap_or(global);
+        let drop_rate = match &config.congestion_drop {
+            Some(congestion_drop) => congestion_drop.drop_rate(self.sent.len()),
+            None => 0.0,
+        };
+        decisions.drop(|| drop_rate > 0.0 && rand.gen_bool(drop_rate))
+    }
+
+    fn delay(
+        &self,
+        global: &config::Link,
+        decisions: &mut Decisions,
+        rand: &mut dyn RngCore,
+        message: &Protocol,
+    ) -> Duration {
+        let config = match message {
+            Protocol::Tcp(_) => self.config.tcp_latency.as_ref(),
+            Protocol::Udp(_) => self.config.udp_latency.as_ref(),
+            Protocol::Icmp(_) | Protocol::Raw(_) => None,
+        };
+        let config = config
+            .or(self.config.latency.as_ref())
+            .unwrap_or(global.latency());
+
+        let delay = decisions.latency(|| match &self.latency_trace {
+            Some((epoch, trace)) => trace.latency_at(self.now - *epoch),
+            None => {
+                let mult = config.latency_distribution.sample(rand);
+                let range =
+                    (config.max_message_latency - config.min_message_latency).as_millis() as f64;
+                let delay = config.min_message_latency + Duration::from_millis((range * mult) as _);
+
+                std::cmp::min(delay, config.max_message_latency)
+            }
+        });
+
+        delay.max(global.latency_floor)
     }
 
-    fn delay(&self, global: &config::Latency, rand: &mut dyn RngCore) -> Duration {
-        let config = self.config.latency.as_ref().unwrap_or(global);
+    /// Queue `message` on the link, if its rate is limited, returning how long
+    /// until it has been sent. Lower priority messages still waiting on the
+    /// link are delivered later.
+    fn serialize(
+        &mut self,
+        global: &config::Link,
+        src: SocketAddr,
+        dst: SocketAddr,
+        message: &Protocol,
+        priority: u8,
+        seq: u64,
+    ) -> Duration {
+        let Some(bytes_per_sec) = self.config.bandwidth.or(global.bandwidth) else {
+            return Duration::ZERO;
+        };
+
+        let now = self.now;
+        self.wire.retain(|_, wire| wire.is_busy(now));
+
+        let (key, share) = match global.bandwidth_sharing {
+            BandwidthSharing::Fifo => {
+                let unspecified = |addr: SocketAddr| SocketAddr::new(addr.ip(), 0);
+                ((unspecified(src), unspecified(dst)), 1)
+            }
+            BandwidthSharing::Fair => {
+                let others = self
+                    .wire
+                    .keys()
+                    .filter(|key| key.0.ip() == src.ip() && **key != (src, dst))
+                    .count();
+                ((src, dst), others as u64 + 1)
+            }
+        };
+
+        let duration = serialization_delay(message, bytes_per_sec) * share as u32;
+        let (sent_at, pushed_back) = self
+            .wire
+            .entry(key)
+            .or_default()
+            .push(now, src, message, priority, seq, duration);
+        self.postpone(&pushed_back, duration);
 
-        let mult = config.latency_distribution.sample(rand);
-        let range = (config.max_message_latency - config.min_message_latency).as_millis() as f64;
-        let delay = config.min_message_latency + Duration::from_millis((range * mult) as _);
+        sent_at - now
+    }
 
-        std::cmp::min(delay, config.max_message_latency)
+    /// Delay delivery of the messages with the send orders `seqs` by `by`.
+    fn postpone(&mut self, seqs: &[u64], by: Duration) {
+        for sent in &mut self.sent {
+            if let DeliveryStatus::DeliverAfter(at) = &mut sent.status {
+                if seqs.contains(&sent.seq) {
+                    *at += by;
+                }
+            }
+        }
     }
 
     fn latency(&mut self, global: &config::Latency) -> &mut config::Latency {
@@ -471,3 +1275,30 @@ impl Link {
             .get_or_insert_with(|| global.clone())
     }
 }
+
+/// The size of the data `message` carries, in bytes.
+fn payload_len(message: &Protocol) -> usize {
+    match message {
+        Protocol::Tcp(Segment::Data(_, data)) => data.len(),
+        Protocol::Udp(Datagram(data)) | Protocol::Raw(Frame(data)) => data.len(),
+        _ => 0,
+    }
+}
+
+/// How long it takes to put `message` on a link with the given rate.
+fn serialization_delay(message: &Protocol, bytes_per_sec: u64) -> Duration {
+    let size = payload_len(message);
+
+    // Integer math keeps the delay exact, and so deterministic
+    let nanos = size as u128 * 1_000_000_000 / bytes_per_sec as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Linearly interpolate from `a` to `b`, where `t` is in `0.0..=1.0`.
+pub(crate) fn lerp(a: Duration, b: Duration, t: f64) -> Duration {
+    if b >= a {
+        a + (b - a).mul_f64(t)
+    } else {
+        a - (a - b).mul_f64(t)
+    }
+}
@@ -0,0 +1,55 @@
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// A recording of packets, which can be replayed into a simulation with
+/// [`Builder::replay`](crate::Builder::replay).
+///
+/// Packets are re-emitted at their recorded simulated time, alongside any
+/// traffic from running hosts. Only UDP datagrams are supported, as TCP
+/// segments depend on connection state that can't be replayed in isolation.
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    packets: Vec<Packet>,
+}
+
+/// A recorded packet.
+#[derive(Clone, Debug)]
+pub(crate) struct Packet {
+    /// Simulated time at which the packet is sent.
+    pub(crate) at: Duration,
+    pub(crate) src: SocketAddr,
+    pub(crate) dst: SocketAddr,
+    pub(crate) payload: Bytes,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a UDP datagram sent from `src` to `dst` `at` the given simulated
+    /// time.
+    pub fn udp(
+        &mut self,
+        at: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: impl Into<Bytes>,
+    ) -> &mut Self {
+        self.packets.push(Packet {
+            at,
+            src,
+            dst,
+            payload: payload.into(),
+        });
+        self
+    }
+
+    /// Recorded packets, ordered by the time they are sent.
+    pub(crate) fn into_packets(self) -> Vec<Packet> {
+        let mut packets = self.packets;
+        packets.sort_by_key(|p| p.at);
+        packets
+    }
+}
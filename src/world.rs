@@ -1,8 +1,15 @@
-use crate::envelope::Protocol;
-use crate::{config, Dns, Host, ToIpAddr, ToIpAddrs, Topology, TRACING_TARGET};
+use crate::builder::derive_seed;
+use crate::envelope::{Envelope, Protocol};
+use crate::net::SocketPair;
+use crate::top::SendOptions;
+use crate::{
+    config, Dns, Host, TcpModel, ToIpAddr, ToIpAddrs, ToSocketAddrs, Topology, TRACING_TARGET,
+};
 
+use bytes::{Buf, BytesMut};
 use indexmap::IndexMap;
-use rand::RngCore;
+use rand::rngs::SmallRng;
+use rand::{RngCore, SeedableRng};
 use scoped_tls::scoped_thread_local;
 use std::cell::RefCell;
 use std::net::{IpAddr, SocketAddr};
@@ -19,25 +26,60 @@ pub(crate) struct World {
     /// Maps hostnames to ip addresses.
     pub(crate) dns: Dns,
 
+    /// TCP configuration shared by all hosts.
+    pub(crate) tcp: config::Tcp,
+
+    /// Port allocation for newly registered hosts.
+    ports: config::Ports,
+
+    /// Bytes written to each TCP stream that have not yet been read by the
+    /// peer. Only tracked when validating TCP integrity.
+    tcp_unread: IndexMap<SocketPair, BytesMut>,
+
+    /// Members of each anycast address, in the order they were added.
+    anycast: IndexMap<IpAddr, Vec<IpAddr>>,
+
+    /// Replicas of each GeoDNS name, by the name's address, in the order they
+    /// were added.
+    geo_dns: IndexMap<IpAddr, Vec<IpAddr>>,
+
     /// If set, this is the current host being executed.
     pub(crate) current: Option<IpAddr>,
 
     /// Random number generator used for all decisions. To make execution
     /// determinstic, reuse the same seed.
     pub(crate) rng: Box<dyn RngCore>,
+
+    /// Base seed for each host's rng, which is combined with the host's name
+    /// so that hosts don't draw from `rng`.
+    host_seed: u64,
 }
 
 scoped_thread_local!(static CURRENT: RefCell<World>);
 
 impl World {
     /// Initialize a new world.
-    pub(crate) fn new(link: config::Link, rng: Box<dyn RngCore>) -> World {
+    pub(crate) fn new(
+        link: config::Link,
+        zones: config::Zones,
+        latency_traces: config::LatencyTraces,
+        tcp: config::Tcp,
+        ports: config::Ports,
+        rng: Box<dyn RngCore>,
+        host_seed: u64,
+    ) -> World {
         World {
             hosts: IndexMap::new(),
-            topology: Topology::new(link),
+            topology: Topology::new(link, zones, latency_traces),
             dns: Dns::new(),
+            tcp,
+            ports,
+            tcp_unread: IndexMap::new(),
+            anycast: IndexMap::new(),
+            geo_dns: IndexMap::new(),
             current: None,
             rng,
+            host_seed,
         }
     }
 
@@ -76,6 +118,20 @@ impl World {
         self.dns.lookup_many(hosts)
     }
 
+    /// The address of the registered host `name`, if any.
+    pub(crate) fn host_addr(&self, name: &str) -> Option<IpAddr> {
+        self.dns
+            .get(name)
+            .filter(|addr| self.hosts.contains_key(addr))
+    }
+
+    /// Whether `name` is the name a host was registered with, rather than an
+    /// alias.
+    pub(crate) fn is_host_name(&self, name: &str) -> bool {
+        self.host_addr(name)
+            .is_some_and(|addr| self.dns.reverse(addr) == name)
+    }
+
     pub(crate) fn hold(&mut self, a: IpAddr, b: IpAddr) {
         self.topology.hold(a, b);
     }
@@ -99,22 +155,277 @@ impl World {
             "already registered host for the given ip address"
         );
 
-        tracing::info!(target: TRACING_TARGET, hostname = ?self.dns.reverse(addr), ?addr, "New");
+        let hostname = self.dns.reverse(addr);
+
+        tracing::info!(target: TRACING_TARGET, ?hostname, ?addr, "New");
+
+        self.topology.register_host(addr, hostname);
 
         // Register links between the new host and all existing hosts
         for existing in self.hosts.keys() {
             self.topology.register(*existing, addr);
         }
 
-        // Initialize host state
-        self.hosts.insert(addr, Host::new(addr));
+        // Initialize host state, with an rng derived from the host's name so
+        // that it doesn't depend on registration order
+        let rng = SmallRng::seed_from_u64(derive_seed(self.host_seed, fnv1a(hostname)));
+        let mut host = Host::new(addr, rng, &self.ports);
+
+        if let TcpModel::AckGated { window } = self.tcp.model {
+            host.tcp.ack_window = Some(window);
+        }
+
+        self.hosts.insert(addr, host);
+    }
+
+    /// Retransmit TCP segments that have gone unacknowledged for too long.
+    pub(crate) fn retransmit_tcp(&mut self) {
+        let due = self
+            .hosts
+            .values_mut()
+            .flat_map(|host| host.tcp_retransmits())
+            .collect::<Vec<_>>();
+
+        for (pair, segment, extra_latency) in due {
+            tracing::trace!(target: TRACING_TARGET, src = ?pair.local, dst = ?pair.remote, protocol = %segment, "Retransmit");
+
+            self.send_message_with(
+                pair.local,
+                pair.remote,
+                Protocol::Tcp(segment),
+                SendOptions {
+                    extra_latency: extra_latency + self.tcp_directional_latency(pair),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    /// Whether no host will wake its software by itself. See
+    /// [`Host::is_quiescent`].
+    pub(crate) fn is_quiescent(&self) -> bool {
+        self.hosts
+            .values()
+            .all(|host| host.is_quiescent(self.tcp.idle_timeout))
+    }
+
+    /// Close TCP streams that have been idle for the idle timeout, on both
+    /// sides, if configured.
+    pub(crate) fn reap_idle_tcp(&mut self) {
+        let Some(timeout) = self.tcp.idle_timeout else {
+            return;
+        };
+
+        let idle = self
+            .hosts
+            .values()
+            .flat_map(|host| host.tcp_idle_streams(timeout))
+            .collect::<Vec<_>>();
+
+        for pair in idle {
+            if let Some(host) = self.hosts.get_mut(&pair.local.ip()) {
+                host.tcp.reap_stream(pair);
+            }
+
+            if let Some(peer) = self.hosts.get_mut(&pair.remote.ip()) {
+                peer.tcp
+                    .reap_stream(SocketPair::new(pair.remote, pair.local));
+            }
+        }
+    }
+
+    /// The latency to add to a segment sent on the stream `pair`, from the
+    /// outbound latency set by its sender and the inbound latency set by its
+    /// receiver. See [`TcpStream::set_directional_latency`].
+    ///
+    /// [`TcpStream::set_directional_latency`]: crate::net::TcpStream::set_directional_latency
+    pub(crate) fn tcp_directional_latency(&self, pair: SocketPair) -> Duration {
+        let outbound = self
+            .hosts
+            .get(&pair.local.ip())
+            .map_or(Duration::ZERO, |host| host.tcp.directional_latency(pair).0);
+
+        let inbound = self
+            .hosts
+            .get(&pair.remote.ip())
+            .map_or(Duration::ZERO, |host| {
+                host.tcp
+                    .directional_latency(SocketPair::new(pair.remote, pair.local))
+                    .1
+            });
+
+        outbound + inbound
+    }
+
+    /// Route datagrams sent to `addr` to the closest of `members`.
+    pub(crate) fn anycast(&mut self, addr: IpAddr, members: Vec<IpAddr>) {
+        assert!(
+            !self.hosts.contains_key(&addr),
+            "anycast address {addr} is owned by a host"
+        );
+
+        self.anycast.insert(addr, members);
+    }
+
+    /// If `dst` is an anycast address, route to the member closest to `src`.
+    pub(crate) fn anycast_route(&self, src: IpAddr, dst: SocketAddr) -> SocketAddr {
+        let Some(members) = self.anycast.get(&dst.ip()) else {
+            return dst;
+        };
+
+        match self.closest(src, members) {
+            Some(member) => (member, dst.port()).into(),
+            None => dst,
+        }
+    }
+
+    /// Resolve the name with address `addr` to the replicas in `replicas`.
+    pub(crate) fn geo_dns(&mut self, addr: IpAddr, replicas: Vec<IpAddr>) {
+        assert!(
+            !self.hosts.contains_key(&addr),
+            "GeoDNS name {addr} is owned by a host"
+        );
+
+        self.geo_dns.insert(addr, replicas);
+    }
+
+    /// Resolve `addr` from the current host.
+    ///
+    /// A GeoDNS name resolves to its replica closest to the current host, or
+    /// to its first registered replica outside of a host. Other addresses
+    /// resolve as usual.
+    pub(crate) fn resolve(&self, addr: IpAddr) -> IpAddr {
+        let Some(replicas) = self.geo_dns.get(&addr) else {
+            return addr;
+        };
+
+        let replica = match self.current {
+            Some(src) => self.closest(src, replicas),
+            None => replicas
+                .iter()
+                .copied()
+                .find(|replica| self.hosts.contains_key(replica)),
+        };
+
+        replica.unwrap_or(addr)
+    }
+
+    /// Resolve the socket address `addr` from the current host. See
+    /// [`World::resolve`].
+    pub(crate) fn resolve_socket_addr(&self, addr: impl ToSocketAddrs) -> SocketAddr {
+        let addr = addr.to_socket_addr(&self.dns);
+        (self.resolve(addr.ip()), addr.port()).into()
+    }
+
+    /// The registered host in `hosts` closest to `src`.
+    ///
+    /// The closest host is the one with the lowest minimum link latency,
+    /// preferring hosts that aren't partitioned from `src`. Ties are broken
+    /// by the order of `hosts`.
+    fn closest(&self, src: IpAddr, hosts: &[IpAddr]) -> Option<IpAddr> {
+        hosts
+            .iter()
+            .copied()
+            .filter(|host| self.hosts.contains_key(host))
+            .min_by_key(|&host| {
+                if host == src {
+                    (false, Duration::ZERO)
+                } else {
+                    (
+                        self.topology.is_partitioned(src, host),
+                        self.topology.min_message_latency(src, host),
+                    )
+                }
+            })
     }
 
     /// Send `message` from `src` to `dst`. Delivery is asynchronous and not
     /// guaranteed.
     pub(crate) fn send_message(&mut self, src: SocketAddr, dst: SocketAddr, message: Protocol) {
+        self.send_message_with(src, dst, message, SendOptions::default());
+    }
+
+    /// Send `message` from `src` to `dst` with the sending socket's `options`.
+    pub(crate) fn send_message_with(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        message: Protocol,
+        options: SendOptions,
+    ) {
+        if src.ip() == dst.ip() {
+            self.loopback(src, dst, message);
+            return;
+        }
+
+        if let Some(host) = self.hosts.get(&src.ip()) {
+            if !host.permits_egress(src, dst, &message) {
+                return;
+            }
+        }
+
+        // There's no link to an address no host owns, e.g. when replying to a
+        // spoofed source
+        if !self.hosts.contains_key(&dst.ip()) {
+            tracing::trace!(target: TRACING_TARGET, ?src, ?dst, protocol = %message, "Drop (No route)");
+            return;
+        }
+
         self.topology
-            .enqueue_message(&mut self.rng, src, dst, message);
+            .enqueue_message(&mut self.rng, src, dst, message, options);
+    }
+
+    /// Deliver a message a host sent to itself immediately, as there is no
+    /// link to send it over. Any reply, e.g. a TCP RST, is delivered the same
+    /// way.
+    fn loopback(&mut self, src: SocketAddr, dst: SocketAddr, message: Protocol) {
+        let host = self.hosts.get_mut(&dst.ip()).expect("missing host");
+
+        if let Err(reply) = host.receive_from_network(Envelope { src, dst, message }) {
+            let _ = host.receive_from_network(Envelope {
+                src: dst,
+                dst: src,
+                message: reply,
+            });
+        }
+    }
+
+    /// Forget any bytes recorded for a previous stream using `pair`.
+    pub(crate) fn reset_tcp_integrity(&mut self, pair: SocketPair) {
+        self.tcp_unread.swap_remove(&pair);
+        self.tcp_unread
+            .swap_remove(&SocketPair::new(pair.remote, pair.local));
+    }
+
+    /// Record `data` written to the stream `pair` for integrity validation.
+    pub(crate) fn tcp_written(&mut self, pair: SocketPair, data: &[u8]) {
+        if self.tcp.validate_integrity {
+            self.tcp_unread
+                .entry(pair)
+                .or_default()
+                .extend_from_slice(data);
+        }
+    }
+
+    /// Validate that `data` read from the stream `pair` is exactly the next
+    /// bytes written by the peer.
+    pub(crate) fn tcp_read(&mut self, pair: SocketPair, data: &[u8]) {
+        if !self.tcp.validate_integrity {
+            return;
+        }
+
+        let unread = self
+            .tcp_unread
+            .entry(SocketPair::new(pair.remote, pair.local))
+            .or_default();
+
+        assert!(
+            unread.starts_with(data),
+            "TCP integrity violated on {pair:?}: read {data:?}, but peer wrote {:?}",
+            &unread[..std::cmp::min(unread.len(), data.len())]
+        );
+
+        unread.advance(data.len());
     }
 
     /// Tick the host at `addr` by `duration`.
@@ -125,3 +436,10 @@ impl World {
             .tick(duration);
     }
 }
+
+/// FNV-1a hash of `name`, which unlike std's hashers is stable across runs.
+fn fnv1a(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
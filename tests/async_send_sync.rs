@@ -11,12 +11,14 @@ fn require_unpin<T: Unpin>(_t: &T) {}
 #[allow(dead_code)]
 struct Invalid;
 
+#[allow(dead_code)]
 trait AmbiguousIfSend<A> {
     fn some_item(&self) {}
 }
 impl<T: ?Sized> AmbiguousIfSend<()> for T {}
 impl<T: ?Sized + Send> AmbiguousIfSend<Invalid> for T {}
 
+#[allow(dead_code)]
 trait AmbiguousIfSync<A> {
     fn some_item(&self) {}
 }
@@ -93,6 +95,7 @@ macro_rules! assert_value {
 assert_value!(turmoil::net::TcpListener: Send & Sync & Unpin);
 assert_value!(turmoil::net::TcpStream: Send & Sync & Unpin);
 assert_value!(turmoil::net::UdpSocket: Send & Sync & Unpin);
+assert_value!(turmoil::net::RawSocket: Send & Sync & Unpin);
 assert_value!(turmoil::net::tcp::OwnedReadHalf: Send & Sync & Unpin);
 assert_value!(turmoil::net::tcp::OwnedWriteHalf: Send & Sync & Unpin);
 assert_value!(turmoil::net::tcp::ReuniteError: Send & Sync & Unpin);
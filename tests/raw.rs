@@ -0,0 +1,103 @@
+use std::{cell::Cell, io, rc::Rc, time::Duration};
+
+use turmoil::{lookup, net::RawSocket, Builder, Error, Result};
+
+const PROTOCOL: u8 = 253;
+
+#[test]
+fn ping_pong() -> Result {
+    let latency = Duration::from_millis(10);
+
+    let mut sim = Builder::new()
+        .min_message_latency(latency)
+        .max_message_latency(latency)
+        .build();
+
+    sim.host("server", || async {
+        let sock = RawSocket::bind(PROTOCOL).await?;
+
+        loop {
+            let (from, frame) = sock.recv_from().await?;
+            sock.send_to(&frame, from).await?;
+        }
+    });
+
+    sim.client("client", async move {
+        let sock = RawSocket::bind(PROTOCOL).await?;
+        assert_eq!(PROTOCOL, sock.protocol());
+
+        sock.send_to(b"ping", "server").await?;
+
+        let (from, frame) = sock.recv_from().await?;
+        assert_eq!(lookup("server"), from);
+        assert_eq!(&b"ping"[..], &frame[..]);
+        assert_eq!(latency * 2, turmoil::elapsed());
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn partition_drops_frames() -> Result {
+    let mut sim = Builder::new().build();
+
+    let received = Rc::new(Cell::new(0));
+    let count = received.clone();
+
+    sim.host("server", move || {
+        let count = count.clone();
+
+        async move {
+            let sock = RawSocket::bind(PROTOCOL).await?;
+
+            loop {
+                sock.recv_from().await?;
+                count.set(count.get() + 1);
+            }
+        }
+    });
+
+    sim.client("client", async move {
+        let sock = RawSocket::bind(PROTOCOL).await?;
+
+        turmoil::partition("client", "server");
+        sock.send_to(b"lost", "server").await?;
+
+        turmoil::repair("client", "server");
+        sock.send_to(b"delivered", "server").await?;
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        Ok(())
+    });
+
+    sim.run()?;
+    assert_eq!(1, received.get());
+
+    Ok(())
+}
+
+#[test]
+fn bind_and_send_errors() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.client("client", async move {
+        let sock = RawSocket::bind(PROTOCOL).await?;
+
+        let err = RawSocket::bind(PROTOCOL).await.err().unwrap();
+        assert_eq!(io::ErrorKind::AddrInUse, err.kind());
+
+        let err = sock.send_to(b"", "missing").await.unwrap_err();
+        assert!(matches!(Error::from_io(&err), Some(Error::HostNotFound(_))));
+
+        // The protocol can be bound again once the socket is dropped
+        drop(sock);
+        RawSocket::bind(PROTOCOL).await?;
+
+        Ok(())
+    });
+
+    sim.run()
+}
@@ -1,6 +1,7 @@
 use std::{
+    cell::{Cell, RefCell},
     io,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     rc::Rc,
     time::Duration,
 };
@@ -12,8 +13,9 @@ use tokio::{
     time::timeout,
 };
 use turmoil::{
+    lookup,
     net::{TcpListener, TcpStream},
-    Builder, Result,
+    Builder, DeliveryKind, Error, Icmp, Result, TcpModel,
 };
 
 const PORT: u16 = 1738;
@@ -162,6 +164,774 @@ fn network_partition_once_connected() -> Result {
     sim.run()
 }
 
+#[test]
+fn timeout_on_partition() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async move {
+        let listener = bind().await?;
+        let (_s, _) = listener.accept().await?;
+
+        future::pending().await
+    });
+
+    sim.client("client", async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        turmoil::partition("server", "client");
+
+        let start = tokio::time::Instant::now();
+        let res = turmoil::timeout(Duration::from_millis(250), s.read_u8()).await;
+        assert!(res.is_err());
+        assert_eq!(Duration::from_millis(250), start.elapsed());
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn inject_icmp() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+
+        loop {
+            let (mut s, _) = listener.accept().await?;
+            let byte = s.read_u8().await?;
+            s.write_u8(byte).await?;
+        }
+    });
+
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        // The reader is blocked when the error is injected
+        assert_error_kind(s.read_u8().await, io::ErrorKind::HostUnreachable);
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_error_kind(s.write_u8(1).await, io::ErrorKind::InvalidInput);
+
+        // The errors are transient
+        s.write_u8(2).await?;
+        assert_eq!(2, s.read_u8().await?);
+
+        Ok(())
+    });
+
+    for _ in 0..100 {
+        sim.step()?;
+    }
+
+    sim.inject_icmp("client", "server", Icmp::HostUnreachable);
+    sim.step()?;
+    sim.inject_icmp("client", "server", Icmp::FragmentationNeeded);
+
+    sim.run()
+}
+
+#[test]
+fn blackhole_mtu() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        let mut buf = [0; 512];
+        loop {
+            let n = s.read(&mut buf).await?;
+            s.write_all(&buf[..n]).await?;
+        }
+    });
+
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+        let mut buf = [0; 512];
+
+        s.write_all(&[1; 64]).await?;
+        assert_eq!(64, s.read(&mut buf).await?);
+
+        // The large segment vanishes, however often it's retransmitted
+        s.write_all(&[2; 256]).await?;
+        assert!(timeout(Duration::from_secs(5), s.read(&mut buf))
+            .await
+            .is_err());
+
+        Ok(())
+    });
+
+    sim.set_link_blackhole_mtu("client", "server", 128);
+
+    sim.run()
+}
+
+#[test]
+fn fail_writes_on_partition() -> Result {
+    let mut sim = Builder::new().fail_writes_on_partition(true).build();
+
+    sim.client("server", async move {
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        assert_eq!(1, s.read_u8().await?);
+
+        Ok(())
+    });
+
+    sim.client("client", async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        turmoil::partition("server", "client");
+
+        assert_error_kind(s.write_u8(1).await, io::ErrorKind::HostUnreachable);
+
+        turmoil::repair("server", "client");
+
+        s.write_u8(1).await?;
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn connect_by_ip() -> Result {
+    let mut sim = Builder::new().build();
+
+    let server = sim.lookup("server");
+
+    // The alias no longer resolves to the server, but its IP still connects
+    sim.add_dns("db", "server");
+    sim.remove_dns("db");
+    assert_eq!(None, sim.host_addr("db"));
+
+    sim.client("server", async move {
+        let listener = bind().await?;
+        let _ = listener.accept().await?;
+
+        Ok(())
+    });
+
+    sim.client("client", async move {
+        let unknown = IpAddr::from(Ipv4Addr::new(10, 0, 0, 1));
+
+        let err = TcpStream::connect((unknown, PORT)).await.unwrap_err();
+        assert_eq!(io::ErrorKind::HostUnreachable, err.kind());
+        assert_eq!(Some(&Error::HostNotFound(unknown)), Error::from_io(&err));
+
+        let _ = TcpStream::connect((server, PORT)).await?;
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn unsupported_bind() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.client("server", async {
+        let addr = (IpAddr::from(Ipv4Addr::LOCALHOST), PORT);
+
+        let err = TcpListener::bind(addr).await.err().unwrap();
+        assert_eq!(io::ErrorKind::AddrNotAvailable, err.kind());
+        assert_eq!(
+            Some(&Error::UnsupportedBind(addr.into())),
+            Error::from_io(&err)
+        );
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn connect_to_self() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.client("client", async {
+        let addr = (lookup("client"), PORT);
+
+        // Nothing is bound, so the connection is refused without waiting
+        let start = tokio::time::Instant::now();
+        assert_error_kind(
+            TcpStream::connect(addr).await,
+            io::ErrorKind::ConnectionRefused,
+        );
+        assert_eq!(Duration::ZERO, start.elapsed());
+
+        let listener = bind().await?;
+        let accept = tokio::task::spawn_local(async move { listener.accept().await });
+
+        let mut s = TcpStream::connect(addr).await?;
+        let (mut peer, _) = accept.await??;
+
+        s.write_u8(1).await?;
+        assert_eq!(1, peer.read_u8().await?);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn connect_to_self_fail_writes_on_partition() -> Result {
+    let mut sim = Builder::new().fail_writes_on_partition(true).build();
+
+    sim.client("client", async {
+        let listener = bind().await?;
+        let accept = tokio::task::spawn_local(async move { listener.accept().await });
+
+        let mut s = TcpStream::connect((lookup("client"), PORT)).await?;
+        let (mut peer, _) = accept.await??;
+
+        // Loopback doesn't use a link, so is never partitioned
+        s.write_u8(1).await?;
+        assert_eq!(1, peer.read_u8().await?);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn syn_retries() -> Result {
+    let backoff = Duration::from_millis(100);
+
+    let mut sim = Builder::new().syn_retries(2, backoff).build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+
+        loop {
+            let _ = listener.accept().await?;
+        }
+    });
+
+    sim.client("client", async move {
+        turmoil::partition("client", "server");
+
+        // Repair between the first and second retransmission
+        tokio::task::spawn_local(async move {
+            tokio::time::sleep(backoff * 2).await;
+            turmoil::repair("client", "server");
+        });
+
+        let start = tokio::time::Instant::now();
+        let _ = TcpStream::connect(("server", PORT)).await?;
+        assert!(start.elapsed() >= backoff * 3);
+
+        // Every retransmission is lost
+        turmoil::partition("client", "server");
+
+        let start = tokio::time::Instant::now();
+        assert_error_kind(
+            TcpStream::connect(("server", PORT)).await,
+            io::ErrorKind::ConnectionRefused,
+        );
+        assert_eq!(backoff * 3, start.elapsed());
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn duplicate_syns() -> Result {
+    let latency = Duration::from_millis(50);
+    let accepted = Rc::new(Cell::new(0));
+
+    // SYNs are retransmitted at 10ms, 30ms and 70ms, all before the first
+    // arrives
+    let mut sim = fixed_latency(latency)
+        .syn_retries(3, Duration::from_millis(10))
+        .retransmit_delayed_syns(true)
+        .build();
+
+    let count = accepted.clone();
+    sim.host("server", move || {
+        let count = count.clone();
+
+        async move {
+            let listener = bind().await?;
+
+            // The first connection's duplicates arrive while it is pending
+            tokio::time::sleep(latency * 4).await;
+
+            loop {
+                let _ = listener.accept().await?;
+                count.set(count.get() + 1);
+            }
+        }
+    });
+
+    sim.client("client", async move {
+        let _first = TcpStream::connect(("server", PORT)).await?;
+
+        // The second connection's duplicates arrive once it is accepted
+        let _second = TcpStream::connect(("server", PORT)).await?;
+
+        tokio::time::sleep(latency * 4).await;
+        assert_eq!(2, accepted.get());
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn accept_delay() -> Result {
+    let delay = Duration::from_millis(500);
+
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+
+        loop {
+            let _ = listener.accept().await?;
+        }
+    });
+
+    sim.client("client", async move {
+        let connect = TcpStream::connect(("server", PORT));
+        assert!(timeout(delay / 2, connect).await.is_err());
+
+        let start = tokio::time::Instant::now();
+        let _ = TcpStream::connect(("server", PORT)).await?;
+        assert!(start.elapsed() >= delay);
+
+        Ok(())
+    });
+
+    sim.set_accept_delay("server", delay);
+
+    sim.run()
+}
+
+#[test]
+fn syn_flood() -> Result {
+    let mut sim = Builder::new()
+        .max_message_latency(Duration::from_millis(1))
+        .build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+
+        loop {
+            let _ = listener.accept().await?;
+        }
+    });
+
+    sim.host("attacker", future::pending);
+
+    sim.client("client", async move {
+        // The flood fills the backlog
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_error_kind(
+            TcpStream::connect(("server", PORT)).await,
+            io::ErrorKind::ConnectionRefused,
+        );
+
+        // Once the flood stops, the backlog drains
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let _ = TcpStream::connect(("server", PORT)).await?;
+
+        Ok(())
+    });
+
+    sim.set_accept_delay("server", Duration::from_millis(100));
+    sim.syn_flood(
+        ("server", PORT),
+        "attacker",
+        1000,
+        Duration::from_millis(200),
+    );
+
+    sim.run()
+}
+
+#[test]
+fn half_open_limit() -> Result {
+    let mut sim = Builder::new()
+        .max_message_latency(Duration::from_millis(1))
+        .build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+        listener.set_half_open_limit(8);
+
+        loop {
+            let _ = listener.accept().await?;
+        }
+    });
+
+    sim.host("attacker", future::pending);
+
+    sim.client("client", async move {
+        // The flood fills the SYN_RECV queue, well before the backlog, and the
+        // connect times out rather than being refused
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(timeout(
+            Duration::from_millis(300),
+            TcpStream::connect(("server", PORT))
+        )
+        .await
+        .is_err());
+
+        // Once the flood stops, half-open connections are accepted away
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let _ = TcpStream::connect(("server", PORT)).await?;
+
+        Ok(())
+    });
+
+    sim.set_accept_delay("server", Duration::from_millis(100));
+    sim.syn_flood(
+        ("server", PORT),
+        "attacker",
+        1000,
+        Duration::from_millis(200),
+    );
+
+    sim.run()
+}
+
+#[test]
+fn validate_tcp_integrity() -> Result {
+    let mut sim = Builder::new()
+        .min_message_latency(Duration::from_millis(1))
+        .max_message_latency(Duration::from_millis(50))
+        .validate_tcp_integrity(true)
+        .build();
+
+    sim.client("server", async move {
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        let mut buf = vec![];
+        s.read_to_end(&mut buf).await?;
+
+        assert_eq!((0..100).collect::<Vec<u8>>(), buf);
+
+        Ok(())
+    });
+
+    sim.client("client", async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        for chunk in (0..100).collect::<Vec<u8>>().chunks(10) {
+            s.write_all(chunk).await?;
+        }
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn extra_latency() -> Result {
+    let latency = Duration::from_millis(2);
+    let extra = Duration::from_millis(10);
+
+    let mut sim = Builder::new()
+        .min_message_latency(latency)
+        .max_message_latency(latency)
+        .build();
+
+    sim.host("server", move || async move {
+        let listener = bind().await?;
+
+        // The first connection is proxied
+        let (mut s, _) = listener.accept().await?;
+        s.set_extra_latency(extra);
+        s.write_u8(1).await?;
+
+        let (mut s, _) = listener.accept().await?;
+        s.write_u8(2).await?;
+
+        future::pending().await
+    });
+
+    sim.client("client", async move {
+        for expected in [latency + extra, latency] {
+            let mut s = TcpStream::connect(("server", PORT)).await?;
+
+            let start = tokio::time::Instant::now();
+            s.read_u8().await?;
+            assert_eq!(expected, start.elapsed());
+        }
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn jitter() -> Result {
+    fn arrivals(seed: u64) -> Result<Vec<Duration>> {
+        let latency = Duration::from_millis(2);
+
+        let mut sim = Builder::from_master_seed(seed, 0)
+            .min_message_latency(latency)
+            .max_message_latency(latency)
+            .build();
+
+        sim.host("server", || async {
+            let listener = bind().await?;
+            let (mut s, _) = listener.accept().await?;
+            s.set_jitter(Duration::from_millis(20));
+
+            for i in 0..10 {
+                s.write_u8(i).await?;
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+
+            future::pending().await
+        });
+
+        let arrivals = Rc::new(RefCell::new(vec![]));
+        let record = arrivals.clone();
+
+        sim.client("client", async move {
+            let mut s = TcpStream::connect(("server", PORT)).await?;
+
+            for i in 0..10 {
+                assert_eq!(i, s.read_u8().await?);
+                record.borrow_mut().push(turmoil::elapsed());
+            }
+
+            Ok(())
+        });
+
+        sim.run()?;
+
+        let arrivals = arrivals.borrow().clone();
+        Ok(arrivals)
+    }
+
+    let first = arrivals(1)?;
+    assert_eq!(first, arrivals(1)?);
+
+    // Sent 1ms apart, but not received that way
+    assert!(first
+        .windows(2)
+        .any(|w| w[1] - w[0] != Duration::from_millis(1)));
+
+    Ok(())
+}
+
+#[test]
+fn directional_latency() -> Result {
+    let latency = Duration::from_millis(2);
+    let slow = Duration::from_millis(20);
+
+    let mut sim = fixed_latency(latency).build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+
+        loop {
+            let (mut s, _) = listener.accept().await?;
+            tokio::spawn(async move {
+                while let Ok(n) = s.read_u8().await {
+                    s.write_u8(n).await?;
+                }
+                Ok::<_, io::Error>(())
+            });
+        }
+    });
+
+    sim.client("client", async move {
+        // Responses on the first connection are slow
+        let mut first = TcpStream::connect(("server", PORT)).await?;
+        first.set_directional_latency(Duration::ZERO, slow);
+
+        let mut second = TcpStream::connect(("server", PORT)).await?;
+
+        for (s, expected) in [(&mut first, latency * 2 + slow), (&mut second, latency * 2)] {
+            let start = tokio::time::Instant::now();
+            s.write_u8(1).await?;
+            s.read_u8().await?;
+            assert_eq!(expected, start.elapsed());
+        }
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn tcp_read_boundary() -> Result {
+    let mut sim = Builder::new().tcp_read_boundary(8).build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        for chunk in [&[1, 2][..], &[3, 4, 5], &[6, 7, 8], &[9]] {
+            s.write_all(chunk).await?;
+        }
+
+        Ok(())
+    });
+
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        // Reads are held until the boundary is reached
+        let mut buf = [0; 16];
+        assert_eq!(8, s.read(&mut buf).await?);
+        assert_eq!([1, 2, 3, 4, 5, 6, 7, 8], buf[..8]);
+
+        // The remainder is released when the stream is closed
+        assert_eq!(1, s.read(&mut buf).await?);
+        assert_eq!(9, buf[0]);
+        assert_eq!(0, s.read(&mut buf).await?);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn max_read_chunk() -> Result {
+    let mut sim = Builder::new().max_read_chunk(3).build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        s.write_all(&[1, 2, 3, 4, 5, 6, 7]).await?;
+
+        Ok(())
+    });
+
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        let mut buf = [0; 16];
+        assert_eq!(3, s.read(&mut buf).await?);
+        assert_eq!([1, 2, 3], buf[..3]);
+        assert_eq!(3, s.read(&mut buf).await?);
+        assert_eq!([4, 5, 6], buf[..3]);
+        assert_eq!(1, s.read(&mut buf).await?);
+        assert_eq!(7, buf[0]);
+        assert_eq!(0, s.read(&mut buf).await?);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn ack_gated_window() -> Result {
+    let latency = Duration::from_millis(10);
+
+    let mut sim = Builder::new()
+        .min_message_latency(latency)
+        .max_message_latency(latency)
+        .tcp_model(TcpModel::AckGated { window: 4 })
+        .build();
+
+    sim.client("server", async move {
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        let mut buf = [0; 4];
+        s.read_exact(&mut buf).await?;
+        assert_eq!([1, 2, 3, 4], buf);
+
+        // The second half waits a round trip for the first to be acknowledged
+        let start = tokio::time::Instant::now();
+        s.read_exact(&mut buf).await?;
+        assert_eq!([5, 6, 7, 8], buf);
+        assert_eq!(latency * 2, start.elapsed());
+
+        Ok(())
+    });
+
+    sim.host("client", || async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        let start = tokio::time::Instant::now();
+        s.write_all(&[1, 2, 3, 4, 5, 6, 7, 8]).await?;
+        assert_eq!(latency * 2, start.elapsed());
+
+        future::pending().await
+    });
+
+    sim.run()
+}
+
+#[test]
+fn ack_gated_retransmit() -> Result {
+    let latency = Duration::from_millis(10);
+
+    let mut sim = Builder::new()
+        .min_message_latency(latency)
+        .max_message_latency(latency)
+        .tcp_model(TcpModel::AckGated { window: 64 })
+        .capture(true)
+        .build();
+
+    sim.host("server", || async move {
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        assert_eq!(1, s.read_u8().await?);
+
+        future::pending().await
+    });
+
+    sim.client("client", async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        s.write_u8(1).await?;
+
+        // The segment is in flight, and its ACK is lost
+        turmoil::partition("client", "server");
+        tokio::time::sleep(latency * 2).await;
+        turmoil::repair("client", "server");
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        Ok(())
+    });
+
+    sim.run()?;
+
+    // The segment is delivered a second time, once retransmitted
+    let seen = Cell::new(0);
+    sim.assert_delivered("client", "server", |d| {
+        if d.kind == DeliveryKind::TcpData {
+            seen.set(seen.get() + 1);
+        }
+
+        seen.get() == 2
+    });
+    sim.assert_not_delivered("client", "server", |d| {
+        d.kind == DeliveryKind::TcpData && d.payload != [1][..]
+    });
+
+    Ok(())
+}
+
 #[test]
 fn accept_front_of_line_blocking() -> Result {
     let wait = Rc::new(Notify::new());
@@ -308,6 +1078,203 @@ fn server_concurrency() -> Result {
     sim.run()
 }
 
+#[test]
+fn idle_timeout() -> Result {
+    let timeout = Duration::from_millis(100);
+
+    let mut sim = fixed_latency(Duration::from_millis(1))
+        .idle_timeout(timeout)
+        .build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+
+        loop {
+            let (mut s, _) = listener.accept().await?;
+            tokio::spawn(async move {
+                while let Ok(n) = s.read_u8().await {
+                    let _ = s.write_u8(n).await;
+                }
+
+                assert_eq!(0, s.read(&mut [0; 1]).await.unwrap());
+            });
+        }
+    });
+
+    sim.client("client", async move {
+        let mut idle = TcpStream::connect(("server", PORT)).await?;
+        let mut busy = TcpStream::connect(("server", PORT)).await?;
+
+        // Traffic resets the timeout
+        for i in 0..5 {
+            busy.write_u8(i).await?;
+            assert_eq!(i, busy.read_u8().await?);
+            tokio::time::sleep(timeout / 2).await;
+        }
+
+        assert_eq!(0, idle.read(&mut [0; 1]).await?);
+        assert_error_kind(idle.write_u8(1).await, io::ErrorKind::BrokenPipe);
+
+        busy.write_u8(5).await?;
+        assert_eq!(5, busy.read_u8().await?);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn reconnect_storm_threshold() -> Result {
+    let setup = |pause: Duration| {
+        move || {
+            let mut sim = Builder::new()
+                .reconnect_storm_threshold(3, Duration::from_secs(1))
+                .build();
+
+            sim.host("server", || async {
+                let listener = bind().await?;
+                loop {
+                    listener.accept().await?;
+                }
+            });
+
+            sim.client("client", async move {
+                for _ in 0..10 {
+                    TcpStream::connect(("server", PORT)).await?;
+                    tokio::time::sleep(pause).await;
+                }
+
+                Ok(())
+            });
+
+            sim
+        }
+    };
+
+    // Connects fall out of the window as time passes
+    turmoil::run(setup(Duration::from_millis(400)))?;
+
+    let failure = turmoil::run(setup(Duration::from_millis(100))).unwrap_err();
+    assert_eq!(Some("client"), failure.host.as_deref());
+    assert!(failure.panicked);
+    assert!(
+        failure
+            .message
+            .starts_with("reconnect storm: client opened 4 connections to server"),
+        "{}",
+        failure.message
+    );
+
+    Ok(())
+}
+
+#[test]
+fn connect_secure() -> Result {
+    let mut sim = fixed_latency(Duration::from_millis(10))
+        .secure_handshake(2, 0.0)
+        .build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+        loop {
+            let (mut s, _) = listener.accept().await?;
+            s.write_u8(1).await?;
+        }
+    });
+
+    sim.client("client", async {
+        let start = tokio::time::Instant::now();
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+        let rtt = start.elapsed();
+        assert_eq!(1, s.read_u8().await?);
+
+        // The handshake's round trips follow the connect's
+        let start = tokio::time::Instant::now();
+        let mut s = TcpStream::connect_secure(("server", PORT)).await?;
+        assert_eq!(rtt * 3, start.elapsed());
+        assert_eq!(1, s.read_u8().await?);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn connect_secure_handshake_failure() -> Result {
+    let mut sim = Builder::new().secure_handshake(1, 1.0).build();
+
+    sim.client("server", async {
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        assert_error_kind(s.read_u8().await, io::ErrorKind::ConnectionReset);
+
+        Ok(())
+    });
+
+    sim.client("client", async {
+        let err = TcpStream::connect_secure(("server", PORT))
+            .await
+            .unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            Some(&Error::HandshakeFailed((lookup("server"), PORT).into())),
+            Error::from_io(&err)
+        );
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn simultaneous_connect() -> Result {
+    let run = || -> Result<Vec<(String, SocketAddr, SocketAddr)>> {
+        let mut sim = Builder::from_master_seed(3, 0).build();
+        let conns = Rc::new(RefCell::new(vec![]));
+
+        for (host, peer) in [("a", "b"), ("b", "a")] {
+            let conns = conns.clone();
+
+            sim.client(host, async move {
+                let listener = bind().await?;
+
+                // Both hosts connect in the same tick, before either accepts
+                let (connected, accepted) =
+                    tokio::join!(TcpStream::connect((peer, PORT)), listener.accept());
+                let (mut connected, (mut accepted, _)) = (connected?, accepted?);
+
+                connected.write_u8(1).await?;
+                assert_eq!(1, accepted.read_u8().await?);
+
+                conns.borrow_mut().push((
+                    host.to_string(),
+                    connected.local_addr()?,
+                    connected.peer_addr()?,
+                ));
+
+                Ok(())
+            });
+        }
+
+        sim.run()?;
+
+        let conns = conns.borrow().clone();
+        Ok(conns)
+    };
+
+    let conns = run()?;
+    assert_eq!(2, conns.len());
+    assert_ne!(conns[0].1, conns[1].2);
+    assert_eq!(conns, run()?);
+
+    Ok(())
+}
+
 #[test]
 fn drop_listener() -> Result {
     let how_many_conns = 3;
@@ -371,6 +1338,73 @@ fn drop_listener() -> Result {
     sim.run()
 }
 
+#[test]
+fn accepted_stream_outlives_listener() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+        let (mut first, _) = listener.accept().await?;
+        drop(listener);
+
+        // Data flows both ways after the listener is gone
+        let n = first.read_u8().await?;
+        first.write_u8(n + 1).await?;
+
+        // The port can be bound again while the accepted stream is open
+        let listener = bind().await?;
+        let (mut second, _) = listener.accept().await?;
+        second.write_u8(2).await?;
+
+        first.write_u8(n + 2).await?;
+
+        future::pending().await
+    });
+
+    sim.client("client", async {
+        let mut first = TcpStream::connect(("server", PORT)).await?;
+
+        first.write_u8(1).await?;
+        assert_eq!(2, first.read_u8().await?);
+
+        let mut second = TcpStream::connect(("server", PORT)).await?;
+        assert_eq!(2, second.read_u8().await?);
+        assert_eq!(3, first.read_u8().await?);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn close_listener() -> Result {
+    let mut sim = Builder::new().build();
+
+    let leaked = Rc::new(RefCell::new(None));
+    let slot = leaked.clone();
+
+    sim.client("server", async move {
+        let listener = bind().await?;
+        listener.close()?;
+
+        // The port can be bound again once closed
+        let listener = bind().await?;
+        *slot.borrow_mut() = Some(listener);
+
+        Ok(())
+    });
+
+    sim.run()?;
+
+    // Outside of the simulation the bind can't be released
+    let listener = leaked.borrow_mut().take().unwrap();
+    let err = listener.close().unwrap_err();
+    assert_eq!(Some(&Error::NoSimulation), Error::from_io(&err));
+
+    Ok(())
+}
+
 #[test]
 fn drop_listener_with_non_empty_queue() -> Result {
     let how_many_conns = 3;
@@ -401,13 +1435,107 @@ fn drop_listener_with_non_empty_queue() -> Result {
             ))));
         }
 
-        // sleep for one iteration to land syns in the listener
-        tokio::time::sleep(tick).await;
-        notify.notify_one();
+        // sleep for one iteration to land syns in the listener
+        tokio::time::sleep(tick).await;
+        notify.notify_one();
+
+        for fut in conns {
+            assert_error_kind(fut.await?, io::ErrorKind::ConnectionRefused);
+        }
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn recycle_server_socket_bind() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.client("server", async {
+        for _ in 0..3 {
+            let _ = TcpListener::bind((IpAddr::from(Ipv4Addr::UNSPECIFIED), 1234)).await?;
+        }
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn drain_then_crash() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+
+        loop {
+            let (mut s, _) = listener.accept().await?;
+
+            tokio::spawn(async move {
+                while let Ok(b) = s.read_u8().await {
+                    s.write_u8(b).await?;
+                }
+
+                Ok::<_, io::Error>(())
+            });
+        }
+    });
+
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        // Draining: new connections are refused, existing ones still work
+        assert_error_kind(
+            TcpStream::connect(("server", PORT)).await,
+            io::ErrorKind::ConnectionRefused,
+        );
+
+        s.write_u8(1).await?;
+        assert_eq!(1, s.read_u8().await?);
+
+        // After the grace window the connection is reset
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_error_kind(s.read_u8().await, io::ErrorKind::ConnectionReset);
+
+        Ok(())
+    });
+
+    // Connecting takes at most 200ms
+    while sim.elapsed() < Duration::from_millis(250) {
+        sim.step()?;
+    }
+
+    sim.drain_then_crash("server", Duration::from_secs(1));
+
+    sim.run()
+}
+
+#[test]
+fn reset() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.client("server", async {
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        s.write_u8(1).await?;
+
+        assert_error_kind(s.read_u8().await, io::ErrorKind::ConnectionReset);
+        assert_error_kind(s.write_u8(2).await, io::ErrorKind::BrokenPipe);
 
-        for fut in conns {
-            assert_error_kind(fut.await?, io::ErrorKind::ConnectionRefused);
-        }
+        Ok(())
+    });
+
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        assert_eq!(1, s.read_u8().await?);
+        s.reset();
 
         Ok(())
     });
@@ -416,18 +1544,49 @@ fn drop_listener_with_non_empty_queue() -> Result {
 }
 
 #[test]
-fn recycle_server_socket_bind() -> Result {
+fn tap() -> Result {
     let mut sim = Builder::new().build();
 
     sim.client("server", async {
-        for _ in 0..3 {
-            let _ = TcpListener::bind((IpAddr::from(Ipv4Addr::UNSPECIFIED), 1234)).await?;
-        }
+        let listener = bind().await?;
+        let (mut s, _) = listener.accept().await?;
+
+        let mut buf = [0; 4];
+        s.read_exact(&mut buf).await?;
+        s.write_all(b"pong").await?;
 
         Ok(())
     });
 
-    sim.run()
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        s.write_all(b"ping").await?;
+
+        let mut buf = [0; 4];
+        s.read_exact(&mut buf).await?;
+
+        Ok(())
+    });
+
+    let mut tap = sim.tap("client", "server");
+    sim.run()?;
+
+    let (mut sent, mut received) = (vec![], vec![]);
+    while let Some(tapped) = tap.try_recv() {
+        assert!(tapped.elapsed > Duration::ZERO);
+
+        if tapped.dst.port() == PORT {
+            sent.extend_from_slice(&tapped.data);
+        } else {
+            received.extend_from_slice(&tapped.data);
+        }
+    }
+
+    assert_eq!(b"ping", &sent[..]);
+    assert_eq!(b"pong", &received[..]);
+
+    Ok(())
 }
 
 #[test]
@@ -473,6 +1632,42 @@ fn hangup() -> Result {
     sim.run()
 }
 
+#[test]
+fn half_open_after_kill() -> Result {
+    let connected = Rc::new(Cell::new(false));
+
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+        let (_s, _) = listener.accept().await?;
+
+        future::pending().await
+    });
+
+    let is_connected = connected.clone();
+    sim.client("client", async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+        is_connected.set(true);
+
+        // No FIN or RST is received
+        assert!(timeout(Duration::from_secs(1), s.read_u8()).await.is_err());
+
+        // Writes are sent into the void
+        s.write_u8(1).await?;
+
+        Ok(())
+    });
+
+    while !connected.get() {
+        sim.step()?;
+    }
+
+    sim.kill("server");
+
+    sim.run()
+}
+
 #[test]
 fn shutdown_write() -> Result {
     let mut sim = Builder::new().build();
@@ -657,3 +1852,381 @@ fn split() -> Result {
 
     sim.run()
 }
+
+#[test]
+fn relay() -> Result {
+    let latency = Duration::from_millis(10);
+    let mut sim = fixed_latency(latency).build();
+
+    // Echoes a byte, then closes
+    sim.host("server", || async {
+        let listener = bind().await?;
+
+        loop {
+            let (mut s, _) = listener.accept().await?;
+            tokio::task::spawn_local(async move {
+                let byte = s.read_u8().await?;
+                s.write_u8(byte).await
+            });
+        }
+    });
+
+    sim.add_relay("relay", PORT, ("server", PORT));
+
+    sim.client("client", async move {
+        let mut a = TcpStream::connect(("relay", PORT)).await?;
+        let mut b = TcpStream::connect(("relay", PORT)).await?;
+
+        let start = turmoil::elapsed();
+        a.write_u8(1).await?;
+        b.write_u8(2).await?;
+
+        // Both links, in both directions
+        assert_eq!(1, a.read_u8().await?);
+        assert!(turmoil::elapsed() - start >= latency * 4);
+        assert_eq!(2, b.read_u8().await?);
+
+        // The server's close is forwarded
+        assert_eq!(0, a.read(&mut [0; 1]).await?);
+        assert_eq!(0, b.read(&mut [0; 1]).await?);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn relay_resets_when_upstream_unreachable() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", future::pending);
+    sim.add_relay("relay", PORT, ("server", PORT));
+
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("relay", PORT)).await?;
+
+        assert_error_kind(s.read_u8().await, io::ErrorKind::ConnectionReset);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn stream_stats() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", || async {
+        let listener = bind().await?;
+
+        loop {
+            let (mut s, _) = listener.accept().await?;
+            s.write_all(&[0; 10]).await?;
+            s.write_all(&[0; 20]).await?;
+
+            // Hold the stream open
+            let _ = s.read_u8().await;
+        }
+    });
+
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+        s.write_all(&[0; 5]).await?;
+
+        let mut buf = [0; 4];
+        s.read_exact(&mut buf).await?;
+
+        let stats = s.stats();
+        assert_eq!(5, stats.bytes_sent);
+        assert_eq!(1, stats.segments_sent);
+        assert_eq!(10, stats.bytes_received);
+        assert_eq!(1, stats.segments_received);
+        assert_eq!(6, stats.buffered);
+
+        let mut buf = [0; 26];
+        s.read_exact(&mut buf).await?;
+
+        let stats = s.stats();
+        assert_eq!(30, stats.bytes_received);
+        assert_eq!(2, stats.segments_received);
+        assert_eq!(0, stats.buffered);
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+/// When teardown tests tear down a connection, once it is established.
+const TEARDOWN_AT: Duration = Duration::from_millis(100);
+
+/// The default tick duration.
+const TICK: Duration = Duration::from_millis(1);
+
+/// A builder with a fixed `latency`, so teardown timing is exact.
+fn fixed_latency(latency: Duration) -> Builder {
+    let mut builder = Builder::new();
+    builder
+        .min_message_latency(latency)
+        .max_message_latency(latency);
+    builder
+}
+
+fn step_until(sim: &mut turmoil::Sim, elapsed: Duration) -> Result {
+    while sim.elapsed() < elapsed {
+        sim.step()?;
+    }
+
+    Ok(())
+}
+
+/// Step until `woken` is set, returning when it was.
+fn step_until_woken(sim: &mut turmoil::Sim, woken: &Cell<bool>) -> Result<Duration> {
+    while !woken.get() {
+        sim.step()?;
+    }
+
+    Ok(sim.elapsed())
+}
+
+/// A server that accepts connections, and holds them open.
+async fn hold_connections() -> Result {
+    let listener = bind().await?;
+    let mut streams = vec![];
+
+    loop {
+        streams.push(listener.accept().await?);
+    }
+}
+
+#[test]
+fn crash_wakes_pending_read() -> Result {
+    let latency = Duration::from_millis(10);
+    let woken = Rc::new(Cell::new(false));
+
+    let mut sim = fixed_latency(latency).build();
+
+    sim.host("server", hold_connections);
+
+    let set = woken.clone();
+    sim.client("client", async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        // Dropping the stream on crash sends a FIN
+        assert!(matches!(s.read(&mut [0; 1]).await, Ok(0)));
+        set.set(true);
+
+        Ok(())
+    });
+
+    step_until(&mut sim, TEARDOWN_AT)?;
+    sim.crash("server");
+
+    assert_eq!(TEARDOWN_AT + latency, step_until_woken(&mut sim, &woken)?);
+
+    sim.run()
+}
+
+#[test]
+fn reset_wakes_pending_read() -> Result {
+    let latency = Duration::from_millis(10);
+    let reset_at = Rc::new(Cell::new(Duration::ZERO));
+    let woken_at = Rc::new(Cell::new(Duration::ZERO));
+
+    let mut sim = fixed_latency(latency).build();
+
+    let set = reset_at.clone();
+    sim.client("server", async move {
+        let listener = bind().await?;
+        let (s, _) = listener.accept().await?;
+
+        tokio::time::sleep(TEARDOWN_AT).await;
+        s.reset();
+        set.set(turmoil::elapsed());
+
+        Ok(())
+    });
+
+    let set = woken_at.clone();
+    sim.client("client", async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        assert_error_kind(s.read_u8().await, io::ErrorKind::ConnectionReset);
+        set.set(turmoil::elapsed());
+
+        Ok(())
+    });
+
+    sim.run()?;
+
+    assert_eq!(reset_at.get() + latency, woken_at.get());
+
+    Ok(())
+}
+
+#[test]
+fn drain_then_crash_wakes_pending_read() -> Result {
+    let latency = Duration::from_millis(10);
+    let grace = Duration::from_millis(50);
+    let woken = Rc::new(Cell::new(false));
+
+    let mut sim = fixed_latency(latency).build();
+
+    sim.host("server", hold_connections);
+
+    let set = woken.clone();
+    sim.client("client", async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        assert_error_kind(s.read_u8().await, io::ErrorKind::ConnectionReset);
+        set.set(true);
+
+        Ok(())
+    });
+
+    step_until(&mut sim, TEARDOWN_AT)?;
+    sim.drain_then_crash("server", grace);
+
+    assert_eq!(
+        TEARDOWN_AT + grace + latency,
+        step_until_woken(&mut sim, &woken)?
+    );
+
+    sim.run()
+}
+
+#[test]
+fn kill_and_bounce_wakes_pending_read() -> Result {
+    let latency = Duration::from_millis(10);
+    let woken = Rc::new(Cell::new(false));
+
+    let mut sim = fixed_latency(latency).build();
+
+    sim.host("server", hold_connections);
+
+    let set = woken.clone();
+    sim.client("client", async move {
+        let (mut rx, mut tx) = TcpStream::connect(("server", PORT)).await?.into_split();
+
+        let read = tokio::task::spawn_local(async move {
+            assert_error_kind(rx.read_u8().await, io::ErrorKind::ConnectionReset);
+            set.set(true);
+        });
+
+        // The killed server receives nothing until it restarts, with no
+        // socket for the connection, and resets it
+        tokio::time::sleep(TEARDOWN_AT).await;
+        tx.write_u8(1).await?;
+
+        read.await?;
+
+        Ok(())
+    });
+
+    step_until(&mut sim, TEARDOWN_AT)?;
+    sim.kill("server");
+
+    step_until(&mut sim, TEARDOWN_AT * 2)?;
+    sim.bounce("server");
+
+    // The segment is delivered on the first tick after the restart
+    assert_eq!(
+        TEARDOWN_AT * 2 + TICK + latency,
+        step_until_woken(&mut sim, &woken)?
+    );
+
+    sim.run()
+}
+
+#[test]
+fn kill_and_bounce_wakes_pending_write() -> Result {
+    let latency = Duration::from_millis(10);
+    let woken = Rc::new(Cell::new(false));
+
+    let mut sim = fixed_latency(latency)
+        .tcp_model(TcpModel::AckGated { window: 4 })
+        .build();
+
+    sim.host("server", hold_connections);
+
+    let set = woken.clone();
+    sim.client("client", async move {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+
+        // The killed server doesn't acknowledge, so the window stays full
+        tokio::time::sleep(TEARDOWN_AT).await;
+        s.write_all(&[1, 2, 3, 4]).await?;
+
+        assert_error_kind(s.write_u8(5).await, io::ErrorKind::BrokenPipe);
+        set.set(true);
+
+        Ok(())
+    });
+
+    step_until(&mut sim, TEARDOWN_AT)?;
+    sim.kill("server");
+
+    // Once restarted, the server resets the connection
+    step_until(&mut sim, TEARDOWN_AT * 3)?;
+    sim.bounce("server");
+
+    // The segments are delivered on the first tick after the restart
+
+    assert_eq!(
+        TEARDOWN_AT * 3 + TICK + latency,
+        step_until_woken(&mut sim, &woken)?
+    );
+
+    sim.run()
+}
+
+#[test]
+fn reset_network() -> Result {
+    let mut sim = fixed_latency(Duration::from_millis(10)).build();
+
+    sim.host("server", || async {
+        // Survives the reset, unlike a crash
+        let mut accepted = 0;
+
+        loop {
+            let listener = bind().await?;
+
+            loop {
+                match listener.accept().await {
+                    Ok((mut s, _)) => {
+                        accepted += 1;
+                        s.write_u8(accepted).await?;
+
+                        tokio::task::spawn_local(async move {
+                            assert_error_kind(s.read_u8().await, io::ErrorKind::ConnectionReset);
+                        });
+                    }
+                    Err(e) => {
+                        assert_eq!(io::ErrorKind::ConnectionAborted, e.kind());
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    sim.client("client", async {
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+        assert_eq!(1, s.read_u8().await?);
+
+        assert_error_kind(s.read_u8().await, io::ErrorKind::ConnectionReset);
+
+        // The server rebinds on the same port
+        let mut s = TcpStream::connect(("server", PORT)).await?;
+        assert_eq!(2, s.read_u8().await?);
+
+        Ok(())
+    });
+
+    step_until(&mut sim, TEARDOWN_AT)?;
+    sim.reset_network("server");
+
+    sim.run()
+}
@@ -1,12 +1,13 @@
 use std::{
-    matches,
+    cell::Cell,
+    future, io,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     rc::Rc,
     sync::{atomic::AtomicUsize, atomic::Ordering},
     time::Duration,
 };
 use tokio::time::timeout;
-use turmoil::{lookup, net, Builder, Result};
+use turmoil::{lookup, net, Builder, Result, Trace};
 
 const PORT: u16 = 1738;
 
@@ -130,6 +131,74 @@ fn try_ping_pong() -> Result {
     sim.run()
 }
 
+#[test]
+fn poll_send_ready() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.client("server", async {
+        let sock = bind().await?;
+
+        let origin = recv_ping(&sock).await?;
+
+        future::poll_fn(|cx| sock.poll_send_ready(cx)).await?;
+        try_send_pong(&sock, origin)
+    });
+
+    sim.client("client", async {
+        let sock = bind().await?;
+
+        future::poll_fn(|cx| sock.poll_send_ready(cx)).await?;
+        try_send_ping(&sock)?;
+
+        recv_pong(&sock).await
+    });
+
+    sim.run()
+}
+
+#[test]
+fn send_buffer() -> Result {
+    let mut sim = Builder::new()
+        .max_message_latency(Duration::ZERO)
+        .bandwidth(1000)
+        .udp_send_buffer(200)
+        .build();
+
+    sim.client("server", async {
+        let sock = bind().await?;
+
+        for _ in 0..4 {
+            sock.recv_from(&mut [0; 128]).await?;
+        }
+
+        Ok(())
+    });
+
+    sim.client("client", async {
+        let sock = bind().await?;
+
+        sock.try_send_to(&[0; 100], ("server", PORT))?;
+        sock.try_send_to(&[0; 100], ("server", PORT))?;
+
+        // Both datagrams are still waiting on the link
+        let err = sock.try_send_to(&[0; 100], ("server", PORT)).unwrap_err();
+        assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+
+        // Until the first has been sent, which wakes the task on the next step
+        sock.writable().await?;
+        assert_eq!(Duration::from_millis(101), turmoil::elapsed());
+        sock.try_send_to(&[0; 100], ("server", PORT))?;
+
+        // Waits for the second
+        sock.send_to(&[0; 100], ("server", PORT)).await?;
+        assert_eq!(Duration::from_millis(201), turmoil::elapsed());
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
 #[test]
 fn recv_buf_is_clipped() -> Result {
     let mut sim = Builder::new().build();
@@ -158,6 +227,174 @@ fn recv_buf_is_clipped() -> Result {
     sim.run()
 }
 
+#[test]
+fn send_to_hostname() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.client("server", async {
+        let sock = bind().await?;
+
+        for _ in 0..2 {
+            let origin = recv_ping(&sock).await?;
+            send_pong(&sock, origin).await?;
+        }
+
+        Ok(())
+    });
+
+    sim.client("client", async {
+        let sock = bind().await?;
+
+        sock.send_to(b"ping", "server:1738").await?;
+        recv_pong(&sock).await?;
+
+        sock.send_to(b"ping", ("server", PORT)).await?;
+        recv_pong(&sock).await
+    });
+
+    sim.run()
+}
+
+#[test]
+fn migrate_peer() -> Result {
+    let migrated = Rc::new(Cell::new(false));
+
+    let mut sim = Builder::new().build();
+
+    for server in ["server-1", "server-2"] {
+        sim.host(server, || async {
+            let sock = bind().await?;
+
+            while let Ok(origin) = recv_ping(&sock).await {
+                let _ = send_pong(&sock, origin).await;
+            }
+
+            Ok(())
+        });
+    }
+
+    let is_migrated = migrated.clone();
+    sim.client("client", async move {
+        let sock = bind().await?;
+        let server = (lookup("server-1"), PORT);
+
+        for expected in ["server-1", "server-2"] {
+            while is_migrated.get() != (expected == "server-2") {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+
+            sock.send_to(b"ping", server).await?;
+
+            let mut buf = vec![0; 8];
+            let (_, origin) = sock.recv_from(&mut buf).await?;
+            assert_eq!((lookup(expected), PORT), (origin.ip(), origin.port()));
+        }
+
+        Ok(())
+    });
+
+    // Run until the client is waiting on the migration
+    for _ in 0..200 {
+        sim.step()?;
+    }
+
+    sim.migrate(
+        "client",
+        (sim.lookup("server-1"), PORT),
+        (sim.lookup("server-2"), PORT),
+    );
+    migrated.set(true);
+
+    sim.run()
+}
+
+#[test]
+fn migrate_connected_peer() -> Result {
+    let migrated = Rc::new(Cell::new(false));
+
+    let mut sim = Builder::new().build();
+
+    for server in ["server-1", "server-2"] {
+        sim.host(server, || async {
+            let sock = bind().await?;
+
+            while let Ok(origin) = recv_ping(&sock).await {
+                let _ = send_pong(&sock, origin).await;
+            }
+
+            Ok(())
+        });
+    }
+
+    let is_migrated = migrated.clone();
+    sim.client("client", async move {
+        let sock = bind().await?;
+        sock.connect(("server-1", PORT)).await?;
+
+        for expected in ["server-1", "server-2"] {
+            while is_migrated.get() != (expected == "server-2") {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+
+            sock.send(b"ping").await?;
+
+            // Replies from the new address reach the connected socket
+            let mut buf = vec![0; 8];
+            let _ = sock.recv(&mut buf).await?;
+            assert_eq!(
+                SocketAddr::from((lookup(expected), PORT)),
+                sock.peer_addr()?
+            );
+        }
+
+        Ok(())
+    });
+
+    // Run until the client is waiting on the migration
+    for _ in 0..200 {
+        sim.step()?;
+    }
+
+    sim.migrate(
+        "client",
+        (sim.lookup("server-1"), PORT),
+        (sim.lookup("server-2"), PORT),
+    );
+    migrated.set(true);
+
+    sim.run()
+}
+
+#[test]
+fn replay_trace() -> Result {
+    // Addresses are assigned in the order host names are first resolved, so
+    // they match those of the sim below.
+    let addrs = Builder::new().build();
+    let server = (addrs.lookup("server"), PORT).into();
+    let client = (addrs.lookup("client"), PORT).into();
+    let unknown = (IpAddr::from(Ipv4Addr::new(10, 0, 0, 1)), PORT).into();
+
+    let mut trace = Trace::new();
+    trace
+        .udp(Duration::from_millis(500), client, server, "ping")
+        .udp(Duration::from_millis(100), unknown, server, "ping");
+
+    let mut sim = Builder::new().replay(trace).build();
+
+    sim.client("server", async move {
+        let sock = bind().await?;
+
+        assert_eq!(client, recv_ping(&sock).await?);
+        assert!(turmoil::elapsed() >= Duration::from_millis(500));
+
+        Ok(())
+    });
+
+    sim.host("client", future::pending);
+
+    sim.run()
+}
+
 #[test]
 fn hold_and_release() -> Result {
     let mut sim = Builder::new().build();
@@ -180,7 +417,7 @@ fn hold_and_release() -> Result {
         send_ping(&sock).await?;
 
         let res = timeout(Duration::from_secs(1), recv_pong(&sock)).await;
-        assert!(matches!(res, Err(_)));
+        assert!(res.is_err());
 
         // resume the network. note that the client ping does not have to be
         // resent.
@@ -192,6 +429,210 @@ fn hold_and_release() -> Result {
     sim.run()
 }
 
+#[test]
+fn priority() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.client("server", async {
+        let sock = bind().await?;
+
+        let mut buf = [0; 8];
+        for expected in [b"high", b"low1", b"low2"] {
+            let (n, _) = sock.recv_from(&mut buf).await?;
+            assert_eq!(expected, &buf[..n]);
+        }
+
+        Ok(())
+    });
+
+    sim.client("client", async {
+        turmoil::hold("client", "server");
+
+        let sock = bind().await?;
+        assert_eq!(0, sock.priority());
+
+        sock.send_to(b"low1", ("server", PORT)).await?;
+        sock.set_priority(7);
+        sock.send_to(b"high", ("server", PORT)).await?;
+        sock.set_priority(0);
+        sock.send_to(b"low2", ("server", PORT)).await?;
+
+        // Held messages are delivered together, highest priority first
+        turmoil::release("client", "server");
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn priority_on_bandwidth_limited_link() -> Result {
+    for host_bandwidth in [false, true] {
+        let mut sim = Builder::new().max_message_latency(Duration::ZERO).build();
+
+        sim.client("server", async {
+            let sock = bind().await?;
+
+            // The first message is already on the wire when the others queue
+            let mut buf = [0; 128];
+            for (expected, at) in [(b'1', 100), (b'h', 200), (b'2', 300)] {
+                sock.recv_from(&mut buf).await?;
+                assert_eq!(expected, buf[0]);
+                assert_eq!(Duration::from_millis(at), turmoil::elapsed());
+            }
+
+            Ok(())
+        });
+
+        sim.client("client", async {
+            let sock = bind().await?;
+
+            sock.send_to(&[b'1'; 100], ("server", PORT)).await?;
+            sock.send_to(&[b'2'; 100], ("server", PORT)).await?;
+            sock.set_priority(7);
+            sock.send_to(&[b'h'; 100], ("server", PORT)).await?;
+
+            Ok(())
+        });
+
+        if host_bandwidth {
+            sim.set_host_bandwidth("client", 1000);
+        } else {
+            sim.set_link_bandwidth("client", "server", 1000);
+        }
+
+        sim.run()?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn broadcast() -> Result {
+    let mut sim = Builder::new().build();
+
+    for peer in ["a", "b"] {
+        sim.client(peer, async {
+            let sock = bind().await?;
+            recv_ping(&sock).await?;
+
+            Ok(())
+        });
+    }
+
+    sim.client("client", async {
+        let sock = bind().await?;
+        let target = SocketAddr::from((Ipv4Addr::BROADCAST, PORT));
+
+        // Off by default, as on Linux
+        assert!(!sock.broadcast()?);
+        let err = sock.send_to(b"ping", target).await.unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+
+        sock.set_broadcast(true)?;
+        sock.send_to(b"ping", target).await?;
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn ephemeral_ports() -> Result {
+    let mut sim = Builder::new()
+        .udp_ephemeral_ports(5000..=5002)
+        .reserved_ports(&[5001])
+        .build();
+
+    sim.client("client", async {
+        let a = bind_to(0).await?;
+        let b = bind_to(0).await?;
+
+        assert_eq!(5000, a.local_addr()?.port());
+        assert_eq!(5002, b.local_addr()?.port());
+
+        // Reserved ports can still be bound explicitly
+        bind_to(5001).await?;
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn connected_port_unreachable() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.host("server", future::pending);
+
+    sim.client("client", async {
+        let sock = bind().await?;
+        sock.connect(("server", PORT)).await?;
+        assert_eq!(
+            SocketAddr::from((lookup("server"), PORT)),
+            sock.peer_addr()?
+        );
+
+        sock.send(b"ping").await?;
+
+        let mut buf = [0; 4];
+        let err = sock.recv(&mut buf).await.unwrap_err();
+        assert_eq!(std::io::ErrorKind::ConnectionRefused, err.kind());
+
+        // Unconnected sockets drop the error
+        let sock = bind_to(PORT + 1).await?;
+        send_ping(&sock).await?;
+
+        let res = timeout(Duration::from_secs(1), sock.recv_from(&mut buf)).await;
+        assert!(res.is_err());
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn anycast() -> Result {
+    let mut sim = Builder::new().build();
+
+    for member in ["far", "near"] {
+        sim.host(member, || async {
+            let sock = bind().await?;
+
+            loop {
+                let origin = recv_ping(&sock).await?;
+                send_pong(&sock, origin).await?;
+            }
+        });
+    }
+
+    sim.client("client", async {
+        let sock = bind().await?;
+
+        for expected in ["near", "far"] {
+            sock.send_to(b"ping", ("service", PORT)).await?;
+
+            let (_, from) = sock.recv_from(&mut [0; 4]).await?;
+            assert_eq!(lookup(expected), from.ip());
+
+            // The nearest member is unreachable, so the next is used
+            turmoil::partition("client", "near");
+        }
+
+        Ok(())
+    });
+
+    sim.set_link_latency("client", "far", Duration::from_millis(20));
+    sim.set_link_latency("client", "near", Duration::from_millis(5));
+    sim.anycast("service", ["far", "near"]);
+
+    sim.run()
+}
+
 #[test]
 fn network_partition() -> Result {
     let mut sim = Builder::new().build();
@@ -323,3 +764,114 @@ fn bulk_transfer() -> Result {
 
     sim.run()
 }
+
+#[test]
+fn congestion_drop() -> Result {
+    let sent = 50;
+
+    let mut sim = Builder::new()
+        .min_message_latency(Duration::from_millis(10))
+        .max_message_latency(Duration::from_millis(10))
+        .congestion_drop(0.0, 0.02)
+        .build();
+
+    sim.client("server", async move {
+        let sock = bind().await?;
+
+        let mut total = 0;
+        while timeout(Duration::from_secs(1), recv_ping(&sock))
+            .await
+            .is_ok()
+        {
+            total += 1;
+        }
+
+        // The first message always arrives, but later messages are dropped
+        // as the link saturates.
+        assert!(total > 0 && total < sent);
+
+        Ok(())
+    });
+
+    sim.client("client", async move {
+        let sock = bind().await?;
+
+        for _ in 0..sent {
+            send_ping(&sock).await?;
+        }
+
+        Ok(())
+    });
+
+    sim.run()
+}
+
+#[test]
+fn blackhole_mtu() -> Result {
+    let mut sim = Builder::new().build();
+
+    sim.client("server", async move {
+        let sock = bind().await?;
+
+        let mut sizes = vec![];
+        let mut buf = [0; 512];
+        while let Ok(res) = timeout(Duration::from_secs(1), sock.recv_from(&mut buf)).await {
+            sizes.push(res?.0);
+        }
+
+        // The large datagram vanishes without an error
+        assert_eq!(vec![64, 64], sizes);
+
+        Ok(())
+    });
+
+    sim.client("client", async move {
+        let sock = bind().await?;
+
+        for size in [64, 256, 64] {
+            sock.send_to(&vec![0; size], ("server", PORT)).await?;
+        }
+
+        Ok(())
+    });
+
+    sim.set_link_blackhole_mtu("client", "server", 128);
+
+    sim.run()
+}
+
+#[test]
+fn inject_spoofed() -> Result {
+    let mut sim = Builder::new().build();
+    let nobody: SocketAddr = (Ipv4Addr::new(10, 9, 9, 9), 53).into();
+
+    sim.client("server", async move {
+        let sock = bind().await?;
+        let mut buf = [0; 8];
+
+        for expected in [nobody, (lookup("client"), PORT).into()] {
+            let (n, src) = sock.recv_from(&mut buf).await?;
+            assert_eq!(expected, src);
+            assert_eq!(b"spoofed", &buf[..n]);
+
+            // Replies to a source no host owns go nowhere
+            sock.send_to(b"pong", src).await?;
+        }
+
+        Ok(())
+    });
+
+    // The owner of the spoofed address receives the reply
+    sim.client("client", async move {
+        let sock = bind().await?;
+        recv_pong(&sock).await
+    });
+
+    sim.step()?;
+
+    let client = (sim.lookup("client"), PORT).into();
+    sim.inject_spoofed(nobody, ("server", PORT), "spoofed");
+    sim.inject_spoofed(client, ("server", PORT), "spoofed");
+
+    sim.run()
+}